@@ -20,6 +20,7 @@ fn main() {
             rouille::proxy::ProxyConfig {
                 addr: "example.com:80",
                 replace_host: Some("example.com".into()),
+                preserve_host: false,
             },
         )
         .unwrap()
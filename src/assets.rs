@@ -7,12 +7,8 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
-use std::fs;
 use std::path::Path;
 
-use filetime;
-use time;
-
 use Request;
 use Response;
 
@@ -24,6 +20,10 @@ use Response;
 /// extension. If you wish so, you can modify that `Content-Type` by modifying the `Response`
 /// object returned by this function.
 ///
+/// The response contains an `X-Content-Type-Options: nosniff` header, so that browsers trust
+/// this `Content-Type` instead of guessing it from the content of the file. Call
+/// `without_header("X-Content-Type-Options")` on the response if you don't want it.
+///
 /// # Example
 ///
 /// In this example, a request made for example to `/test.txt` will return the file
@@ -110,29 +110,10 @@ where
         return Response::empty_404();
     }
 
-    // Check that it's a file and not a directory.
-    match fs::metadata(&potential_file) {
-        Ok(ref m) if m.is_file() => (),
-        _ => return Response::empty_404(),
-    };
-
-    let extension = potential_file.extension().and_then(|s| s.to_str());
-
-    let file = match fs::File::open(&potential_file) {
-        Ok(f) => f,
-        Err(_) => return Response::empty_404(),
-    };
-
-    let now = time::OffsetDateTime::now_local().unwrap_or_else(|_| time::OffsetDateTime::now_utc());
-    let etag: String = (fs::metadata(&potential_file)
-        .map(|meta| filetime::FileTime::from_last_modification_time(&meta).unix_seconds() as u64)
-        .unwrap_or(now.nanosecond() as u64)
-        ^ 0xd3f4_0305_c9f8_e911_u64)
-        .to_string();
-
-    Response::from_file(extension_to_mime_impl(extension), file)
-        .with_etag(request, etag)
+    // `from_path` returns a 404 if this is a directory and not a file.
+    Response::from_path(request, &potential_file)
         .with_public_cache(3600) // TODO: is this a good idea? what if the file is private?
+        .with_nosniff()
 }
 
 /// Returns the mime type of a file based on its extension, or `application/octet-stream` if the
@@ -198,6 +179,7 @@ fn extension_to_mime_impl(extension: Option<&str>) -> &'static str {
         Some("atom") => "application/atom+xml",
         Some("au") => "audio/basic",
         Some("avi") => "video/x-msvideo",
+        Some("avif") => "image/avif",
         Some("axs") => "application/olescript",
         Some("bas") => "text/plain; charset=utf8",
         Some("bcpio") => "application/x-bcpio",
@@ -378,7 +360,7 @@ fn extension_to_mime_impl(extension: Option<&str>) -> &'static str {
         Some("mak") => "text/plain; charset=utf8",
         Some("man") => "application/x-troff-man",
         Some("manifest") => "application/x-ms-manifest",
-        Some("map") => "text/plain; charset=utf8",
+        Some("map") => "application/json",
         Some("master") => "application/xml",
         Some("mda") => "application/msaccess",
         Some("mdb") => "application/x-msaccess",
@@ -391,6 +373,7 @@ fn extension_to_mime_impl(extension: Option<&str>) -> &'static str {
         Some("mid") => "audio/mid",
         Some("midi") => "audio/mid",
         Some("mix") => "application/octet-stream",
+        Some("mjs") => "text/javascript",
         Some("mk") => "text/plain; charset=utf8",
         Some("mmf") => "application/x-smaf",
         Some("mno") => "application/xml",
@@ -439,7 +422,7 @@ fn extension_to_mime_impl(extension: Option<&str>) -> &'static str {
         Some("onetoc2") => "application/onenote",
         Some("orderedtest") => "application/xml",
         Some("osdx") => "application/opensearchdescription+xml",
-        Some("otf") => "application/x-font-opentype",
+        Some("otf") => "font/otf",
         Some("p10") => "application/pkcs10",
         Some("p12") => "application/x-pkcs12",
         Some("p7b") => "application/x-pkcs7-certificates",
@@ -593,7 +576,7 @@ fn extension_to_mime_impl(extension: Option<&str>) -> &'static str {
         Some("trx") => "application/xml",
         Some("ts") => "video/vnd.dlna.mpeg-tts",
         Some("tsv") => "text/tab-separated-values; charset=utf8",
-        Some("ttf") => "application/x-font-ttf",
+        Some("ttf") => "font/ttf",
         Some("tts") => "video/vnd.dlna.mpeg-tts",
         Some("txt") => "text/plain; charset=utf8",
         Some("u32") => "application/octet-stream",
@@ -643,6 +626,9 @@ fn extension_to_mime_impl(extension: Option<&str>) -> &'static str {
         Some("wdb") => "application/vnd.ms-works",
         Some("wdp") => "image/vnd.ms-photo",
         Some("webarchive") => "application/x-safari-webarchive",
+        Some("webm") => "video/webm",
+        Some("webmanifest") => "application/manifest+json",
+        Some("webp") => "image/webp",
         Some("webtest") => "application/xml",
         Some("wiq") => "application/xml",
         Some("wiz") => "application/msword",
@@ -662,8 +648,8 @@ fn extension_to_mime_impl(extension: Option<&str>) -> &'static str {
         Some("wmv") => "video/x-ms-wmv",
         Some("wmx") => "video/x-ms-wmx",
         Some("wmz") => "application/x-ms-wmz",
-        Some("woff") => "application/font-woff",
-        Some("woff2") => "application/font-woff2",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
         Some("wpl") => "application/vnd.ms-wpl",
         Some("wps") => "application/vnd.ms-works",
         Some("wri") => "application/x-mswrite",
@@ -717,3 +703,114 @@ fn extension_to_mime_impl(extension: Option<&str>) -> &'static str {
         _ => "application/octet-stream",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::extension_to_mime;
+    use super::match_assets;
+    use filetime;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use Request;
+
+    // Creates an empty directory in the system's temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rouille-assets-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn etag_of(dir: &PathBuf, url: &str) -> String {
+        let request = Request::fake_http("GET", url, vec![], vec![]);
+        let response = match_assets(&request, dir);
+        assert_eq!(response.status_code, 200);
+        response
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("ETag"))
+            .map(|(_, v)| v.clone().into_owned())
+            .unwrap()
+    }
+
+    #[test]
+    fn etag_if_none_match() {
+        let dir = temp_dir("etag-if-none-match");
+        fs::write(dir.join("file.txt"), b"hello world").unwrap();
+
+        let etag = etag_of(&dir, "/file.txt");
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+
+        let request = Request::fake_http(
+            "GET",
+            "/file.txt",
+            vec![("If-None-Match".to_owned(), etag)],
+            vec![],
+        );
+        let response = match_assets(&request, &dir);
+        assert_eq!(response.status_code, 304);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn etag_depends_on_mtime() {
+        let dir = temp_dir("etag-depends-on-mtime");
+        fs::write(dir.join("a.txt"), b"aaaa").unwrap();
+        fs::write(dir.join("b.txt"), b"bbbb").unwrap();
+        filetime::set_file_mtime(
+            dir.join("a.txt"),
+            filetime::FileTime::from_unix_time(1000, 0),
+        )
+        .unwrap();
+        filetime::set_file_mtime(
+            dir.join("b.txt"),
+            filetime::FileTime::from_unix_time(2000, 0),
+        )
+        .unwrap();
+
+        assert_ne!(etag_of(&dir, "/a.txt"), etag_of(&dir, "/b.txt"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nosniff() {
+        let dir = temp_dir("nosniff");
+        fs::write(dir.join("file.txt"), b"hello world").unwrap();
+
+        let request = Request::fake_http("GET", "/file.txt", vec![], vec![]);
+        let response = match_assets(&request, &dir);
+        assert_eq!(response.status_code, 200);
+        assert!(response
+            .headers
+            .iter()
+            .any(|(k, v)| k == "X-Content-Type-Options" && v == "nosniff"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn modern_extensions() {
+        assert_eq!(extension_to_mime("woff2"), "font/woff2");
+        assert_eq!(extension_to_mime("webp"), "image/webp");
+        assert_eq!(extension_to_mime("avif"), "image/avif");
+        assert_eq!(extension_to_mime("wasm"), "application/wasm");
+        assert_eq!(extension_to_mime("mjs"), "text/javascript");
+        assert_eq!(extension_to_mime("map"), "application/json");
+        assert_eq!(
+            extension_to_mime("webmanifest"),
+            "application/manifest+json"
+        );
+        assert_eq!(extension_to_mime("webm"), "video/webm");
+    }
+
+    #[test]
+    fn unknown_extension() {
+        assert_eq!(
+            extension_to_mime("doesnotexist"),
+            "application/octet-stream"
+        );
+    }
+}
@@ -0,0 +1,504 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Allows you to let an external application handle the request through FastCGI.
+//!
+//! Contrary to CGI, where a process is spawned for each request, FastCGI applications (for
+//! example PHP-FPM) are long-running servers listening on a TCP or Unix socket. The
+//! `FastCgiClient` connects to such an application, sends it the request and returns its
+//! response.
+//!
+//! The parameters sent to the application are the same as the environment variables of the
+//! `CgiRun` trait. Most applications also need additional parameters, such as `SCRIPT_FILENAME`
+//! for PHP-FPM, which can be added with `FastCgiClient::param`.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use rouille::cgi::fastcgi::FastCgiClient;
+//!
+//! let php = FastCgiClient::tcp("127.0.0.1:9000")
+//!     .param("SCRIPT_FILENAME", "/var/www/index.php");
+//!
+//! rouille::start_server("localhost:8080", move |request| {
+//!     php.run(request).unwrap()
+//! });
+//! ```
+//!
+//! A new connection is opened for each request.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use super::env_vars;
+use super::read_response;
+use Request;
+use Response;
+
+/// Error that can happen when dispatching a request to a FastCGI application.
+#[derive(Debug)]
+pub enum FastCgiError {
+    /// Can't pass through the body of the request because it was already extracted.
+    BodyAlreadyExtracted,
+
+    /// Could not read the body from the request, or could not communicate with the FastCGI
+    /// application.
+    IoError(IoError),
+}
+
+impl From<IoError> for FastCgiError {
+    fn from(err: IoError) -> FastCgiError {
+        FastCgiError::IoError(err)
+    }
+}
+
+impl error::Error for FastCgiError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            FastCgiError::IoError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FastCgiError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            FastCgiError::BodyAlreadyExtracted => "the body of the request was already extracted",
+            FastCgiError::IoError(_) => {
+                "could not read the body from the request, or could not communicate with the \
+                 FastCGI application"
+            }
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+/// Dispatches requests to a FastCGI application.
+#[derive(Debug, Clone)]
+pub struct FastCgiClient {
+    address: Address,
+    params: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+enum Address {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FastCgiClient {
+    /// Builds a client for an application listening on the given TCP address, for example
+    /// `127.0.0.1:9000`.
+    #[inline]
+    pub fn tcp<A>(address: A) -> FastCgiClient
+    where
+        A: Into<String>,
+    {
+        FastCgiClient {
+            address: Address::Tcp(address.into()),
+            params: Vec::new(),
+        }
+    }
+
+    /// Builds a client for an application listening on the given Unix socket, for example
+    /// `/run/php/php-fpm.sock`.
+    #[cfg(unix)]
+    #[inline]
+    pub fn unix<P>(path: P) -> FastCgiClient
+    where
+        P: Into<PathBuf>,
+    {
+        FastCgiClient {
+            address: Address::Unix(path.into()),
+            params: Vec::new(),
+        }
+    }
+
+    /// Adds a parameter that is sent along with every request, or replaces the value of one of
+    /// the parameters built from the request.
+    #[inline]
+    pub fn param<K, V>(mut self, name: K, value: V) -> FastCgiClient
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.params.push((name.into(), value.into()));
+        self
+    }
+
+    /// Dispatches a request to the application.
+    ///
+    /// This function connects to the application, sends the parameters and the body of the
+    /// request, then waits until the application has returned all the headers of the response.
+    /// The body of the returned `Response` is read from the connection while it is sent to the
+    /// client.
+    ///
+    /// Like with `CgiRun::start_cgi`, an error is returned if and only if there was a problem
+    /// communicating with the application. The data that the application writes on its error
+    /// stream is forwarded to the error stream of the server.
+    pub fn run(&self, request: &Request) -> Result<Response, FastCgiError> {
+        match self.address {
+            Address::Tcp(ref address) => self.run_on(TcpStream::connect(&address[..])?, request),
+            #[cfg(unix)]
+            Address::Unix(ref path) => self.run_on(UnixStream::connect(path)?, request),
+        }
+    }
+
+    fn run_on<S>(&self, mut stream: S, request: &Request) -> Result<Response, FastCgiError>
+    where
+        S: Read + Write + Send + 'static,
+    {
+        let mut body = match request.data() {
+            Some(b) => b,
+            None => return Err(FastCgiError::BodyAlreadyExtracted),
+        };
+
+        // Role `FCGI_RESPONDER`, and no flag so that the application closes the connection at
+        // the end of the request.
+        write_record(&mut stream, BEGIN_REQUEST, &[0, 1, 0, 0, 0, 0, 0, 0])?;
+
+        let mut params = Vec::new();
+        let mut all_params = env_vars(request);
+        all_params.retain(|(name, _)| !self.params.iter().any(|(n, _)| n == name));
+        for (name, value) in all_params.iter().chain(self.params.iter()) {
+            encode_length(&mut params, name.len());
+            encode_length(&mut params, value.len());
+            params.extend_from_slice(name.as_bytes());
+            params.extend_from_slice(value.as_bytes());
+        }
+        for chunk in params.chunks(MAX_CONTENT_LENGTH) {
+            write_record(&mut stream, PARAMS, chunk)?;
+        }
+        write_record(&mut stream, PARAMS, &[])?;
+
+        let mut buffer = vec![0; MAX_CONTENT_LENGTH];
+        loop {
+            let num_read = match body.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == IoErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            write_record(&mut stream, STDIN, &buffer[..num_read])?;
+        }
+        write_record(&mut stream, STDIN, &[])?;
+        stream.flush()?;
+
+        Ok(read_response(Stdout {
+            stream,
+            remaining: 0,
+            padding: 0,
+            finished: false,
+        })?)
+    }
+}
+
+const VERSION: u8 = 1;
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+
+// All the records of a connection belong to the same request.
+const REQUEST_ID: u16 = 1;
+const MAX_CONTENT_LENGTH: usize = 65535;
+
+// Writes a record whose content must not be larger than `MAX_CONTENT_LENGTH`.
+fn write_record<W>(writer: &mut W, ty: u8, content: &[u8]) -> io::Result<()>
+where
+    W: Write,
+{
+    debug_assert!(content.len() <= MAX_CONTENT_LENGTH);
+
+    // The content is padded to a multiple of 8 bytes.
+    let padding = (8 - content.len() % 8) % 8;
+    let len = (content.len() as u16).to_be_bytes();
+    let id = REQUEST_ID.to_be_bytes();
+
+    writer.write_all(&[VERSION, ty, id[0], id[1], len[0], len[1], padding as u8, 0])?;
+    writer.write_all(content)?;
+    writer.write_all(&[0; 8][..padding])
+}
+
+// Lengths of names and values of parameters take one byte if they are smaller than 128, and
+// four bytes with the highest bit set otherwise.
+fn encode_length(out: &mut Vec<u8>, len: usize) {
+    if len < 128 {
+        out.push(len as u8);
+    } else {
+        out.extend_from_slice(&(len as u32 | 0x8000_0000).to_be_bytes());
+    }
+}
+
+// Reads the content of the `STDOUT` records sent by the application, until the `END_REQUEST`
+// record.
+struct Stdout<S> {
+    stream: S,
+    // Number of bytes left in the current `STDOUT` record.
+    remaining: usize,
+    // Number of padding bytes after the current `STDOUT` record.
+    padding: usize,
+    finished: bool,
+}
+
+impl<S> Stdout<S>
+where
+    S: Read,
+{
+    fn skip(&mut self, len: usize) -> io::Result<()> {
+        io::copy(&mut self.stream.by_ref().take(len as u64), &mut io::sink()).and_then(|n| {
+            if n == len as u64 {
+                Ok(())
+            } else {
+                Err(IoError::new(
+                    IoErrorKind::UnexpectedEof,
+                    "the FastCGI application closed the connection",
+                ))
+            }
+        })
+    }
+}
+
+impl<S> Read for Stdout<S>
+where
+    S: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.finished || buf.is_empty() {
+                return Ok(0);
+            }
+
+            if self.remaining > 0 {
+                let max = self.remaining.min(buf.len());
+                let num_read = self.stream.read(&mut buf[..max])?;
+                if num_read == 0 {
+                    return Err(IoError::new(
+                        IoErrorKind::UnexpectedEof,
+                        "the FastCGI application closed the connection",
+                    ));
+                }
+                self.remaining -= num_read;
+                if self.remaining == 0 {
+                    let padding = self.padding;
+                    self.skip(padding)?;
+                }
+                return Ok(num_read);
+            }
+
+            let mut header = [0; 8];
+            self.stream.read_exact(&mut header)?;
+            if header[0] != VERSION {
+                return Err(IoError::new(
+                    IoErrorKind::InvalidData,
+                    "unsupported FastCGI version",
+                ));
+            }
+            let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+            let padding = header[6] as usize;
+
+            match header[1] {
+                STDOUT => {
+                    self.remaining = len;
+                    self.padding = padding;
+                    if len == 0 {
+                        self.skip(padding)?;
+                    }
+                }
+                STDERR => {
+                    let mut content = vec![0; len];
+                    self.stream.read_exact(&mut content)?;
+                    self.skip(padding)?;
+                    let _ = io::stderr().write_all(&content);
+                }
+                END_REQUEST => {
+                    let mut content = vec![0; len];
+                    self.stream.read_exact(&mut content)?;
+                    self.skip(padding)?;
+                    self.finished = true;
+
+                    // The fifth byte is the protocol status, which is `FCGI_REQUEST_COMPLETE`
+                    // if everything went fine.
+                    if content.get(4).map_or(false, |&status| status != 0) {
+                        return Err(IoError::new(
+                            IoErrorKind::Other,
+                            "the FastCGI application rejected the request",
+                        ));
+                    }
+                }
+                _ => self.skip(len + padding)?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn read_record(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0; 8];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(header[0], VERSION);
+        assert_eq!(u16::from_be_bytes([header[2], header[3]]), REQUEST_ID);
+        let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+        let mut content = vec![0; len + header[6] as usize];
+        stream.read_exact(&mut content).unwrap();
+        content.truncate(len);
+        (header[1], content)
+    }
+
+    fn decode_length(data: &[u8], pos: &mut usize) -> usize {
+        if data[*pos] < 128 {
+            *pos += 1;
+            data[*pos - 1] as usize
+        } else {
+            let len =
+                u32::from_be_bytes([data[*pos], data[*pos + 1], data[*pos + 2], data[*pos + 3]]);
+            *pos += 4;
+            (len & 0x7fff_ffff) as usize
+        }
+    }
+
+    // Accepts a single request, and answers with the `QUERY_STRING` and `SCRIPT_FILENAME`
+    // parameters followed with the body of the request.
+    fn mock_application() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+
+            let (ty, content) = read_record(&mut stream);
+            assert_eq!(ty, BEGIN_REQUEST);
+            assert_eq!(&content[..2], &[0, 1]);
+
+            let mut params_data = Vec::new();
+            loop {
+                let (ty, content) = read_record(&mut stream);
+                assert_eq!(ty, PARAMS);
+                if content.is_empty() {
+                    break;
+                }
+                params_data.extend(content);
+            }
+            let mut params = Vec::new();
+            let mut pos = 0;
+            while pos < params_data.len() {
+                let name_len = decode_length(&params_data, &mut pos);
+                let value_len = decode_length(&params_data, &mut pos);
+                let name = String::from_utf8(params_data[pos..pos + name_len].to_vec()).unwrap();
+                pos += name_len;
+                let value = String::from_utf8(params_data[pos..pos + value_len].to_vec()).unwrap();
+                pos += value_len;
+                params.push((name, value));
+            }
+            let param = |name: &str| {
+                params
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v.clone())
+                    .unwrap_or_default()
+            };
+
+            let mut body = Vec::new();
+            loop {
+                let (ty, content) = read_record(&mut stream);
+                assert_eq!(ty, STDIN);
+                if content.is_empty() {
+                    break;
+                }
+                body.extend(content);
+            }
+
+            let mut out = format!(
+                "Status: 201 Created\r\nContent-Type: text/plain\r\n\r\n{} {} ",
+                param("QUERY_STRING"),
+                param("SCRIPT_FILENAME")
+            )
+            .into_bytes();
+            out.extend(body);
+            let (first, second) = out.split_at(20);
+            write_record(&mut stream, STDOUT, first).unwrap();
+            write_record(&mut stream, STDERR, b"").unwrap();
+            for chunk in second.chunks(MAX_CONTENT_LENGTH) {
+                write_record(&mut stream, STDOUT, chunk).unwrap();
+            }
+            write_record(&mut stream, STDOUT, &[]).unwrap();
+            write_record(&mut stream, END_REQUEST, &[0; 8]).unwrap();
+        });
+
+        address
+    }
+
+    #[test]
+    fn basic() {
+        let client = FastCgiClient::tcp(mock_application()).param("SCRIPT_FILENAME", "/index.php");
+
+        // Big enough to be split in multiple `STDIN` records.
+        let body = vec![b'a'; 100_000];
+        let request = Request::fake_http("POST", "/index.php?foo=bar", vec![], body.clone());
+
+        let response = client.run(&request).unwrap();
+        assert_eq!(response.status_code, 201);
+        assert_eq!(
+            response.headers,
+            vec![("Content-Type".into(), "text/plain".into())]
+        );
+
+        let mut data = Vec::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_end(&mut data)
+            .unwrap();
+        let mut expected = b"foo=bar /index.php ".to_vec();
+        expected.extend(body);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn encode_lengths() {
+        let mut out = Vec::new();
+        encode_length(&mut out, 5);
+        encode_length(&mut out, 300);
+        assert_eq!(out, vec![5, 0x80, 0, 1, 44]);
+    }
+
+    #[test]
+    fn body_already_extracted() {
+        let client = FastCgiClient::tcp("127.0.0.1:1");
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        request.data();
+        match client.run_on(io::Cursor::new(Vec::new()), &request) {
+            Err(FastCgiError::BodyAlreadyExtracted) => (),
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,361 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Allows you to let an external process handle the request through CGI.
+//!
+//! This module provides a trait named `CgiRun` which is implemented on `std::process::Command`.
+//! In order to dispatch a request, simply start building a `Command` object and call `start_cgi`
+//! on it.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use std::process::Command;
+//! use rouille::cgi::CgiRun;
+//!
+//! rouille::start_server("localhost:8080", move |request| {
+//!     Command::new("php-cgi").start_cgi(request).unwrap()
+//! });
+//! ```
+//!
+//! # About the Result returned by start_cgi
+//!
+//! The `start_cgi` method returns a `Result<Response, std::io::Error>`. This object will contain
+//! an error if and only if there was a problem executing the command (for example if it fails to
+//! start, or starts then crashes, ...), or if it returned an invalid `Status` header.
+//!
+//! If the process returns an error 400 or an error 404 for example, then the result will contain
+//! `Ok`.
+//!
+//! It is therefore appropriate to simply call `.unwrap()` on that result. Any panic will be turned
+//! into an error 500 and add an entry to the logs, which is probably what you want when your
+//! server is misconfigured.
+//!
+//! # FastCGI
+//!
+//! Spawning a process for each request can be expensive. If the program supports it, consider
+//! using [the `fastcgi` module](fastcgi/index.html) instead.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::BufRead;
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Read;
+use std::process::Command;
+use std::process::Stdio;
+
+use Request;
+use Response;
+use ResponseBody;
+
+pub mod fastcgi;
+
+/// Error that can happen when parsing the JSON input.
+#[derive(Debug)]
+pub enum CgiError {
+    /// Can't pass through the body of the request because it was already extracted.
+    BodyAlreadyExtracted,
+
+    /// Could not read the body from the request, or could not execute the CGI program.
+    IoError(IoError),
+}
+
+impl From<IoError> for CgiError {
+    fn from(err: IoError) -> CgiError {
+        CgiError::IoError(err)
+    }
+}
+
+impl error::Error for CgiError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            CgiError::IoError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CgiError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            CgiError::BodyAlreadyExtracted => "the body of the request was already extracted",
+            CgiError::IoError(_) => {
+                "could not read the body from the request, or could not execute the CGI program"
+            }
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+pub trait CgiRun {
+    /// Dispatches a request to the process.
+    ///
+    /// This function modifies the `Command` to add all the required environment variables
+    /// and the request's body, then executes the command and waits until the child process has
+    /// returned all the headers of the response. Once the headers have been sent back, this
+    /// function returns.
+    ///
+    /// In addition to the CGI/1.1 variables such as `REQUEST_METHOD` or `QUERY_STRING`, each
+    /// header of the request is passed as an `HTTP_*` variable, for example `HTTP_USER_AGENT`.
+    ///
+    /// The body of the returned `Response` will hold a handle to the child's stdout output. This
+    /// means that the child can continue running in the background and send data to the client,
+    /// even after you have finished handling the request.
+    fn start_cgi(self, request: &Request) -> Result<Response, CgiError>;
+}
+
+impl CgiRun for Command {
+    fn start_cgi(mut self, request: &Request) -> Result<Response, CgiError> {
+        self.envs(env_vars(request))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .stdin(Stdio::piped());
+
+        let mut child = self.spawn()?;
+
+        if let Some(mut body) = request.data() {
+            let mut stdin = child.stdin.take().unwrap();
+            io::copy(&mut body, &mut stdin)?;
+            // Dropping `stdin` closes it, so that the child knows there is no more data.
+        } else {
+            return Err(CgiError::BodyAlreadyExtracted);
+        }
+
+        Ok(read_response(child.stdout.take().unwrap())?)
+    }
+}
+
+// Builds the CGI/1.1 environment variables corresponding to a request. Also used as the
+// parameters of FastCGI requests.
+fn env_vars(request: &Request) -> Vec<(String, String)> {
+    let default_port = if request.is_secure() { 443 } else { 80 };
+    let (server_name, server_port) = match request.host() {
+        // IPv6 addresses keep their brackets in `SERVER_NAME`.
+        Some((name, port)) if name.contains(':') => (format!("[{}]", name), port),
+        Some((name, port)) => (name, port),
+        None => ("localhost".to_owned(), None),
+    };
+    let server_port = server_port.unwrap_or(default_port);
+
+    let mut vars = vec![
+        ("SERVER_SOFTWARE", "rouille".to_owned()),
+        ("SERVER_NAME", server_name),
+        ("GATEWAY_INTERFACE", "CGI/1.1".to_owned()),
+        ("SERVER_PROTOCOL", "HTTP/1.1".to_owned()), // FIXME:
+        ("SERVER_PORT", server_port.to_string()),
+        (
+            "HTTPS",
+            if request.is_secure() { "on" } else { "off" }.to_owned(),
+        ),
+        ("REQUEST_METHOD", request.method().to_owned()),
+        ("PATH_INFO", request.url()), // TODO: incorrect + what about PATH_TRANSLATED?
+        ("SCRIPT_NAME", "".to_owned()), // FIXME:
+        ("QUERY_STRING", request.raw_query_string().to_owned()),
+        ("REMOTE_ADDR", request.remote_addr().ip().to_string()),
+        ("REMOTE_PORT", request.remote_addr().port().to_string()),
+        ("AUTH_TYPE", "".to_owned()),   // FIXME:
+        ("REMOTE_USER", "".to_owned()), // FIXME:
+        (
+            "CONTENT_TYPE",
+            request.header("Content-Type").unwrap_or("").to_owned(),
+        ),
+        (
+            "CONTENT_LENGTH",
+            request.header("Content-Length").unwrap_or("").to_owned(),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_owned(), value))
+    .collect::<Vec<_>>();
+
+    vars.extend(http_env_vars(request));
+    vars
+}
+
+// Reads the headers written by a CGI program, and builds a `Response` whose body is the rest of
+// the output.
+fn read_response<R>(output: R) -> Result<Response, IoError>
+where
+    R: Read + Send + 'static,
+{
+    let mut output = io::BufReader::new(output);
+
+    let mut headers = Vec::new();
+    let mut status_code = 200;
+    for header in output.by_ref().lines() {
+        let header = header?;
+        if header.is_empty() {
+            break;
+        }
+
+        let (header, val) = match header.split_once(':') {
+            Some((header, val)) => (header.trim(), val.trim()),
+            None => continue,
+        };
+
+        if header.eq_ignore_ascii_case("Status") {
+            status_code = match val.get(0..3).and_then(|code| code.parse().ok()) {
+                Some(code) if (100..1000).contains(&code) => code,
+                _ => {
+                    return Err(IoError::new(
+                        IoErrorKind::InvalidData,
+                        format!("invalid Status returned by the CGI program: {}", val),
+                    ))
+                }
+            };
+        } else {
+            headers.push((header.to_owned().into(), val.to_owned().into()));
+        }
+    }
+
+    Ok(Response {
+        status_code,
+        headers,
+        data: ResponseBody::from_reader(output),
+        upgrade: None,
+    })
+}
+
+// Builds the `HTTP_*` environment variables corresponding to the headers of the request.
+//
+// `Content-Type` and `Content-Length` are already passed as `CONTENT_TYPE` and `CONTENT_LENGTH`.
+// `Proxy` is skipped, as a `HTTP_PROXY` variable would be picked up as the proxy to use by many
+// programs (see https://httpoxy.org).
+fn http_env_vars(request: &Request) -> Vec<(String, String)> {
+    let mut vars: Vec<(String, String)> = Vec::new();
+
+    for (name, value) in request.headers() {
+        if name.eq_ignore_ascii_case("Content-Type")
+            || name.eq_ignore_ascii_case("Content-Length")
+            || name.eq_ignore_ascii_case("Proxy")
+        {
+            continue;
+        }
+
+        let var = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+        match vars.iter_mut().find(|(v, _)| *v == var) {
+            Some((_, existing)) => {
+                existing.push_str(", ");
+                existing.push_str(value);
+            }
+            None => vars.push((var, value.to_owned())),
+        }
+    }
+
+    vars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::env_vars;
+    use super::http_env_vars;
+    use super::read_response;
+    use super::CgiRun;
+    use std::io::Cursor;
+    use std::io::ErrorKind;
+    use std::io::Read;
+    use std::process::Command;
+    use Request;
+
+    #[test]
+    fn http_vars() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![
+                ("Host".to_owned(), "example.com".to_owned()),
+                ("X-Custom-Header".to_owned(), "a".to_owned()),
+                ("x-custom-header".to_owned(), "b".to_owned()),
+                ("Content-Type".to_owned(), "text/plain".to_owned()),
+                ("Proxy".to_owned(), "evil".to_owned()),
+            ],
+            vec![],
+        );
+
+        assert_eq!(
+            http_env_vars(&request),
+            vec![
+                ("HTTP_HOST".to_owned(), "example.com".to_owned()),
+                ("HTTP_X_CUSTOM_HEADER".to_owned(), "a, b".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn server_name_and_port() {
+        let server = |host: Option<&str>| {
+            let headers = host
+                .map(|host| vec![("Host".to_owned(), host.to_owned())])
+                .unwrap_or_default();
+            let vars = env_vars(&Request::fake_http("GET", "/", headers, vec![]));
+            let var = |name| vars.iter().find(|(k, _)| k == name).unwrap().1.clone();
+            (var("SERVER_NAME"), var("SERVER_PORT"))
+        };
+
+        let expected = |name: &str, port: &str| (name.to_owned(), port.to_owned());
+        assert_eq!(server(Some("example.com")), expected("example.com", "80"));
+        assert_eq!(
+            server(Some("example.com:8080")),
+            expected("example.com", "8080")
+        );
+        assert_eq!(server(Some("[::1]")), expected("[::1]", "80"));
+        assert_eq!(server(Some("[::1]:8080")), expected("[::1]", "8080"));
+        assert_eq!(server(Some("a/b")), expected("localhost", "80"));
+        assert_eq!(server(None), expected("localhost", "80"));
+    }
+
+    #[test]
+    fn invalid_status() {
+        for output in &["Status: abc\n\n", "Status: \n\n", "Status: 042 Foo\n\n"] {
+            let err = read_response(Cursor::new(output.as_bytes().to_vec())).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+
+        let response = read_response(Cursor::new(b"Status: 404 Not Found\n\n".to_vec())).unwrap();
+        assert_eq!(response.status_code, 404);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn query_string() {
+        let request = Request::fake_http(
+            "POST",
+            "/script?foo=bar&baz",
+            vec![("Host".to_owned(), "example.com:8080".to_owned())],
+            b"body".to_vec(),
+        );
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(
+            "printf 'Status: 201 Created\\nContent-Type: text/plain\\n\\n'; \
+             printf '%s %s %s %s ' \"$QUERY_STRING\" \"$REQUEST_METHOD\" \"$SERVER_NAME\" \"$SERVER_PORT\"; \
+             cat",
+        );
+        let response = command.start_cgi(&request).unwrap();
+        assert_eq!(response.status_code, 201);
+        assert_eq!(
+            response.headers,
+            vec![("Content-Type".into(), "text/plain".into())]
+        );
+
+        let mut body = String::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "foo=bar&baz POST example.com 8080 body");
+    }
+}
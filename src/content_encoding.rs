@@ -54,18 +54,18 @@ use Response;
 /// }
 /// ```
 pub fn apply(request: &Request, mut response: Response) -> Response {
-    // Only text should be encoded. Otherwise just return.
-    if !response_is_text(&response) {
+    // Only text should be encoded. Otherwise just return. Responses that take over the connection
+    // write their data themselves and can't be encoded either.
+    if !response_is_text(&response) || response.upgrade.is_some() {
         return response;
     }
 
     // If any of the response's headers is equal to `Content-Encoding`, ignore the function
-    // call and return immediately.
-    if response
-        .headers
-        .iter()
-        .any(|(key, _)| key.eq_ignore_ascii_case("Content-Encoding"))
-    {
+    // call and return immediately. The same goes for partial responses, as their `Content-Range`
+    // refers to the unencoded body.
+    if response.headers.iter().any(|(key, _)| {
+        key.eq_ignore_ascii_case("Content-Encoding") || key.eq_ignore_ascii_case("Content-Range")
+    }) {
         return response;
     }
 
@@ -7,6 +7,89 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
+use input::parse_priority_header;
+use Request;
+
+/// Returns the content type amongst `offered` that the client prefers according to the `Accept`
+/// header of the request.
+///
+/// The quality values (`q=`) of the header are taken into account, as well as the `type/*` and
+/// `*/*` wildcards. When multiple entries of the header match an offered content type, the most
+/// specific one is used. Content types with a quality of `0` are never returned.
+///
+/// If the request doesn't have an `Accept` header, the first offered content type is returned.
+/// If none of the offered content types is acceptable, `None` is returned. If multiple content
+/// types have the same priority, the earliest in `offered` is returned.
+///
+/// # Example
+///
+/// ```
+/// use rouille::input;
+/// use rouille::Request;
+/// use rouille::Response;
+///
+/// fn handle(request: &Request) -> Response {
+///     match input::preferred_content_type(request, &["text/html", "application/json"]) {
+///         Some("text/html") => Response::html("<p>Hello world</p>"),
+///         Some("application/json") => Response::json(&"Hello world"),
+///         _ => Response::empty_406(),
+///     }
+/// }
+/// ```
+pub fn preferred_content_type<'a>(request: &Request, offered: &[&'a str]) -> Option<&'a str> {
+    let header = match request.header("Accept") {
+        Some(h) => h,
+        None => return offered.first().cloned(),
+    };
+
+    let mut result = None;
+    let mut result_quality = 0.0f32;
+
+    for &content_type in offered {
+        let (offered_type, offered_subtype) = split_mime(content_type);
+
+        // Quality of the most specific entry of the header that matches this content type.
+        // Specificity is `2` for `type/subtype`, `1` for `type/*` and `0` for `*/*`.
+        let mut best: Option<(u8, f32)> = None;
+        for (entry, quality) in parse_priority_header(header) {
+            let (entry_type, entry_subtype) = split_mime(entry);
+
+            let specificity = if entry_type == "*" && entry_subtype == "*" {
+                0
+            } else if !entry_type.eq_ignore_ascii_case(offered_type) {
+                continue;
+            } else if entry_subtype == "*" {
+                1
+            } else if entry_subtype.eq_ignore_ascii_case(offered_subtype) {
+                2
+            } else {
+                continue;
+            };
+
+            if best.map_or(true, |(s, _)| specificity > s) {
+                best = Some((specificity, quality));
+            }
+        }
+
+        if let Some((_, quality)) = best {
+            if quality > result_quality {
+                result = Some(content_type);
+                result_quality = quality;
+            }
+        }
+    }
+
+    result
+}
+
+// Splits a MIME type such as `text/html` into `("text", "html")`.
+fn split_mime(mime: &str) -> (&str, &str) {
+    let mut parts = mime.splitn(2, '/');
+    let left = parts.next().unwrap_or("").trim();
+    let right = parts.next().unwrap_or("").trim();
+    (left, right)
+}
+
 /// Dispatches between blocks depending on the value of the `Accept` header.
 ///
 /// This macro takes as first parameter the request object, and then each additional parameter must
@@ -92,8 +175,77 @@ macro_rules! accept {
 
 #[cfg(test)]
 mod tests {
+    use super::preferred_content_type;
     use Request;
 
+    fn accept(header: &str) -> Request {
+        Request::fake_http(
+            "GET",
+            "/",
+            vec![("Accept".to_owned(), header.to_owned())],
+            vec![],
+        )
+    }
+
+    #[test]
+    fn preferred_quality() {
+        let request = accept("application/json;q=0.9, text/html");
+        assert_eq!(
+            preferred_content_type(&request, &["application/json", "text/html"]),
+            Some("text/html")
+        );
+        assert_eq!(
+            preferred_content_type(&request, &["application/json", "image/png"]),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn preferred_wildcards() {
+        let request = accept("text/*;q=0.5, */*;q=0.1");
+        assert_eq!(
+            preferred_content_type(&request, &["image/png", "text/html"]),
+            Some("text/html")
+        );
+
+        let request = accept("*/*");
+        assert_eq!(
+            preferred_content_type(&request, &["image/png", "text/html"]),
+            Some("image/png")
+        );
+    }
+
+    #[test]
+    fn preferred_most_specific() {
+        let request = accept("text/html;q=0.2, */*");
+        assert_eq!(
+            preferred_content_type(&request, &["text/html", "application/json"]),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn preferred_not_acceptable() {
+        let request = accept("image/gif");
+        assert_eq!(
+            preferred_content_type(&request, &["text/html", "application/json"]),
+            None
+        );
+
+        let request = accept("text/html;q=0, */*;q=0.5");
+        assert_eq!(preferred_content_type(&request, &["text/html"]), None);
+    }
+
+    #[test]
+    fn preferred_no_header() {
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        assert_eq!(
+            preferred_content_type(&request, &["text/html", "application/json"]),
+            Some("text/html")
+        );
+        assert_eq!(preferred_content_type(&request, &[]), None);
+    }
+
     #[test]
     fn basic() {
         let request = Request::fake_http(
@@ -0,0 +1,148 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use input::parse_priority_header;
+use Request;
+
+/// Returns the language amongst `offered` that the client prefers according to the
+/// `Accept-Language` header of the request.
+///
+/// The quality values (`q=`) of the header are taken into account. A language range of the
+/// header matches an offered tag if they are equal, or if one is a prefix of the other (for
+/// example `en-US` matches `en`, and `en` matches `en-GB`). An exact match takes precedence over
+/// a prefix match, which itself takes precedence over the `*` wildcard. Languages with a quality
+/// of `0` are never returned.
+///
+/// If the request doesn't have an `Accept-Language` header, the first offered language is
+/// returned. If none of the offered languages is acceptable, `None` is returned. If multiple
+/// languages have the same priority, the earliest in `offered` is returned.
+///
+/// # Example
+///
+/// ```
+/// use rouille::input;
+/// use rouille::Request;
+/// use rouille::Response;
+///
+/// fn handle(request: &Request) -> Response {
+///     match input::preferred_language(request, &["en", "fr"]) {
+///         Some("fr") => Response::text("Bonjour le monde"),
+///         _ => Response::text("Hello world"),
+///     }
+/// }
+/// ```
+pub fn preferred_language<'a>(request: &Request, offered: &[&'a str]) -> Option<&'a str> {
+    let header = match request.header("Accept-Language") {
+        Some(h) => h,
+        None => return offered.first().cloned(),
+    };
+
+    let mut result = None;
+    let mut result_quality = 0.0f32;
+
+    for &language in offered {
+        // Quality of the most specific entry of the header that matches this language.
+        // Specificity is `2` for an exact match, `1` for a prefix match and `0` for `*`.
+        let mut best: Option<(u8, f32)> = None;
+        for (range, quality) in parse_priority_header(header) {
+            let specificity = if range == "*" {
+                0
+            } else if range.eq_ignore_ascii_case(language) {
+                2
+            } else if is_prefix(range, language) || is_prefix(language, range) {
+                1
+            } else {
+                continue;
+            };
+
+            best = match best {
+                Some((s, q)) if s > specificity || (s == specificity && q >= quality) => {
+                    Some((s, q))
+                }
+                _ => Some((specificity, quality)),
+            };
+        }
+
+        if let Some((_, quality)) = best {
+            if quality > result_quality {
+                result = Some(language);
+                result_quality = quality;
+            }
+        }
+    }
+
+    result
+}
+
+// Returns true if `prefix` is a prefix of the language tag `tag` in the sense of RFC 4647,
+// meaning that it is followed by a `-` in `tag`.
+fn is_prefix(prefix: &str, tag: &str) -> bool {
+    tag.len() > prefix.len()
+        && tag.as_bytes()[prefix.len()] == b'-'
+        && tag[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::preferred_language;
+    use Request;
+
+    fn accept_language(header: &str) -> Request {
+        Request::fake_http(
+            "GET",
+            "/",
+            vec![("Accept-Language".to_owned(), header.to_owned())],
+            vec![],
+        )
+    }
+
+    #[test]
+    fn basic() {
+        let request = accept_language("fr-CH, fr;q=0.9, en;q=0.8");
+        assert_eq!(
+            preferred_language(&request, &["en", "fr", "de"]),
+            Some("fr")
+        );
+        assert_eq!(preferred_language(&request, &["en", "de"]), Some("en"));
+        assert_eq!(preferred_language(&request, &["de"]), None);
+    }
+
+    #[test]
+    fn region_matches_language() {
+        let request = accept_language("en-US");
+        assert_eq!(preferred_language(&request, &["fr", "en"]), Some("en"));
+
+        let request = accept_language("en");
+        assert_eq!(
+            preferred_language(&request, &["fr", "en-GB"]),
+            Some("en-GB")
+        );
+    }
+
+    #[test]
+    fn exact_match_preferred() {
+        let request = accept_language("en-US;q=0.5, en-GB");
+        assert_eq!(preferred_language(&request, &["en", "en-US"]), Some("en"));
+
+        let request = accept_language("de;q=0, *");
+        assert_eq!(preferred_language(&request, &["de", "it"]), Some("it"));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let request = accept_language("EN-us");
+        assert_eq!(preferred_language(&request, &["en-US"]), Some("en-US"));
+    }
+
+    #[test]
+    fn no_header() {
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        assert_eq!(preferred_language(&request, &["en", "fr"]), Some("en"));
+    }
+}
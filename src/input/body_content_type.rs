@@ -0,0 +1,138 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use input::multipart::multipart_boundary;
+use Request;
+
+/// Kind of body of a request, as returned by `body_content_type`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BodyContentType {
+    /// `application/json`. Can be parsed with `json_input`.
+    Json,
+
+    /// `application/x-www-form-urlencoded`. Can be parsed with `post_input!`.
+    FormUrlEncoded,
+
+    /// Any `multipart/*` type, for example `multipart/form-data`. Contains the boundary. Can be
+    /// parsed with `multipart::get_multipart_input`.
+    Multipart(String),
+
+    /// Any other content type. Contains the MIME type in lowercase, without the parameters.
+    Other(String),
+
+    /// The request doesn't have a `Content-Type` header.
+    None,
+}
+
+/// Returns the kind of body of the request, based on its `Content-Type` header.
+///
+/// This doesn't read the body, and is useful for routes that accept several formats and need
+/// to choose the right parser. A multipart content type without a boundary is returned as
+/// `Other`, as it can't be parsed.
+///
+/// # Example
+///
+/// ```
+/// use rouille::input::{self, BodyContentType};
+/// use rouille::Request;
+/// use rouille::Response;
+///
+/// fn handle(request: &Request) -> Response {
+///     match input::body_content_type(request) {
+///         BodyContentType::Json => {
+///             // ... parse with `input::json_input` ...
+///             Response::empty_204()
+///         }
+///         BodyContentType::FormUrlEncoded => {
+///             // ... parse with `post_input!` ...
+///             Response::empty_204()
+///         }
+///         _ => Response::empty_400(),
+///     }
+/// }
+/// ```
+pub fn body_content_type(request: &Request) -> BodyContentType {
+    let content_type = match request.content_type() {
+        Some(c) => c,
+        None => return BodyContentType::None,
+    };
+
+    match &content_type.mime[..] {
+        "application/json" => BodyContentType::Json,
+        "application/x-www-form-urlencoded" => BodyContentType::FormUrlEncoded,
+        _ => match multipart_boundary(&content_type) {
+            Some(boundary) => BodyContentType::Multipart(boundary.to_owned()),
+            None => BodyContentType::Other(content_type.mime),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::body_content_type;
+    use super::BodyContentType;
+    use Request;
+
+    fn with_content_type(value: &str) -> BodyContentType {
+        let request = Request::fake_http(
+            "POST",
+            "/",
+            vec![("Content-Type".to_owned(), value.to_owned())],
+            vec![],
+        );
+        body_content_type(&request)
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(with_content_type("application/json"), BodyContentType::Json);
+        assert_eq!(
+            with_content_type("Application/JSON; charset=utf-8"),
+            BodyContentType::Json
+        );
+    }
+
+    #[test]
+    fn form_url_encoded() {
+        assert_eq!(
+            with_content_type("application/x-www-form-urlencoded"),
+            BodyContentType::FormUrlEncoded
+        );
+    }
+
+    #[test]
+    fn multipart() {
+        assert_eq!(
+            with_content_type("multipart/form-data; boundary=\"abc;def\""),
+            BodyContentType::Multipart("abc;def".to_owned())
+        );
+        assert_eq!(
+            with_content_type("multipart/form-data"),
+            BodyContentType::Other("multipart/form-data".to_owned())
+        );
+        assert_eq!(
+            with_content_type("multipart/mixed; boundary=xyz"),
+            BodyContentType::Multipart("xyz".to_owned())
+        );
+    }
+
+    #[test]
+    fn other() {
+        assert_eq!(
+            with_content_type("Text/Plain; charset=utf-8"),
+            BodyContentType::Other("text/plain".to_owned())
+        );
+    }
+
+    #[test]
+    fn none() {
+        let request = Request::fake_http("POST", "/", vec![], vec![]);
+        assert_eq!(body_content_type(&request), BodyContentType::None);
+    }
+}
@@ -73,8 +73,15 @@ impl<'a> Iterator for CookiesIter<'a> {
                 Some(v) => v,
             };
 
+            // Everything after the first `=` is the value, including any `=` padding. Only a pair
+            // of surrounding double quotes is removed.
             let key = key.trim();
-            let value = value.trim().trim_matches(|c| c == '"');
+            let value = value.trim();
+            let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                &value[1..value.len() - 1]
+            } else {
+                value
+            };
 
             return Some((key, value));
         }
@@ -112,4 +119,57 @@ mod test {
             vec![("a", "b"), ("hello", "world")]
         );
     }
+
+    #[test]
+    fn request_cookie() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("Cookie".to_owned(), "a=b; hello=world; a=c".to_owned())],
+            Vec::new(),
+        );
+
+        assert_eq!(request.cookie("hello"), Some("world"));
+        assert_eq!(request.cookie("a"), Some("b"));
+        assert_eq!(request.cookie("missing"), None);
+        assert_eq!(request.cookies().count(), 3);
+    }
+
+    #[test]
+    fn base64_value() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("Cookie".to_owned(), "session=dGVzdA==; b=a=b=".to_owned())],
+            Vec::new(),
+        );
+
+        assert_eq!(
+            cookies(&request).collect::<Vec<_>>(),
+            vec![("session", "dGVzdA=="), ("b", "a=b=")]
+        );
+    }
+
+    #[test]
+    fn quoted_value() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![(
+                "Cookie".to_owned(),
+                r#"a="hello, world"; b=""; c="unmatched; d=""""#.to_owned(),
+            )],
+            Vec::new(),
+        );
+
+        assert_eq!(
+            cookies(&request).collect::<Vec<_>>(),
+            vec![
+                ("a", "hello, world"),
+                ("b", ""),
+                ("c", "\"unmatched"),
+                ("d", "\"")
+            ]
+        );
+    }
 }
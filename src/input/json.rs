@@ -39,6 +39,8 @@ use serde_json;
 use std::error;
 use std::fmt;
 use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Read;
 use Request;
 
 /// Error that can happen when parsing the JSON input.
@@ -50,10 +52,18 @@ pub enum JsonError {
     /// Wrong content type.
     WrongContentType,
 
+    /// The `charset` parameter of the content type isn't supported. Contains the name of the
+    /// charset.
+    UnsupportedCharset(String),
+
+    /// The body had to be converted to UTF-8 before being parsed, and was larger than the limit.
+    LimitExceeded,
+
     /// Could not read the body from the request. Also happens if the body is not valid UTF-8.
     IoError(IoError),
 
-    /// Error while parsing.
+    /// Error while parsing. The error contains the line and the column where the problem was
+    /// detected, and is included in the message of the `JsonError`.
     ParseError(serde_json::Error),
 }
 
@@ -86,10 +96,16 @@ impl fmt::Display for JsonError {
         let description = match *self {
             JsonError::BodyAlreadyExtracted => "the body of the request was already extracted",
             JsonError::WrongContentType => "the request didn't have a JSON content type",
+            JsonError::UnsupportedCharset(ref charset) => {
+                return write!(fmt, "unsupported charset for a JSON body: {}", charset);
+            }
+            JsonError::LimitExceeded => "the limit to the number of bytes has been exceeded",
             JsonError::IoError(_) => {
                 "could not read the body from the request, or could not execute the CGI program"
             }
-            JsonError::ParseError(_) => "error while parsing the JSON body",
+            JsonError::ParseError(ref err) => {
+                return write!(fmt, "error while parsing the JSON body: {}", err);
+            }
         };
 
         write!(fmt, "{}", description)
@@ -100,6 +116,13 @@ impl fmt::Display for JsonError {
 ///
 /// Returns an error if the content-type of the request is not JSON, or if the JSON is malformed.
 ///
+/// The body is expected to be UTF-8, unless the content type has a `charset` parameter. The
+/// `us-ascii` charset is a subset of UTF-8. Bodies in `utf-16`, `utf-16le`, `utf-16be` or
+/// `iso-8859-1` are converted to UTF-8 first, which requires reading them entirely in memory,
+/// and are rejected with `JsonError::LimitExceeded` if they are larger than 4 MiB. For `utf-16`,
+/// the byte order is given by the byte order mark at the start of the body, and defaults to big
+/// endian. Other charsets are rejected with `JsonError::UnsupportedCharset`.
+///
 /// # Example
 ///
 /// ```
@@ -135,9 +158,231 @@ where
         return Err(JsonError::WrongContentType);
     }
 
-    if let Some(b) = request.data() {
-        serde_json::from_reader::<_, O>(b).map_err(From::from)
-    } else {
-        Err(JsonError::BodyAlreadyExtracted)
+    let charset = match request
+        .content_type()
+        .as_ref()
+        .and_then(|c| c.param("charset"))
+    {
+        None => Charset::Utf8,
+        Some(c)
+            if ["utf-8", "utf8", "us-ascii", "ascii"]
+                .iter()
+                .any(|n| c.eq_ignore_ascii_case(n)) =>
+        {
+            Charset::Utf8
+        }
+        Some(c) if c.eq_ignore_ascii_case("utf-16") => Charset::Utf16(None),
+        Some(c) if c.eq_ignore_ascii_case("utf-16be") => Charset::Utf16(Some(true)),
+        Some(c) if c.eq_ignore_ascii_case("utf-16le") => Charset::Utf16(Some(false)),
+        Some(c) if c.eq_ignore_ascii_case("iso-8859-1") || c.eq_ignore_ascii_case("latin1") => {
+            Charset::Latin1
+        }
+        Some(c) => return Err(JsonError::UnsupportedCharset(c.to_owned())),
+    };
+
+    let body = match request.data() {
+        Some(b) => b,
+        None => return Err(JsonError::BodyAlreadyExtracted),
+    };
+
+    if let Charset::Utf8 = charset {
+        return serde_json::from_reader::<_, O>(body).map_err(From::from);
+    }
+
+    let mut data = Vec::new();
+    body.take(MAX_CONVERTED_BODY as u64 + 1)
+        .read_to_end(&mut data)?;
+    if data.len() > MAX_CONVERTED_BODY {
+        return Err(JsonError::LimitExceeded);
+    }
+
+    let text = match charset {
+        Charset::Utf16(big_endian) => decode_utf16(&data, big_endian).ok_or_else(|| {
+            IoError::new(IoErrorKind::InvalidData, "the body is not valid UTF-16")
+        })?,
+        // The code points of ISO-8859-1 are the same as the first 256 of Unicode.
+        Charset::Latin1 => data.iter().map(|&b| char::from(b)).collect(),
+        Charset::Utf8 => unreachable!(),
+    };
+    serde_json::from_str::<O>(&text).map_err(From::from)
+}
+
+// Maximum size of the bodies that must be converted to UTF-8 before being parsed.
+const MAX_CONVERTED_BODY: usize = 4 * 1024 * 1024;
+
+// Charsets supported by `json_input`.
+enum Charset {
+    Utf8,
+    // Whether the data is big endian, or `None` if it depends on the byte order mark.
+    Utf16(Option<bool>),
+    Latin1,
+}
+
+// Decodes UTF-16 data. If `big_endian` is `None`, the byte order is determined by the byte order
+// mark, or is big endian if there isn't any.
+fn decode_utf16(data: &[u8], big_endian: Option<bool>) -> Option<String> {
+    if data.len() % 2 != 0 {
+        return None;
+    }
+
+    let (data, big_endian) = match (big_endian, data) {
+        (None, [0xfe, 0xff, rest @ ..]) => (rest, true),
+        (None, [0xff, 0xfe, rest @ ..]) => (rest, false),
+        (None, data) => (data, true),
+        (Some(big_endian), data) => (data, big_endian),
+    };
+
+    let units = data
+        .chunks(2)
+        .map(|c| {
+            if big_endian {
+                u16::from_be_bytes([c[0], c[1]])
+            } else {
+                u16::from_le_bytes([c[0], c[1]])
+            }
+        })
+        .collect::<Vec<_>>();
+
+    String::from_utf16(&units).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::json_input;
+    use super::JsonError;
+    use Request;
+
+    #[derive(Debug, Deserialize)]
+    struct Data {
+        field1: String,
+        field2: u32,
+    }
+
+    fn json_request(body: &[u8]) -> Request {
+        Request::fake_http(
+            "POST",
+            "/",
+            vec![("Content-Type".to_owned(), "application/json".to_owned())],
+            body.to_vec(),
+        )
+    }
+
+    #[test]
+    fn ok() {
+        let request = json_request(br#"{"field1": "hello", "field2": 5}"#);
+        let data: Data = json_input(&request).unwrap();
+        assert_eq!(data.field1, "hello");
+        assert_eq!(data.field2, 5);
+    }
+
+    #[test]
+    fn wrong_content_type() {
+        let request = Request::fake_http("POST", "/", vec![], b"{}".to_vec());
+        match json_input::<Data>(&request) {
+            Err(JsonError::WrongContentType) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn missing_field_message() {
+        let request = json_request(br#"{"field1": "hello"}"#);
+        let err = json_input::<Data>(&request).unwrap_err();
+        match err {
+            JsonError::ParseError(ref e) => assert_eq!((e.line(), e.column()), (1, 19)),
+            _ => panic!(),
+        }
+
+        let message = err.to_string();
+        assert!(message.contains("`field2`"), "{}", message);
+        assert!(message.contains("line 1 column 19"), "{}", message);
+    }
+
+    fn utf16_request(charset: &str, body: &[u8]) -> Request {
+        Request::fake_http(
+            "POST",
+            "/",
+            vec![(
+                "Content-Type".to_owned(),
+                format!("application/json; charset={}", charset),
+            )],
+            body.to_vec(),
+        )
+    }
+
+    #[test]
+    fn utf16() {
+        let json = r#"{"field1": "héllo", "field2": 5}"#;
+        let le = json
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes())
+            .collect::<Vec<_>>();
+        let be = json
+            .encode_utf16()
+            .flat_map(|u| u.to_be_bytes())
+            .collect::<Vec<_>>();
+
+        let data: Data = json_input(&utf16_request("utf-16le", &le)).unwrap();
+        assert_eq!(data.field1, "héllo");
+
+        let data: Data = json_input(&utf16_request("UTF-16BE", &be)).unwrap();
+        assert_eq!(data.field1, "héllo");
+
+        // The byte order mark decides for `utf-16`.
+        let mut with_bom = vec![0xff, 0xfe];
+        with_bom.extend_from_slice(&le);
+        let data: Data = json_input(&utf16_request("utf-16", &with_bom)).unwrap();
+        assert_eq!(data.field1, "héllo");
+        assert_eq!(data.field2, 5);
+
+        match json_input::<Data>(&utf16_request("utf-16le", &le[1..])) {
+            Err(JsonError::IoError(_)) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn utf8_charset() {
+        let request = utf16_request("\"UTF-8\"", br#"{"field1": "hello", "field2": 5}"#);
+        let data: Data = json_input(&request).unwrap();
+        assert_eq!(data.field2, 5);
+    }
+
+    #[test]
+    fn ascii_compatible_charsets() {
+        let request = utf16_request("US-ASCII", br#"{"field1": "hello", "field2": 5}"#);
+        let data: Data = json_input(&request).unwrap();
+        assert_eq!(data.field1, "hello");
+
+        let request = utf16_request("iso-8859-1", b"{\"field1\": \"h\xe9llo\", \"field2\": 5}");
+        let data: Data = json_input(&request).unwrap();
+        assert_eq!(data.field1, "héllo");
+    }
+
+    #[test]
+    fn unsupported_charset() {
+        let request = utf16_request("shift_jis", b"{}");
+        let err = json_input::<Data>(&request).unwrap_err();
+        match err {
+            JsonError::UnsupportedCharset(ref charset) => assert_eq!(charset, "shift_jis"),
+            _ => panic!(),
+        }
+        assert!(err.to_string().contains("shift_jis"), "{}", err);
+    }
+
+    #[test]
+    fn converted_body_limit() {
+        let body = vec![b' '; super::MAX_CONVERTED_BODY + 2];
+        match json_input::<Data>(&utf16_request("utf-16le", &body)) {
+            Err(JsonError::LimitExceeded) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn syntax_error_position() {
+        let request = json_request(b"{\n  \"field1\": \"hello\",\n  \"field2\": oops\n}");
+        let message = json_input::<Data>(&request).unwrap_err().to_string();
+        assert!(message.contains("line 3"), "{}", message);
     }
 }
@@ -14,16 +14,30 @@
 //!
 //! - In order to parse JSON, see [the `json` module](json/index.html).
 //! - In order to parse input from HTML forms, see [the `post` module](post/index.html).
+//! - In order to find out which parser to use for the body, see
+//!   [the `body_content_type` function](fn.body_content_type.html).
 //! - In order to read a plain text body, see
 //!   [the `plain_text_body` function](fn.plain_text_body.html).
+//! - In order to choose a content type depending on the `Accept` header, see
+//!   [the `preferred_content_type` function](fn.preferred_content_type.html).
+//! - In order to choose a language depending on the `Accept-Language` header, see
+//!   [the `preferred_language` function](fn.preferred_language.html).
+//! - In order to handle `If-Match` and the other conditional headers, see
+//!   [the `check_precondition` function](fn.check_precondition.html).
 
+pub use self::accept::preferred_content_type;
+pub use self::accept_language::preferred_language;
 pub use self::basic_http_auth::basic_http_auth;
 pub use self::basic_http_auth::HttpAuthCredentials;
+pub use self::body_content_type::body_content_type;
+pub use self::body_content_type::BodyContentType;
 pub use self::cookies::cookies;
 pub use self::cookies::CookiesIter;
 pub use self::json::json_input;
 pub use self::plain::plain_text_body;
 pub use self::plain::plain_text_body_with_limit;
+pub use self::precondition::check_precondition;
+pub use self::precondition::PreconditionResult;
 pub use self::priority_header::parse_priority_header;
 pub use self::priority_header::priority_header_preferred;
 pub use self::priority_header::PriorityHeaderIter;
@@ -33,7 +47,10 @@ pub mod multipart;
 pub mod post;
 
 mod accept;
+mod accept_language;
 mod basic_http_auth;
+mod body_content_type;
 mod cookies;
 mod plain;
+mod precondition;
 mod priority_header;
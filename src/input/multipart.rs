@@ -14,15 +14,28 @@
 
 use std::error;
 use std::fmt;
+use std::io;
+use std::io::Error as IoError;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+use ContentType;
 use Request;
 use RequestBody;
 
+use multipart::server::save::PartialReason;
+use multipart::server::save::SaveResult;
 use multipart::server::Multipart as InnerMultipart;
 
 // TODO: provide wrappers around these
+pub use multipart::server::save::SavedData;
+pub use multipart::server::Entries;
+pub use multipart::server::FieldHeaders;
 pub use multipart::server::MultipartData;
 pub use multipart::server::MultipartField;
+pub use multipart::server::SavedField;
 
 /// Error that can happen when decoding multipart data.
 #[derive(Clone, Debug)]
@@ -54,10 +67,112 @@ impl fmt::Display for MultipartError {
     }
 }
 
+/// Error that can happen when saving multipart data with `Multipart::save`.
+#[derive(Debug)]
+pub enum SaveError {
+    /// One of the `MultipartLimits` was exceeded. You probably want to answer with a
+    /// `413 Payload Too Large` response.
+    LimitExceeded,
+
+    /// Could not read the body from the request, or could not write the files.
+    IoError(IoError),
+}
+
+impl From<IoError> for SaveError {
+    fn from(err: IoError) -> SaveError {
+        SaveError::IoError(err)
+    }
+}
+
+impl error::Error for SaveError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            SaveError::IoError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SaveError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            SaveError::LimitExceeded => "a limit of the multipart data has been exceeded",
+            SaveError::IoError(_) => {
+                "could not read the body from the request, or could not write the files"
+            }
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+/// Limits to enforce when decoding multipart data.
+///
+/// A limit set to `None` means that there is no limit.
+///
+/// # Example
+///
+/// ```
+/// use rouille::input::multipart::{self, MultipartLimits, SaveError};
+/// use rouille::{Request, Response};
+///
+/// fn handle(request: &Request) -> Response {
+///     let limits = MultipartLimits {
+///         max_fields: Some(16),
+///         max_file_size: Some(1024 * 1024),
+///         max_total_size: Some(4 * 1024 * 1024),
+///     };
+///
+///     let mut input = try_or_400!(multipart::get_multipart_input_with_limits(request, limits));
+///     let entries = match input.save() {
+///         Ok(entries) => entries,
+///         Err(SaveError::LimitExceeded) => {
+///             return Response::text("Payload Too Large").with_status_code(413)
+///         }
+///         Err(SaveError::IoError(_)) => return Response::empty_400(),
+///     };
+///
+///     Response::text(format!("received {} fields", entries.fields_count()))
+/// }
+/// # #[macro_use] extern crate rouille; fn main() {}
+/// ```
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct MultipartLimits {
+    /// Maximum number of fields.
+    pub max_fields: Option<u32>,
+
+    /// Maximum size in bytes of the content of each field. Only enforced by `Multipart::save` and
+    /// `Multipart::foreach_field`.
+    pub max_file_size: Option<u64>,
+
+    /// Maximum size in bytes of the whole body of the request.
+    pub max_total_size: Option<u64>,
+}
+
 /// Attempts to decode the content of the request as `multipart/form-data` data.
+///
+/// Any `multipart/*` content type is accepted, for example `multipart/mixed`. The boundary is
+/// read from the `boundary` parameter of the `Content-Type` header, wherever it is among the
+/// other parameters, and may be quoted.
+#[inline]
 pub fn get_multipart_input(request: &Request) -> Result<Multipart, MultipartError> {
-    let boundary = match multipart_boundary(request) {
-        Some(b) => b,
+    get_multipart_input_with_limits(request, MultipartLimits::default())
+}
+
+/// Attempts to decode the content of the request as `multipart/form-data` data, while enforcing
+/// the given limits.
+///
+/// When reading the fields with `Multipart::next`, `None` is returned when the number of fields
+/// or the size of the body exceeds the limits, and `Multipart::limit_exceeded` returns `true`.
+/// When using `Multipart::save`, a `SaveError::LimitExceeded` error is returned instead.
+pub fn get_multipart_input_with_limits(
+    request: &Request,
+    limits: MultipartLimits,
+) -> Result<Multipart<'_>, MultipartError> {
+    let boundary = match request.content_type().as_ref().and_then(multipart_boundary) {
+        Some(b) => b.to_owned(),
         None => return Err(MultipartError::WrongContentType),
     };
 
@@ -67,38 +182,436 @@ pub fn get_multipart_input(request: &Request) -> Result<Multipart, MultipartErro
         return Err(MultipartError::BodyAlreadyExtracted);
     };
 
+    let limit_exceeded = Arc::new(AtomicBool::new(false));
+    let request_body = match limits.max_total_size {
+        Some(max) => RequestBody {
+            body: Box::new(Limited {
+                inner: request_body.body,
+                remaining: max,
+                limit_exceeded: limit_exceeded.clone(),
+            }),
+            marker: PhantomData,
+        },
+        None => request_body,
+    };
+
     Ok(Multipart {
         inner: InnerMultipart::with_body(request_body, boundary),
+        limits,
+        fields_read: 0,
+        limit_exceeded,
     })
 }
 
 /// Allows you to inspect the content of the multipart input of a request.
 pub struct Multipart<'a> {
     inner: InnerMultipart<RequestBody<'a>>,
+    limits: MultipartLimits,
+    // Number of fields returned by `next`.
+    fields_read: u32,
+    // Shared with the `Limited` readers, if any.
+    limit_exceeded: Arc<AtomicBool>,
 }
 
 impl<'a> Multipart<'a> {
     #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<MultipartField<&mut InnerMultipart<RequestBody<'a>>>> {
-        self.inner.read_entry().unwrap_or(None)
+        self.read_entry().unwrap_or(None)
+    }
+
+    /// Calls `callback` for each remaining field, with the headers of the field and a reader of
+    /// its content. This makes it possible to stream the files to any destination, without
+    /// storing them on the disk first.
+    ///
+    /// The callback doesn't have to read the whole content. If it returns an error, the iteration
+    /// stops and the error is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io;
+    /// use rouille::input::multipart;
+    /// use rouille::{Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     let mut input = try_or_400!(multipart::get_multipart_input(request));
+    ///     let mut total = 0;
+    ///     let result = input.foreach_field(|headers, content| {
+    ///         if headers.filename.is_some() {
+    ///             total += io::copy(content, &mut io::sink())?;
+    ///         }
+    ///         Ok(())
+    ///     });
+    ///
+    ///     try_or_400!(result);
+    ///     Response::text(format!("received {} bytes of files", total))
+    /// }
+    /// # #[macro_use] extern crate rouille; fn main() {}
+    /// ```
+    pub fn foreach_field<F>(&mut self, mut callback: F) -> Result<(), SaveError>
+    where
+        F: FnMut(&FieldHeaders, &mut dyn Read) -> Result<(), IoError>,
+    {
+        let max_file_size = self.limits.max_file_size;
+        let limit_exceeded = self.limit_exceeded.clone();
+
+        let mut result = Ok(());
+        while let Some(mut field) = self.read_entry()? {
+            result = match max_file_size {
+                Some(max) => callback(
+                    &field.headers,
+                    &mut Limited {
+                        inner: &mut field.data,
+                        remaining: max,
+                        limit_exceeded: limit_exceeded.clone(),
+                    },
+                ),
+                None => callback(&field.headers, &mut field.data),
+            };
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        match result {
+            _ if self.limit_exceeded() => Err(SaveError::LimitExceeded),
+            Err(err) => Err(SaveError::IoError(err)),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    // Reads the next field while enforcing the limit to the number of fields.
+    fn read_entry(
+        &mut self,
+    ) -> Result<Option<MultipartField<&mut InnerMultipart<RequestBody<'a>>>>, IoError> {
+        if let Some(max) = self.limits.max_fields {
+            if self.fields_read >= max {
+                if self.inner.read_entry()?.is_some() {
+                    self.limit_exceeded.store(true, Ordering::Relaxed);
+                }
+                return Ok(None);
+            }
+        }
+
+        let field = self.inner.read_entry()?;
+        if field.is_some() {
+            self.fields_read += 1;
+        }
+        Ok(field)
+    }
+
+    /// Returns `true` if one of the limits passed to `get_multipart_input_with_limits` has been
+    /// exceeded.
+    #[inline]
+    pub fn limit_exceeded(&self) -> bool {
+        self.limit_exceeded.load(Ordering::Relaxed)
+    }
+
+    /// Reads all the remaining fields. Small fields are kept in memory, and the other ones are
+    /// saved to files in a temporary directory that is deleted when the `Entries` are dropped.
+    pub fn save(&mut self) -> Result<Entries, SaveError> {
+        // The multipart crate reports a `CountLimit` when the number of fields reaches the limit,
+        // even if there's no field left. Allowing one more field tells the two situations apart.
+        let count_limit = self
+            .limits
+            .max_fields
+            .map(|max| max.saturating_sub(self.fields_read).saturating_add(1));
+
+        let result = self
+            .inner
+            .save()
+            .size_limit(self.limits.max_file_size)
+            .count_limit(count_limit)
+            .temp();
+
+        let error = match result {
+            SaveResult::Full(entries) => return Ok(entries),
+            SaveResult::Partial(_, PartialReason::CountLimit)
+            | SaveResult::Partial(_, PartialReason::SizeLimit) => {
+                self.limit_exceeded.store(true, Ordering::Relaxed);
+                return Err(SaveError::LimitExceeded);
+            }
+            SaveResult::Partial(_, PartialReason::IoError(err)) | SaveResult::Error(err) => err,
+            SaveResult::Partial(_, PartialReason::Utf8Error(err)) => {
+                IoError::new(io::ErrorKind::InvalidData, err)
+            }
+        };
+
+        if self.limit_exceeded() {
+            Err(SaveError::LimitExceeded)
+        } else {
+            Err(SaveError::IoError(error))
+        }
     }
 }
 
-fn multipart_boundary(request: &Request) -> Option<String> {
-    const BOUNDARY: &str = "boundary=";
+// Reader that produces an error once more than `remaining` bytes have been read.
+struct Limited<R> {
+    inner: R,
+    remaining: u64,
+    limit_exceeded: Arc<AtomicBool>,
+}
 
-    let content_type = match request.header("Content-Type") {
-        None => return None,
-        Some(c) => c,
-    };
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
 
-    let start = match content_type.find(BOUNDARY) {
-        Some(pos) => pos + BOUNDARY.len(),
-        None => return None,
-    };
+        if self.remaining == 0 {
+            // Checking whether the body really goes beyond the limit.
+            if self.inner.read(&mut [0])? == 0 {
+                return Ok(0);
+            }
+            self.limit_exceeded.store(true, Ordering::Relaxed);
+            return Err(IoError::new(
+                io::ErrorKind::InvalidData,
+                "the data exceeds the limit",
+            ));
+        }
+
+        let max = buf
+            .len()
+            .min(self.remaining.min(usize::MAX as u64) as usize);
+        let n = self.inner.read(&mut buf[..max])?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+// Returns the boundary of a `multipart/*` content type, or `None` if the content type isn't
+// multipart or doesn't have a boundary. Also used by `body_content_type`.
+pub(crate) fn multipart_boundary(content_type: &ContentType) -> Option<&str> {
+    if !content_type.mime.starts_with("multipart/") {
+        return None;
+    }
+
+    content_type
+        .param("boundary")
+        .filter(|boundary| !boundary.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::get_multipart_input;
+    use super::get_multipart_input_with_limits;
+    use super::MultipartError;
+    use super::MultipartLimits;
+    use super::SaveError;
+    use std::io;
+    use Request;
+
+    // Builds a request whose body contains the given `(name, content)` fields.
+    fn request(fields: &[(&str, &str)]) -> Request {
+        let mut body = String::new();
+        for &(name, content) in fields {
+            body.push_str(&format!(
+                "--boundary\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                name, content
+            ));
+        }
+        body.push_str("--boundary--\r\n");
+
+        Request::fake_http(
+            "POST",
+            "/",
+            vec![(
+                "Content-Type".to_owned(),
+                "multipart/form-data; boundary=boundary".to_owned(),
+            )],
+            body.into_bytes(),
+        )
+    }
+
+    // Builds a request with a single field named `a`, using the given `Content-Type` and `xyz` as
+    // the boundary.
+    fn request_with_content_type(content_type: &str) -> Request {
+        Request::fake_http(
+            "POST",
+            "/",
+            vec![("Content-Type".to_owned(), content_type.to_owned())],
+            b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nhello\r\n--xyz--\r\n"
+                .to_vec(),
+        )
+    }
+
+    fn first_field_name(request: &Request) -> Option<String> {
+        let mut input = get_multipart_input(request).ok()?;
+        let field = input.next()?;
+        Some(field.headers.name.to_string())
+    }
+
+    #[test]
+    fn boundary_quoted() {
+        let request = request_with_content_type("multipart/form-data; boundary=\"xyz\"");
+        assert_eq!(first_field_name(&request), Some("a".to_owned()));
+    }
+
+    #[test]
+    fn boundary_params_reordered() {
+        let request = request_with_content_type(
+            "multipart/form-data; charset=utf-8; Boundary=\"xyz\"; foo=\"a;b\"",
+        );
+        assert_eq!(first_field_name(&request), Some("a".to_owned()));
+    }
+
+    #[test]
+    fn boundary_multipart_mixed() {
+        let request = request_with_content_type("Multipart/Mixed; boundary=xyz");
+        assert_eq!(first_field_name(&request), Some("a".to_owned()));
+    }
+
+    #[test]
+    fn boundary_not_multipart() {
+        let request = request_with_content_type("text/plain; boundary=xyz");
+        match get_multipart_input(&request) {
+            Err(MultipartError::WrongContentType) => (),
+            _ => panic!(),
+        }
+
+        let request = request_with_content_type("multipart/form-data; boundary=\"\"");
+        match get_multipart_input(&request) {
+            Err(MultipartError::WrongContentType) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn foreach_field_sum() {
+        let request = Request::fake_http(
+            "POST",
+            "/",
+            vec![(
+                "Content-Type".to_owned(),
+                "multipart/form-data; boundary=boundary".to_owned(),
+            )],
+            b"--boundary\r\n\
+              Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+              hello\r\n\
+              --boundary\r\n\
+              Content-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\
+              Content-Type: application/octet-stream\r\n\r\n\
+              \x01\x02\x03\x04\r\n\
+              --boundary--\r\n"
+                .to_vec(),
+        );
 
-    let end = content_type[start..]
-        .find(';')
-        .map_or(content_type.len(), |end| start + end);
-    Some(content_type[start..end].to_owned())
+        let mut input = get_multipart_input(&request).unwrap();
+        let mut names = Vec::new();
+        let mut sum = 0u32;
+        input
+            .foreach_field(|headers, content| {
+                names.push(headers.name.to_string());
+                if headers.filename.is_some() {
+                    let mut data = Vec::new();
+                    content.read_to_end(&mut data)?;
+                    sum += data.iter().map(|&b| u32::from(b)).sum::<u32>();
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(names, vec!["text", "file"]);
+        assert_eq!(sum, 10);
+    }
+
+    #[test]
+    fn foreach_field_too_large() {
+        let request = request(&[("a", "hi"), ("b", "hello world")]);
+        let mut input =
+            get_multipart_input_with_limits(&request, limits(None, Some(5), None)).unwrap();
+
+        let result = input.foreach_field(|_, content| {
+            io::copy(content, &mut io::sink())?;
+            Ok(())
+        });
+        match result {
+            Err(SaveError::LimitExceeded) => (),
+            _ => panic!(),
+        }
+    }
+
+    fn limits(fields: Option<u32>, file: Option<u64>, total: Option<u64>) -> MultipartLimits {
+        MultipartLimits {
+            max_fields: fields,
+            max_file_size: file,
+            max_total_size: total,
+        }
+    }
+
+    #[test]
+    fn next_within_limits() {
+        let request = request(&[("a", "1"), ("b", "2")]);
+        let mut input =
+            get_multipart_input_with_limits(&request, limits(Some(2), None, None)).unwrap();
+
+        let mut names = Vec::new();
+        while let Some(field) = input.next() {
+            names.push(field.headers.name.to_string());
+        }
+        assert_eq!(names, vec!["a", "b"]);
+        assert!(!input.limit_exceeded());
+    }
+
+    #[test]
+    fn next_too_many_fields() {
+        let request = request(&[("a", "1"), ("b", "2"), ("c", "3")]);
+        let mut input =
+            get_multipart_input_with_limits(&request, limits(Some(2), None, None)).unwrap();
+
+        assert!(input.next().is_some());
+        assert!(input.next().is_some());
+        assert!(input.next().is_none());
+        assert!(input.limit_exceeded());
+    }
+
+    #[test]
+    fn save_within_limits() {
+        let request = request(&[("a", "1"), ("b", "2")]);
+        let mut input =
+            get_multipart_input_with_limits(&request, limits(Some(2), Some(1), Some(1000)))
+                .unwrap();
+
+        let entries = input.save().unwrap();
+        assert_eq!(entries.fields_count(), 2);
+        assert!(!input.limit_exceeded());
+    }
+
+    #[test]
+    fn save_too_many_fields() {
+        let request = request(&[("a", "1"), ("b", "2"), ("c", "3")]);
+        let mut input =
+            get_multipart_input_with_limits(&request, limits(Some(2), None, None)).unwrap();
+
+        match input.save() {
+            Err(SaveError::LimitExceeded) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn save_field_too_large() {
+        let request = request(&[("a", "hello world")]);
+        let mut input =
+            get_multipart_input_with_limits(&request, limits(None, Some(5), None)).unwrap();
+
+        match input.save() {
+            Err(SaveError::LimitExceeded) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn save_body_too_large() {
+        let request = request(&[("a", "hello world"), ("b", "hello world")]);
+        let mut input =
+            get_multipart_input_with_limits(&request, limits(None, None, Some(100))).unwrap();
+
+        match input.save() {
+            Err(SaveError::LimitExceeded) => (),
+            _ => panic!(),
+        }
+        assert!(input.limit_exceeded());
+    }
 }
@@ -0,0 +1,246 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use chrono;
+use std::time::SystemTime;
+
+use response::split_etags;
+use Request;
+
+/// Outcome of `check_precondition`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PreconditionResult {
+    /// The request has no precondition, or its preconditions are met. The handler should process
+    /// the request as usual.
+    Proceed,
+
+    /// The client already has the current version of the resource. The handler should answer
+    /// with an empty 304 response. Only happens for `GET` and `HEAD` requests.
+    NotModified,
+
+    /// A precondition failed. The handler should answer with an empty 412 response without
+    /// doing anything else.
+    PreconditionFailed,
+}
+
+/// Evaluates the `If-Match`, `If-Unmodified-Since`, `If-None-Match` and `If-Modified-Since`
+/// headers of the request against the current state of the resource, in the order given by
+/// RFC 7232.
+///
+/// `current_etag` and `last_modified` are the `ETag` and the modification time of the resource
+/// as it is now, or `None` if they aren't known or if the resource doesn't exist.
+///
+/// This is mostly useful for `PUT` and `DELETE` requests. Clients send for example the `ETag`
+/// they previously received in an `If-Match` header, so that their modification is rejected if
+/// someone else modified the resource in the meantime. For `GET` requests, `Response::with_etag`
+/// and `Response::with_last_modified` already handle the conditional headers.
+///
+/// `If-Match` uses the strong comparison, meaning that a weak `ETag` (starting with `W/`) never
+/// matches. `If-None-Match` uses the weak comparison. `*` matches if the resource exists.
+///
+/// # Example
+///
+/// ```
+/// use rouille::input::{self, PreconditionResult};
+/// use rouille::Request;
+/// use rouille::Response;
+///
+/// fn handle_put(request: &Request, current_etag: &str) -> Response {
+///     // `NotModified` is only returned for `GET` and `HEAD` requests.
+///     let result = input::check_precondition(request, Some(current_etag), None);
+///     if result == PreconditionResult::PreconditionFailed {
+///         return Response::text("The resource was modified in the meantime").with_status_code(412);
+///     }
+///
+///     // ... update the resource ...
+///     Response::empty_204()
+/// }
+/// ```
+pub fn check_precondition(
+    request: &Request,
+    current_etag: Option<&str>,
+    last_modified: Option<SystemTime>,
+) -> PreconditionResult {
+    let exists = current_etag.is_some() || last_modified.is_some();
+    let last_modified = last_modified.map(|t| chrono::DateTime::<chrono::Utc>::from(t).timestamp());
+
+    // Returns the list of tags of all the headers with the given name, or `None` if the request
+    // doesn't have such a header.
+    let tags = |name: &str| {
+        let mut headers = request
+            .headers()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .peekable();
+        headers.peek()?;
+        Some(
+            headers
+                .flat_map(|(_, value)| split_etags(value))
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    let date = |name: &str| {
+        request
+            .header(name)
+            .and_then(|date| chrono::DateTime::parse_from_rfc2822(date).ok())
+            .map(|date| date.timestamp())
+    };
+
+    if let Some(tags) = tags("If-Match") {
+        let matches = tags.iter().any(|tag| match (*tag, current_etag) {
+            ("*", _) => exists,
+            (tag, Some(etag)) => !tag.starts_with("W/") && !etag.starts_with("W/") && tag == etag,
+            (_, None) => false,
+        });
+        if !matches {
+            return PreconditionResult::PreconditionFailed;
+        }
+    } else if let (Some(since), Some(last_modified)) = (date("If-Unmodified-Since"), last_modified)
+    {
+        if last_modified > since {
+            return PreconditionResult::PreconditionFailed;
+        }
+    }
+
+    let is_get = request.method() == "GET" || request.method() == "HEAD";
+
+    if let Some(tags) = tags("If-None-Match") {
+        let opaque = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_owned();
+        let matches = tags.iter().any(|tag| match (*tag, current_etag) {
+            ("*", _) => exists,
+            (tag, Some(etag)) => opaque(tag) == opaque(etag),
+            (_, None) => false,
+        });
+        if matches {
+            return if is_get {
+                PreconditionResult::NotModified
+            } else {
+                PreconditionResult::PreconditionFailed
+            };
+        }
+    } else if let (true, Some(since), Some(last_modified)) =
+        (is_get, date("If-Modified-Since"), last_modified)
+    {
+        if last_modified <= since {
+            return PreconditionResult::NotModified;
+        }
+    }
+
+    PreconditionResult::Proceed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_precondition;
+    use super::PreconditionResult;
+    use std::time::{Duration, UNIX_EPOCH};
+    use Request;
+
+    fn request(method: &str, header: &str, value: &str) -> Request {
+        Request::fake_http(method, "/", vec![], vec![]).with_header(header, value)
+    }
+
+    #[test]
+    fn no_precondition() {
+        let request = Request::fake_http("PUT", "/", vec![], vec![]);
+        assert_eq!(
+            check_precondition(&request, Some("\"a\""), None),
+            PreconditionResult::Proceed
+        );
+    }
+
+    #[test]
+    fn if_match() {
+        let request = request("PUT", "If-Match", "\"a\", \"b\"");
+        assert_eq!(
+            check_precondition(&request, Some("\"b\""), None),
+            PreconditionResult::Proceed
+        );
+        assert_eq!(
+            check_precondition(&request, Some("\"c\""), None),
+            PreconditionResult::PreconditionFailed
+        );
+        assert_eq!(
+            check_precondition(&request, None, None),
+            PreconditionResult::PreconditionFailed
+        );
+
+        // Weak ETags never match `If-Match`.
+        let request = self::request("DELETE", "If-Match", "W/\"a\"");
+        assert_eq!(
+            check_precondition(&request, Some("W/\"a\""), None),
+            PreconditionResult::PreconditionFailed
+        );
+    }
+
+    #[test]
+    fn if_match_star() {
+        let request = request("PUT", "If-Match", "*");
+        assert_eq!(
+            check_precondition(&request, Some("\"a\""), None),
+            PreconditionResult::Proceed
+        );
+        assert_eq!(
+            check_precondition(&request, None, None),
+            PreconditionResult::PreconditionFailed
+        );
+    }
+
+    #[test]
+    fn if_none_match() {
+        // Creating a resource only if it doesn't exist yet.
+        let request = request("PUT", "If-None-Match", "*");
+        assert_eq!(
+            check_precondition(&request, None, None),
+            PreconditionResult::Proceed
+        );
+        assert_eq!(
+            check_precondition(&request, Some("\"a\""), None),
+            PreconditionResult::PreconditionFailed
+        );
+
+        let request = self::request("GET", "If-None-Match", "W/\"a\"");
+        assert_eq!(
+            check_precondition(&request, Some("\"a\""), None),
+            PreconditionResult::NotModified
+        );
+    }
+
+    #[test]
+    fn if_unmodified_since() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        let request = request(
+            "PUT",
+            "If-Unmodified-Since",
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+        );
+        assert_eq!(
+            check_precondition(&request, None, Some(time)),
+            PreconditionResult::Proceed
+        );
+        assert_eq!(
+            check_precondition(&request, None, Some(time + Duration::from_secs(1))),
+            PreconditionResult::PreconditionFailed
+        );
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        let request = request("GET", "If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(
+            check_precondition(&request, None, Some(time)),
+            PreconditionResult::NotModified
+        );
+        assert_eq!(
+            check_precondition(&request, None, Some(time + Duration::from_secs(1))),
+            PreconditionResult::Proceed
+        );
+    }
+}
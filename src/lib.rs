@@ -63,6 +63,8 @@ extern crate chrono;
 #[cfg(feature = "gzip")]
 extern crate deflate;
 extern crate filetime;
+#[cfg(feature = "websocket-deflate")]
+extern crate flate2;
 extern crate multipart;
 extern crate rand;
 extern crate serde;
@@ -72,7 +74,6 @@ pub extern crate percent_encoding;
 extern crate serde_json;
 extern crate sha1_smol;
 extern crate threadpool;
-extern crate time;
 extern crate tiny_http;
 pub extern crate url;
 
@@ -90,40 +91,56 @@ pub const DEFAULT_ENCODE_SET: &percent_encoding::AsciiSet = &percent_encoding::C
 
 pub use assets::extension_to_mime;
 pub use assets::match_assets;
-pub use log::{log, log_custom};
+pub use log::{log, log_custom, log_with_format, LogEntry, LogFormat, LogFormatter};
 pub use response::{Response, ResponseBody};
 pub use tiny_http::ReadWrite;
 
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::io;
 use std::io::Cursor;
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
 use std::io::Read;
 use std::io::Result as IoResult;
 use std::marker::PhantomData;
+use std::net::IpAddr;
 use std::net::SocketAddr;
+use std::net::TcpListener;
 use std::net::ToSocketAddrs;
+use std::num::NonZeroUsize;
 use std::panic;
 use std::panic::AssertUnwindSafe;
+use std::path::Path;
 use std::slice::Iter as SliceIter;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
+use std::sync::Condvar;
 use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
+use std::time::Instant;
 
 pub mod cgi;
 pub mod content_encoding;
 pub mod input;
+pub mod middleware;
 pub mod proxy;
 pub mod session;
+pub mod sse;
 pub mod websocket;
 
 mod assets;
 mod find_route;
 mod log;
 mod response;
-mod router;
+#[doc(hidden)]
+pub mod router;
 #[doc(hidden)]
 pub mod try_or_400;
 
@@ -139,6 +156,101 @@ macro_rules! try_or_404 {
     };
 }
 
+/// This macro assumes that the current function returns a `Response` and takes a `Result`.
+/// If the expression you pass to the macro is an error, then a 500 response is returned.
+///
+/// Use this for errors that are the fault of the server rather than of the client, for example
+/// when the database can't be reached. The body of the response is `Internal Server Error` and
+/// doesn't contain any detail about the error.
+///
+/// You can pass a closure as second parameter. It is called with the error before the response is
+/// returned, which is useful for logging.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// # extern crate postgres;
+/// # fn main() {
+/// use rouille::Request;
+/// use rouille::Response;
+///
+/// fn list_notes(request: &Request, db: &mut postgres::Client) -> Response {
+///     let rows = try_or_500!(db.query("SELECT content FROM notes", &[]), |err| {
+///         eprintln!("failed to list the notes: {}", err)
+///     });
+///
+///     let notes: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+///     Response::json(&notes)
+/// }
+/// # }
+/// ```
+#[macro_export]
+macro_rules! try_or_500 {
+    ($result:expr) => {
+        $crate::try_or_500!($result, |_| ())
+    };
+    ($result:expr, $on_error:expr) => {
+        match $result {
+            Ok(r) => r,
+            Err(err) => {
+                ($on_error)(err);
+                return $crate::Response::text("Internal Server Error").with_status_code(500);
+            }
+        }
+    };
+}
+
+/// Same as `try_or_404!`, but the body of the response is `{"error": "Not Found"}` instead of
+/// being empty.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// # fn main() {
+/// use std::collections::HashMap;
+/// use rouille::Response;
+///
+/// fn get_user(users: &HashMap<u32, String>, id: u32) -> Response {
+///     let name = try_or_404_json!(users.get(&id).ok_or(()));
+///     Response::json(name)
+/// }
+///
+/// let response = get_user(&HashMap::new(), 5);
+/// assert_eq!(response.status_code, 404);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! try_or_404_json {
+    ($result:expr) => {
+        match $result {
+            Ok(r) => r,
+            Err(_) => return $crate::Response::json_error(404, "Not Found"),
+        }
+    };
+}
+
+/// Same as `try_or_500!`, but the body of the response is `{"error": "Internal Server Error"}`.
+///
+/// As with `try_or_500!`, the response doesn't contain any detail about the error, and you can
+/// pass a closure as second parameter in order to log it.
+#[macro_export]
+macro_rules! try_or_500_json {
+    ($result:expr) => {
+        $crate::try_or_500_json!($result, |_| ())
+    };
+    ($result:expr, $on_error:expr) => {
+        match $result {
+            Ok(r) => r,
+            Err(err) => {
+                ($on_error)(err);
+                return $crate::Response::json_error(500, "Internal Server Error");
+            }
+        }
+    };
+}
+
 /// This macro assumes that the current function returns a `Response`. If the condition you pass
 /// to the macro is false, then a 400 response is returned.
 ///
@@ -170,6 +282,60 @@ macro_rules! assert_or_400 {
     };
 }
 
+/// This macro assumes that the current function returns a `Response`. If the condition you pass
+/// to the macro is false, then a 403 response is returned.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// # fn main() {
+/// use rouille::Request;
+/// use rouille::Response;
+///
+/// fn delete_note(request: &Request, current_user: &str, note_author: &str) -> Response {
+///     // Only the author of a note is allowed to delete it.
+///     assert_or_403!(current_user == note_author);
+///     Response::empty_204()
+/// }
+/// # }
+/// ```
+#[macro_export]
+macro_rules! assert_or_403 {
+    ($cond:expr) => {
+        if !$cond {
+            return $crate::Response::empty_403();
+        }
+    };
+}
+
+/// This macro assumes that the current function returns a `Response`. If the condition you pass
+/// to the macro is false, then a 404 response is returned.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// # fn main() {
+/// use std::collections::HashMap;
+/// use rouille::Request;
+/// use rouille::Response;
+///
+/// fn get_note(request: &Request, notes: &HashMap<u32, String>, id: u32) -> Response {
+///     assert_or_404!(notes.contains_key(&id));
+///     Response::text(notes[&id].clone())
+/// }
+/// # }
+/// ```
+#[macro_export]
+macro_rules! assert_or_404 {
+    ($cond:expr) => {
+        if !$cond {
+            return $crate::Response::empty_404();
+        }
+    };
+}
+
 /// Starts a server and uses the given requests handler.
 ///
 /// The request handler takes a `&Request` and must return a `Response` to send to the user.
@@ -215,7 +381,8 @@ macro_rules! assert_or_400 {
 ///
 /// # Panic handling in the handler
 ///
-/// If your request handler panics, a 500 error will automatically be sent to the client.
+/// If your request handler panics, a 500 error will automatically be sent to the client. Use
+/// `Server::with_panic_handler` if you want to customize that response.
 ///
 /// # Panic
 ///
@@ -243,11 +410,8 @@ where
     A: ToSocketAddrs,
     F: Send + Sync + 'static + Fn(&Request) -> Response,
 {
-    let pool_size = pool_size.unwrap_or_else(|| {
-        8 * thread::available_parallelism()
-            .map(|n| n.get())
-            .unwrap_or(1)
-    });
+    let pool_size =
+        pool_size.unwrap_or_else(|| default_pool_size(thread::available_parallelism().ok()));
 
     Server::new(addr, handler)
         .expect("Failed to start server")
@@ -256,6 +420,12 @@ where
     panic!("The server socket closed unexpectedly")
 }
 
+// Size of the thread pool used by `start_server_with_pool` when none is specified. If the number
+// of CPUs is unknown, one CPU is assumed.
+fn default_pool_size(num_cpus: Option<NonZeroUsize>) -> usize {
+    8 * num_cpus.map_or(1, NonZeroUsize::get)
+}
+
 struct AtomicCounter(Arc<AtomicUsize>);
 
 impl AtomicCounter {
@@ -271,10 +441,11 @@ impl Drop for AtomicCounter {
     }
 }
 
-/// Executes a function in either a thread of a thread pool
+/// Executes a function in either a thread of a thread pool, or on the current thread
 enum Executor {
     Threaded { count: Arc<AtomicUsize> },
     Pooled { pool: threadpool::ThreadPool },
+    Inline,
 }
 impl Executor {
     /// `size` must be greater than zero or the call to `ThreadPool::new` will panic.
@@ -296,6 +467,7 @@ impl Executor {
             Executor::Pooled { ref pool } => {
                 pool.execute(f);
             }
+            Executor::Inline => f(),
         }
     }
 
@@ -309,6 +481,7 @@ impl Executor {
             Executor::Pooled { ref pool } => {
                 pool.join();
             }
+            Executor::Inline => {}
         }
     }
 }
@@ -321,6 +494,90 @@ impl Default for Executor {
     }
 }
 
+/// Runs tasks once their deadline is reached, all from the same background thread.
+///
+/// The thread stops when the `Watchdog` is dropped.
+struct Watchdog {
+    shared: Arc<(Mutex<WatchdogState>, Condvar)>,
+}
+
+#[derive(Default)]
+struct WatchdogState {
+    deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+    // Tasks that haven't been run or cancelled yet, by identifier.
+    tasks: HashMap<u64, Box<dyn FnOnce() + Send>>,
+    next_id: u64,
+    stopped: bool,
+}
+
+impl Watchdog {
+    fn new() -> Watchdog {
+        let shared = Arc::new((Mutex::new(WatchdogState::default()), Condvar::new()));
+        let thread_shared = shared.clone();
+        thread::spawn(move || {
+            let (ref state, ref condvar) = *thread_shared;
+            let mut state = state.lock().unwrap();
+            while !state.stopped {
+                let now = Instant::now();
+                match state.deadlines.peek() {
+                    Some(&Reverse((deadline, id))) if deadline <= now => {
+                        state.deadlines.pop();
+                        // Cancelled tasks are only removed from `tasks`.
+                        if let Some(task) = state.tasks.remove(&id) {
+                            drop(state);
+                            task();
+                            state = thread_shared.0.lock().unwrap();
+                        }
+                    }
+                    Some(&Reverse((deadline, _))) => {
+                        state = condvar.wait_timeout(state, deadline - now).unwrap().0;
+                    }
+                    None => state = condvar.wait(state).unwrap(),
+                }
+            }
+        });
+        Watchdog { shared }
+    }
+
+    /// Runs `task` at `deadline`, unless the returned guard has been dropped before.
+    fn schedule<F>(&self, deadline: Instant, task: F) -> WatchdogGuard
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let (ref state, ref condvar) = *self.shared;
+        let mut state = state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.deadlines.push(Reverse((deadline, id)));
+        state.tasks.insert(id, Box::new(task));
+        condvar.notify_one();
+        WatchdogGuard {
+            shared: self.shared.clone(),
+            id,
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let (ref state, ref condvar) = *self.shared;
+        state.lock().unwrap().stopped = true;
+        condvar.notify_one();
+    }
+}
+
+/// Cancels a task of a `Watchdog` when dropped.
+struct WatchdogGuard {
+    shared: Arc<(Mutex<WatchdogState>, Condvar)>,
+    id: u64,
+}
+
+impl Drop for WatchdogGuard {
+    fn drop(&mut self) {
+        self.shared.0.lock().unwrap().tasks.remove(&self.id);
+    }
+}
+
 /// A listening server.
 ///
 /// This struct is the more manual server creation API of rouille and can be used as an alternative
@@ -329,6 +586,24 @@ impl Default for Executor {
 /// The `start_server` function is just a shortcut for `Server::new` followed with `run`. See the
 /// documentation of the `start_server` function for more details about the handler.
 ///
+/// # Keep-alive
+///
+/// Connections are kept alive between requests for as long as the client wants to, following the
+/// usual HTTP/1.1 rules. The connections are handled by tiny-http, which doesn't currently have
+/// an idle timeout: a connection waiting for its next request stays open, and holds one of
+/// tiny-http's connection threads, until the client closes it. If this is a concern, you should
+/// run the server behind a reverse proxy that enforces such a timeout.
+///
+/// Similarly, tiny-http accepts every incoming connection as soon as possible and there is no
+/// way to limit the number of connections open at the same time. A flood of connections can
+/// therefore exhaust the file descriptors of the process. Limit them with a reverse proxy or
+/// with a firewall rule if this matters for your deployment.
+///
+/// Responses use the HTTP version of the request. HTTP/1.0 connections are closed after the
+/// response unless the client sent `Connection: keep-alive`. Note that tiny-http doesn't let
+/// responses carry a `Connection` header, so the server doesn't confirm keep-alive to HTTP/1.0
+/// clients, and these clients will usually close the connection themselves.
+///
 /// # Example
 ///
 /// ```no_run
@@ -344,9 +619,14 @@ impl Default for Executor {
 pub struct Server<F> {
     server: tiny_http::Server,
     handler: Arc<AssertUnwindSafe<F>>,
+    panic_handler: Option<Arc<PanicHandler>>,
+    handler_timeout: Option<(Duration, Arc<Watchdog>)>,
     executor: Executor,
 }
 
+// Function that builds the response sent when the handler panics.
+type PanicHandler = dyn Fn(&Request, &(dyn Any + Send)) -> Response + Send + Sync;
+
 impl<F> Server<F>
 where
     F: Send + Sync + 'static + Fn(&Request) -> Response,
@@ -366,6 +646,80 @@ where
             server,
             executor: Executor::default(),
             handler: Arc::new(AssertUnwindSafe(handler)), // TODO: using AssertUnwindSafe here is wrong, but unwind safety has some usability problems in Rust in general
+            panic_handler: None,
+            handler_timeout: None,
+        })
+    }
+
+    /// Builds a new `Server` object that accepts connections from an already-bound listener.
+    ///
+    /// This can be used with sockets inherited from a parent process, for example with systemd
+    /// socket activation, so that connections aren't refused while the server restarts.
+    ///
+    /// Only one listener can be used per `Server`. In order to listen on multiple sockets, build
+    /// one `Server` for each of them.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::net::TcpListener;
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let listener = TcpListener::bind("localhost:8000").unwrap();
+    /// let server = Server::from_listener(listener, |request| {
+    ///     Response::text("hello world")
+    /// }).unwrap();
+    /// server.run();
+    /// ```
+    pub fn from_listener(
+        listener: TcpListener,
+        handler: F,
+    ) -> Result<Server<F>, Box<dyn Error + Send + Sync + 'static>> {
+        let server = tiny_http::Server::from_listener(listener, None)?;
+        Ok(Server {
+            server,
+            executor: Executor::default(),
+            handler: Arc::new(AssertUnwindSafe(handler)), // TODO: using AssertUnwindSafe here is wrong, but unwind safety has some usability problems in Rust in general
+            panic_handler: None,
+            handler_timeout: None,
+        })
+    }
+
+    /// Builds a new `Server` object that listens on a Unix domain socket at the given path.
+    ///
+    /// This is typically used when the server runs behind a reverse proxy on the same machine.
+    /// The path must not exist yet, and the socket file is removed when the server is dropped.
+    ///
+    /// Unix sockets don't have an IP address, so `remote_addr()` returns `0.0.0.0:0` for the
+    /// requests of such a server, and `server_addr()` panics.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let server = Server::new_unix("/run/my-app.sock", |request| {
+    ///     Response::text("hello world")
+    /// }).unwrap();
+    /// server.run();
+    /// ```
+    #[cfg(unix)]
+    pub fn new_unix<P>(
+        path: P,
+        handler: F,
+    ) -> Result<Server<F>, Box<dyn Error + Send + Sync + 'static>>
+    where
+        P: AsRef<Path>,
+    {
+        let server = tiny_http::Server::http_unix(path.as_ref())?;
+        Ok(Server {
+            server,
+            executor: Executor::default(),
+            handler: Arc::new(AssertUnwindSafe(handler)), // TODO: using AssertUnwindSafe here is wrong, but unwind safety has some usability problems in Rust in general
+            panic_handler: None,
+            handler_timeout: None,
         })
     }
 
@@ -373,6 +727,20 @@ where
     ///
     /// After this function returns, the HTTPS server is listening.
     ///
+    /// `certificate` must contain the PEM-encoded certificate chain, starting with the
+    /// certificate of the server. `private_key` must contain the PEM-encoded private key, which
+    /// can't be encrypted. With the `rustls` feature the key must be either a PKCS#8 key
+    /// (`BEGIN PRIVATE KEY`) or an RSA key (`BEGIN RSA PRIVATE KEY`). With the `ssl` feature any
+    /// key supported by OpenSSL can be used.
+    ///
+    /// The same certificate is used for all the connections, whatever the server name requested
+    /// by the client through SNI. In order to serve multiple hostnames, use a certificate that
+    /// covers all of them, or run one server per certificate.
+    ///
+    /// The certificate can't be changed while the server is running. In order to use a renewed
+    /// certificate, build a new `Server` and drop the old one once it has finished processing
+    /// its requests.
+    ///
     /// Returns an error if there was an error while creating the listening socket, for example if
     /// the port is already in use.
     #[cfg(any(feature = "ssl", feature = "rustls"))]
@@ -394,17 +762,127 @@ where
             server,
             executor: Executor::default(),
             handler: Arc::new(AssertUnwindSafe(handler)), // TODO: using AssertUnwindSafe here is wrong, but unwind safety has some usability problems in Rust in general
+            panic_handler: None,
+            handler_timeout: None,
         })
     }
 
     /// Use a `ThreadPool` of the given size to process requests
     ///
+    /// By default, a new thread is spawned for each request. With a pool, at most `pool_size`
+    /// handlers run at the same time and the other requests wait for a thread to be available.
+    /// A size of `1` processes the requests one by one, which is fine on single-core machines.
+    ///
+    /// This only concerns the threads that run the handler. Reading requests from connections is
+    /// done by threads managed by tiny-http, whose number can't be configured.
+    ///
     /// `pool_size` must be greater than zero or this function will panic.
     pub fn pool_size(mut self, pool_size: usize) -> Self {
         self.executor = Executor::with_size(pool_size);
         self
     }
 
+    /// Processes the requests on the thread that calls `run`, `poll`, `poll_timeout` or
+    /// `stoppable`, instead of using other threads.
+    ///
+    /// The requests are then processed one by one, and the response has been sent when `poll`
+    /// returns. This is mostly useful for tests, where it makes the behaviour of the server
+    /// deterministic. It is usually a bad idea in production, as a single slow handler blocks all
+    /// the other requests.
+    ///
+    /// Note that tiny-http still uses its own threads to accept connections and read requests.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::{Read, Write};
+    /// use std::net::TcpStream;
+    /// use std::time::Duration;
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let server = Server::new("localhost:0", |request| {
+    ///     Response::text("hello world")
+    /// }).unwrap().single_threaded();
+    ///
+    /// let mut stream = TcpStream::connect(server.server_addr()).unwrap();
+    /// stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    ///
+    /// // The response has been written once this function returns.
+    /// server.poll_timeout(Duration::from_millis(100));
+    ///
+    /// let mut response = String::new();
+    /// stream.read_to_string(&mut response).unwrap();
+    /// assert!(response.ends_with("hello world"));
+    /// ```
+    pub fn single_threaded(mut self) -> Self {
+        self.executor = Executor::Inline;
+        self
+    }
+
+    /// Sets the function that builds the response to send when the handler panics.
+    ///
+    /// The function receives the request and the payload of the panic, which is usually a `&str`
+    /// or a `String`. This can be used to log the panic or to render a custom error page. If this
+    /// function panics as well, or if no function has been set, a generic 500 page is sent.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let server = Server::new("localhost:0", |request| {
+    ///     Response::text("hello world")
+    /// }).unwrap()
+    ///   .with_panic_handler(|request, payload| {
+    ///     let message = payload.downcast_ref::<&str>().copied()
+    ///         .or_else(|| payload.downcast_ref::<String>().map(|s| &s[..]))
+    ///         .unwrap_or("unknown error");
+    ///     eprintln!("handler panicked on {}: {}", request.url(), message);
+    ///     Response::html("<h1>Oops</h1><p>Something went wrong.</p>").with_status_code(500)
+    /// });
+    /// server.run();
+    /// ```
+    pub fn with_panic_handler<P>(mut self, panic_handler: P) -> Self
+    where
+        P: Fn(&Request, &(dyn Any + Send)) -> Response + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Arc::new(panic_handler));
+        self
+    }
+
+    /// Sets the maximum time the handler can take to build a response.
+    ///
+    /// If the handler hasn't returned after `timeout`, an empty response with a 503 status code is
+    /// sent to the client, and the response returned later by the handler is discarded. From that
+    /// moment, `Request::is_connected` returns `false` and reading the body of the request returns
+    /// an error, so that the handler can stop early.
+    ///
+    /// Note that there is no way to force a thread to stop, so the handler keeps running until it
+    /// returns and its thread (or its slot in the pool if `pool_size` was used) stays busy in the
+    /// meantime. The `503` response has a `Connection: close` header, and the server closes the
+    /// connection after sending it.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let server = Server::new("localhost:0", |request| {
+    ///     Response::text("hello world")
+    /// }).unwrap()
+    ///   .with_handler_timeout(Duration::from_secs(30));
+    /// server.run();
+    /// ```
+    pub fn with_handler_timeout(mut self, timeout: Duration) -> Self {
+        // The timeouts of all the requests are handled by the same thread.
+        self.handler_timeout = Some((timeout, Arc::new(Watchdog::new())));
+        self
+    }
+
     /// Returns the address of the listening socket.
     #[inline]
     pub fn server_addr(&self) -> SocketAddr {
@@ -547,36 +1025,89 @@ where
 
     // Internal function, called when we got a request from tiny-http that needs to be processed.
     fn process(&self, request: tiny_http::Request) {
-        // We spawn a thread so that requests are processed in parallel.
+        // We spawn a thread so that requests are processed in parallel, unless `single_threaded`
+        // was used.
         let handler = self.handler.clone();
-        self.executor.execute(|| {
+        let panic_handler = self.panic_handler.clone();
+        let handler_timeout = self.handler_timeout.clone();
+        let received = Instant::now();
+        self.executor.execute(move || {
             // Small helper struct that makes it possible to put
             // a `tiny_http::Request` inside a `Box<Read>`.
             struct RequestRead(Arc<Mutex<Option<tiny_http::Request>>>);
             impl Read for RequestRead {
                 #[inline]
                 fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
-                    self.0
-                        .lock()
-                        .unwrap()
-                        .as_mut()
-                        .unwrap()
-                        .as_reader()
-                        .read(buf)
+                    match self.0.lock().unwrap().as_mut() {
+                        Some(request) => request.as_reader().read(buf),
+                        // The timeout of the handler has been reached and a response was sent.
+                        None => Err(IoError::new(
+                            IoErrorKind::TimedOut,
+                            "the handler took too long and a response was already sent",
+                        )),
+                    }
                 }
             }
 
+            // Wraps the body of the response. tiny-http reads the body until the end, unless
+            // writing to the client fails, in which case it stops and silently drops the reader.
+            struct ResponseRead {
+                inner: Box<dyn Read + Send>,
+                connected: Arc<AtomicBool>,
+                // `true` between the first call to `read` and the end of the body.
+                reading: bool,
+            }
+            impl Read for ResponseRead {
+                fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+                    let result = self.inner.read(buf);
+                    // An error of the body itself isn't a disconnection.
+                    self.reading = matches!(result, Ok(n) if n != 0 || buf.is_empty());
+                    result
+                }
+            }
+            impl Drop for ResponseRead {
+                fn drop(&mut self) {
+                    if self.reading {
+                        self.connected.store(false, Ordering::Relaxed);
+                    }
+                }
+            }
+
+            // RFC 7230 requires rejecting requests that have both a `Content-Length` and a
+            // `Transfer-Encoding`. tiny-http reads the body according to `Transfer-Encoding`, but a
+            // proxy in front of the server could use `Content-Length` instead, which can be
+            // exploited to smuggle a request inside the body of another one. Since we can't know
+            // where the next request starts, the connection is closed without reading the body.
+            let has_header = |name| request.headers().iter().any(|h| h.field.equiv(name));
+            if has_header("Content-Length") && has_header("Transfer-Encoding") {
+                let mut request = request;
+                request.close_connection();
+                let response = tiny_http::Response::from_string(
+                    "Requests can't have both a Content-Length and a Transfer-Encoding",
+                )
+                .with_status_code(400);
+                let _ = request.respond(response);
+                return;
+            }
+
             // Building the `Request` object.
+            let connected = Arc::new(AtomicBool::new(true));
             let tiny_http_request;
             let rouille_request = {
                 let url = request.url().to_owned();
                 let method = request.method().as_str().to_owned();
+                let version = request.http_version();
+                let http_version = (version.0, version.1);
                 let headers = request
                     .headers()
                     .iter()
                     .map(|h| (h.field.to_string(), h.value.clone().into()))
                     .collect();
-                let remote_addr = request.remote_addr().copied();
+                // Unix sockets don't have an address.
+                let remote_addr = request
+                    .remote_addr()
+                    .copied()
+                    .or_else(|| Some(SocketAddr::from(([0, 0, 0, 0], 0))));
 
                 tiny_http_request = Arc::new(Mutex::new(Some(request)));
                 let data = Arc::new(Mutex::new(Some(
@@ -586,23 +1117,53 @@ where
                 Request {
                     url,
                     method,
-                    headers,
+                    headers: Arc::new(headers),
                     https: false,
                     data,
                     remote_addr,
+                    connected: connected.clone(),
+                    received,
+                    http_version,
                 }
             };
 
+            // If the handler doesn't return in time, the watchdog answers the request in its
+            // place. The task is cancelled when `handler_done` is dropped.
+            let handler_done = handler_timeout.map(|(timeout, watchdog)| {
+                let tiny_http_request = tiny_http_request.clone();
+                let connected = connected.clone();
+                watchdog.schedule(Instant::now() + timeout, move || {
+                    let request = tiny_http_request.lock().unwrap().take();
+                    if let Some(mut request) = request {
+                        connected.store(false, Ordering::Relaxed);
+                        // The handler might be in the middle of reading the body, so we don't
+                        // know where the next request starts.
+                        request.close_connection();
+                        // Sending the response waits for the previous responses of the
+                        // connection, which must not block the other timeouts.
+                        thread::spawn(move || {
+                            let _ = request.respond(tiny_http::Response::empty(503));
+                        });
+                    }
+                })
+            });
+
             // Calling the handler ; this most likely takes a lot of time.
             // If the handler panics, we build a dummy response.
             let mut rouille_response = {
-                // We don't use the `rouille_request` anymore after the panic, so it's ok to assert
-                // it's unwind safe.
-                let rouille_request = AssertUnwindSafe(rouille_request);
-                let res = panic::catch_unwind(move || {
-                    let rouille_request = rouille_request;
-                    handler(&rouille_request)
-                });
+                // After a panic, the `rouille_request` is only passed to the panic handler, and
+                // nothing is used after a panic of the panic handler, so it's ok to assert it's
+                // unwind safe.
+                let request = AssertUnwindSafe(&rouille_request);
+                let res = panic::catch_unwind(|| handler(*request));
+
+                let res = match (res, panic_handler) {
+                    (Ok(r), _) => Ok(r),
+                    (Err(payload), Some(panic_handler)) => {
+                        panic::catch_unwind(AssertUnwindSafe(|| panic_handler(*request, &*payload)))
+                    }
+                    (Err(payload), None) => Err(payload),
+                };
 
                 match res {
                     Ok(r) => r,
@@ -614,12 +1175,57 @@ where
                 }
             };
 
+            drop(handler_done);
+
+            // The request has already been answered if the handler timed out.
+            let mut tiny_http_request = match tiny_http_request.lock().unwrap().take() {
+                Some(request) => request,
+                None => return,
+            };
+
+            // Reading and discarding what the handler didn't read from a chunked body, so that
+            // the next request of a keep-alive connection starts at the right place. tiny-http
+            // does this by itself for bodies with a `Content-Length`. Connections that are
+            // upgraded are never used for other requests, and their reader is the raw socket.
+            let is_upgrade = rouille_response.upgrade.is_some()
+                || rouille_request
+                    .header("Connection")
+                    .map_or(false, |h| h.to_ascii_lowercase().contains("upgrade"));
+            if rouille_request.header("Transfer-Encoding").is_some() && !is_upgrade {
+                let body_untouched = rouille_request.data.lock().unwrap().is_some();
+                let expects_continue = rouille_request
+                    .header("Expect")
+                    .map_or(false, |h| h.eq_ignore_ascii_case("100-continue"));
+
+                // Reading a body that the handler didn't want would send a `100 Continue` and
+                // make the client upload it. Bodies that are too large aren't worth reading
+                // either. In both cases we close the connection instead.
+                let drained = !(body_untouched && expects_continue) && {
+                    let mut rest = tiny_http_request.as_reader().take(MAX_DISCARDED_BODY + 1);
+                    matches!(io::copy(&mut rest, &mut io::sink()), Ok(n) if n <= MAX_DISCARDED_BODY)
+                };
+                if !drained {
+                    tiny_http_request.close_connection();
+                }
+            }
+
             // writing the response
             let (res_data, res_len) = rouille_response.data.into_reader_and_size();
+            let res_data = ResponseRead {
+                inner: res_data,
+                connected: connected.clone(),
+                reading: false,
+            };
             let mut response = tiny_http::Response::empty(rouille_response.status_code)
                 .with_data(res_data, res_len);
 
             let mut upgrade_header = "".into();
+            let connection_options = connection_options(
+                rouille_response
+                    .headers
+                    .iter()
+                    .map(|(k, v)| (&k[..], &v[..])),
+            );
 
             for (key, value) in rouille_response.headers {
                 if key.eq_ignore_ascii_case("Content-Length") {
@@ -631,6 +1237,13 @@ where
                     continue;
                 }
 
+                if connection_options
+                    .iter()
+                    .any(|name| key.eq_ignore_ascii_case(name))
+                {
+                    continue;
+                }
+
                 if let Ok(header) = tiny_http::Header::from_bytes(key.as_bytes(), value.as_bytes())
                 {
                     response.add_header(header);
@@ -640,18 +1253,14 @@ where
             }
 
             if let Some(ref mut upgrade) = rouille_response.upgrade {
-                let trq = tiny_http_request.lock().unwrap().take().unwrap();
-                let socket = trq.upgrade(&upgrade_header, response);
+                let socket = tiny_http_request.upgrade(&upgrade_header, response);
                 upgrade.build(socket);
             } else {
                 // We don't really care if we fail to send the response to the client, as there's
-                // nothing we can do anyway.
-                let _ = tiny_http_request
-                    .lock()
-                    .unwrap()
-                    .take()
-                    .unwrap()
-                    .respond(response);
+                // nothing we can do anyway. `ResponseRead` takes care of updating `connected`.
+                // Note that for `HEAD` requests tiny-http sends the headers (including the
+                // `Content-Length`) but never reads nor sends the body.
+                let _ = tiny_http_request.respond(response);
             }
         });
     }
@@ -672,10 +1281,16 @@ pub trait Upgrade {
 pub struct Request {
     method: String,
     url: String,
-    headers: Vec<(String, String)>,
+    // Shared with the requests created by `remove_prefix`.
+    headers: Arc<Vec<(String, String)>>,
     https: bool,
     data: Arc<Mutex<Option<Box<dyn Read + Send>>>>,
     remote_addr: Option<SocketAddr>,
+    // Set to `false` by the server when sending the body of the response to the client fails.
+    connected: Arc<AtomicBool>,
+    received: Instant,
+    // Major and minor version of the HTTP protocol of the request.
+    http_version: (u8, u8),
 }
 
 impl fmt::Debug for Request {
@@ -713,8 +1328,11 @@ impl Request {
             method: method.into(),
             https: false,
             data,
-            headers,
+            headers: Arc::new(headers),
             remote_addr,
+            connected: Arc::new(AtomicBool::new(true)),
+            received: Instant::now(),
+            http_version: (1, 1),
         }
     }
 
@@ -737,8 +1355,11 @@ impl Request {
             method: method.into(),
             https: false,
             data,
-            headers,
+            headers: Arc::new(headers),
             remote_addr: Some(from),
+            connected: Arc::new(AtomicBool::new(true)),
+            received: Instant::now(),
+            http_version: (1, 1),
         }
     }
 
@@ -764,8 +1385,11 @@ impl Request {
             method: method.into(),
             https: true,
             data,
-            headers,
+            headers: Arc::new(headers),
             remote_addr,
+            connected: Arc::new(AtomicBool::new(true)),
+            received: Instant::now(),
+            http_version: (1, 1),
         }
     }
 
@@ -788,14 +1412,35 @@ impl Request {
             method: method.into(),
             https: true,
             data,
-            headers,
+            headers: Arc::new(headers),
             remote_addr: Some(from),
+            connected: Arc::new(AtomicBool::new(true)),
+            received: Instant::now(),
+            http_version: (1, 1),
         }
     }
 
+    // Adds a header to a fake request. Existing headers with the same name are kept.
+    // Hidden from the documentation until the way of building fake requests is settled, but
+    // usable by the tests of other crates.
+    #[doc(hidden)]
+    pub fn with_header<K, V>(mut self, key: K, value: V) -> Request
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        Arc::make_mut(&mut self.headers).push((key.into(), value.into()));
+        self
+    }
+
     /// If the decoded URL of the request starts with `prefix`, builds a new `Request` that is
     /// the same as the original but without that prefix.
     ///
+    /// The prefix is compared against the percent-decoded path, so `remove_prefix("/my path")`
+    /// matches a request to `/my%20path/foo`. The URL of the returned request is still raw,
+    /// meaning that it is `/foo` in this example. An encoded slash (`%2F`) in the URL never
+    /// matches a `/` in the prefix, as it isn't a path separator.
+    ///
     /// # Example
     ///
     /// ```
@@ -811,24 +1456,28 @@ impl Request {
     /// }
     /// ```
     pub fn remove_prefix(&self, prefix: &str) -> Option<Request> {
-        if !self.url().starts_with(prefix) {
-            return None;
-        }
+        let prefix_len = raw_prefix_len(&self.url, prefix)?;
 
-        // TODO: url-encoded characters in the prefix are not implemented
-        assert!(self.url.starts_with(prefix));
         Some(Request {
             method: self.method.clone(),
-            url: self.url[prefix.len()..].to_owned(),
-            headers: self.headers.clone(), // TODO: expensive
+            url: self.url[prefix_len..].to_owned(),
+            headers: self.headers.clone(),
             https: self.https,
             data: self.data.clone(),
             remote_addr: self.remote_addr,
+            connected: self.connected.clone(),
+            received: self.received,
+            http_version: self.http_version,
         })
     }
 
     /// Returns `true` if the request uses HTTPS, and `false` if it uses HTTP.
     ///
+    /// This is the only information available about the TLS session. The TLS layer is handled by
+    /// tiny-http, which doesn't expose details such as the certificate of the client, the ALPN
+    /// protocol or the SNI server name. If you need them, for example for mutual TLS, terminate
+    /// TLS in a reverse proxy and have it forward these details in headers.
+    ///
     /// # Example
     ///
     /// ```
@@ -854,6 +1503,21 @@ impl Request {
         &self.method
     }
 
+    /// Returns the method of the request as a `Method`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{Method, Request};
+    ///
+    /// let request = Request::fake_http("PATCH", "/", vec![], vec![]);
+    /// assert_eq!(request.parsed_method(), Method::Patch);
+    /// ```
+    #[inline]
+    pub fn parsed_method(&self) -> Method {
+        Method::from(&self.method[..])
+    }
+
     /// Returns the raw URL requested by the client. It is not decoded and thus can contain strings
     /// such as `%20`, and the query parameters such as `?p=hello`.
     ///
@@ -872,6 +1536,69 @@ impl Request {
         &self.url
     }
 
+    /// Returns the host requested by the client, parsed from the `Host` header, as a name and an
+    /// optional port.
+    ///
+    /// The name is lowercase. For IPv6 addresses, the brackets are removed from the name. Returns
+    /// `None` if the header is missing, or if it isn't a valid host name or address (which is the
+    /// case for example if it contains a `/` or a `@`) or has an invalid port.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let headers = vec![("Host".to_owned(), "Example.com:8443".to_owned())];
+    /// let request = Request::fake_http("GET", "/", headers, vec![]);
+    /// assert_eq!(request.host(), Some(("example.com".to_owned(), Some(8443))));
+    /// ```
+    pub fn host(&self) -> Option<(String, Option<u16>)> {
+        let host = self.header("Host")?;
+
+        // IPv6 addresses are surrounded with brackets and contain `:`.
+        let (name, port) = if let Some(rest) = host.strip_prefix('[') {
+            let end = rest.find(']')?;
+            let name = &rest[..end];
+            if !name
+                .chars()
+                .all(|c| c.is_ascii_hexdigit() || c == ':' || c == '.')
+            {
+                return None;
+            }
+            (name, &rest[end + 1..])
+        } else {
+            let (name, port) = match host.find(':') {
+                Some(pos) => (&host[..pos], &host[pos..]),
+                None => (host, ""),
+            };
+            if !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+            {
+                return None;
+            }
+            (name, port)
+        };
+
+        if name.is_empty() {
+            return None;
+        }
+
+        // The port can be empty, in which case the default one is used.
+        let port = match port {
+            "" | ":" => None,
+            port => {
+                let port = port.strip_prefix(':')?;
+                if !port.bytes().all(|c| c.is_ascii_digit()) {
+                    return None;
+                }
+                Some(port.parse().ok()?)
+            }
+        };
+
+        Some((name.to_ascii_lowercase(), port))
+    }
+
     /// Returns the raw query string requested by the client. In other words, everything after the
     /// first `?` in the raw url.
     ///
@@ -894,7 +1621,8 @@ impl Request {
     ///
     /// > **Note**: This function will decode the token `%2F` will be decoded as `/`. However the
     /// > official specifications say that such a token must not count as a delimiter for URL paths.
-    /// > In other words, `/hello/world` is not the same as `/hello%2Fworld`.
+    /// > In other words, `/hello/world` is not the same as `/hello%2Fworld`. Use
+    /// > `url_raw_segments()` if you need to make the difference.
     ///
     /// # Example
     ///
@@ -917,24 +1645,101 @@ impl Request {
             .into_owned()
     }
 
-    /// Returns the value of a GET parameter or None if it doesn't exist.
-    pub fn get_param(&self, param_name: &str) -> Option<String> {
-        let name_pattern = &format!("{}=", param_name);
-        let param_pairs = self.raw_query_string().split('&');
-        param_pairs
-            .filter(|pair| pair.starts_with(name_pattern) || pair == &param_name)
-            .map(|pair| pair.split('=').nth(1).unwrap_or(""))
-            .next()
-            .map(|value| {
-                percent_encoding::percent_decode(value.replace('+', " ").as_bytes())
+    /// Returns the segments of the path of the URL requested by the client.
+    ///
+    /// The raw URL is split on `/` first, and each segment is decoded afterwards. Contrary to
+    /// `url()`, an encoded slash (`%2F`) is therefore not a separator and ends up as a `/` inside
+    /// of its segment. The leading `/` of the path is ignored and the query string is removed.
+    ///
+    /// If there is any non-unicode character in the URL, it will be replaced with `U+FFFD`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/files/a%2Fb%20c?foo=bar", vec![], vec![]);
+    /// assert_eq!(request.url_raw_segments(), vec!["files", "a/b c"]);
+    /// assert_eq!(request.url(), "/files/a/b c");
+    /// ```
+    pub fn url_raw_segments(&self) -> Vec<String> {
+        let path = self.url.split('?').next().unwrap_or("");
+        let path = path.strip_prefix('/').unwrap_or(path);
+
+        path.split('/')
+            .map(|segment| {
+                percent_encoding::percent_decode(segment.as_bytes())
                     .decode_utf8_lossy()
                     .into_owned()
             })
+            .collect()
+    }
+
+    /// Returns the value of a GET parameter or None if it doesn't exist.
+    ///
+    /// If the parameter appears multiple times in the query string, only the first value is
+    /// returned. Use `query_pairs()` to get all of them.
+    pub fn get_param(&self, param_name: &str) -> Option<String> {
+        self.query_pairs()
+            .find(|(name, _)| name == param_name)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns the list of parameters of the query string, as decoded `(name, value)` pairs.
+    ///
+    /// The pairs are returned in the order of the query string, and parameters that appear
+    /// multiple times are returned multiple times. A parameter without `=` has an empty value.
+    /// `+` is decoded as a space, and non-unicode characters are replaced with `U+FFFD`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/?a=1&b=hello+world&a=3", vec![], vec![]);
+    /// let pairs = request.query_pairs().collect::<Vec<_>>();
+    /// assert_eq!(pairs, vec![
+    ///     ("a".to_owned(), "1".to_owned()),
+    ///     ("b".to_owned(), "hello world".to_owned()),
+    ///     ("a".to_owned(), "3".to_owned()),
+    /// ]);
+    /// ```
+    #[inline]
+    pub fn query_pairs(&self) -> QueryPairsIter<'_> {
+        QueryPairsIter {
+            iter: self.raw_query_string().split(&['&'][..]),
+        }
+    }
+
+    /// Same as `query_pairs()`, but `;` is also considered as a separator between parameters,
+    /// which some legacy clients use.
+    ///
+    /// This isn't the default, as a `;` in a parameter is then interpreted differently depending
+    /// on the server.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/?a=1;b=2&c=3", vec![], vec![]);
+    /// let names = request.query_pairs_with_semicolons().map(|(n, _)| n).collect::<Vec<_>>();
+    /// assert_eq!(names, vec!["a", "b", "c"]);
+    /// ```
+    #[inline]
+    pub fn query_pairs_with_semicolons(&self) -> QueryPairsIter<'_> {
+        QueryPairsIter {
+            iter: self.raw_query_string().split(&['&', ';'][..]),
+        }
     }
 
     /// Returns the value of a header of the request.
     ///
-    /// Returns `None` if no such header could be found.
+    /// Returns `None` if no such header could be found. If the client sent the header multiple
+    /// times, only the first value is returned. Use `headers()` to get all of them.
+    ///
+    /// The name of the header is case-insensitive, and the value doesn't contain any leading or
+    /// trailing whitespace.
     #[inline]
     pub fn header(&self, key: &str) -> Option<&str> {
         self.headers
@@ -951,6 +1756,90 @@ impl Request {
         }
     }
 
+    /// Returns the list of cookies sent by the client, as `(name, value)` pairs.
+    ///
+    /// This is the same as `input::cookies(request)`.
+    #[inline]
+    pub fn cookies(&self) -> input::CookiesIter<'_> {
+        input::cookies(self)
+    }
+
+    /// Returns the value of a cookie sent by the client.
+    ///
+    /// Returns `None` if no such cookie could be found. If the client sent multiple cookies with
+    /// the same name, only the first one is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     match request.cookie("theme") {
+    ///         Some(theme) => Response::text(format!("Using the {} theme", theme)),
+    ///         None => Response::text("Using the default theme"),
+    ///     }
+    /// }
+    /// ```
+    #[inline]
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies()
+            .find(|&(n, _)| n == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Parses the `Content-Type` header of the request.
+    ///
+    /// Returns `None` if the header is missing. See the documentation of `ContentType` for how
+    /// the header is parsed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("POST", "/", vec![
+    ///     ("Content-Type".to_owned(), "text/plain; charset=UTF-8".to_owned())
+    /// ], vec![]);
+    ///
+    /// let content_type = request.content_type().unwrap();
+    /// assert_eq!(content_type.mime, "text/plain");
+    /// assert_eq!(content_type.param("charset"), Some("UTF-8"));
+    /// ```
+    pub fn content_type(&self) -> Option<ContentType> {
+        self.header("Content-Type").map(ContentType::parse)
+    }
+
+    /// Returns the value of the `Content-Length` header of the request.
+    ///
+    /// Returns `None` if the header is missing or can't be parsed. Requests whose body is sent
+    /// with `Transfer-Encoding: chunked` don't have a known length in advance, and this method
+    /// returns `None` for them as well.
+    ///
+    /// Note that this is only what the client announced. Use `data_to_bytes` or a `Take` adapter
+    /// if you need to enforce a limit while reading the body.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     match request.content_length() {
+    ///         Some(len) if len > 1024 * 1024 => Response::empty_400(),
+    ///         _ => Response::text("upload accepted"),
+    ///     }
+    /// }
+    /// ```
+    pub fn content_length(&self) -> Option<u64> {
+        if self.header("Transfer-Encoding").is_some() {
+            return None;
+        }
+
+        self.header("Content-Length")
+            .and_then(|len| len.trim().parse().ok())
+    }
+
     /// Returns the state of the `DNT` (Do Not Track) header.
     ///
     /// If the header is missing or is malformed, `None` is returned. If the header exists,
@@ -984,11 +1873,26 @@ impl Request {
     /// The body can only be retrieved once. Returns `None` is the body has already been retrieved
     /// before.
     ///
+    /// Bodies sent with `Transfer-Encoding: chunked` are decoded, so that the reader only yields
+    /// the content of the chunks.
+    ///
+    /// If the client sent an `Expect: 100-continue` header, the interim `100 Continue` response
+    /// is sent to the client the first time the body is read. If the handler never reads the body
+    /// (for example because it returns an error), the final response is sent directly instead.
+    /// Requests with any other value of `Expect` are answered with `417 Expectation Failed` by the
+    /// server, and never reach the handler.
+    ///
+    /// If the body is chunked, the part that the handler didn't read is read and discarded by the
+    /// server once the handler returns, before the response is sent, so that the next request of
+    /// the connection can be read. If more than 16 kiB are left, or if reading would send a
+    /// `100 Continue` for a body that the handler never asked for, the response gets a
+    /// `Connection: close` header and the server closes the connection after sending it instead.
+    ///
     /// # Example
     ///
     /// ```
     /// use std::io::Read;
-    /// use rouille::{Request, Response, ResponseBody};
+    /// use rouille::{Request, Response};
     ///
     /// fn echo(request: &Request) -> Response {
     ///     let mut data = request.data().expect("Oops, body already retrieved, problem \
@@ -1000,10 +1904,7 @@ impl Request {
     ///         Err(_) => return Response::text("Failed to read body")
     ///     };
     ///
-    ///     Response {
-    ///         data: ResponseBody::from_data(buf),
-    ///         .. Response::text("")
-    ///     }
+    ///     Response::text("").with_body_bytes(buf)
     /// }
     /// ```
     pub fn data(&self) -> Option<RequestBody> {
@@ -1014,8 +1915,67 @@ impl Request {
         })
     }
 
+    /// Reads the whole body of the request into a `Vec<u8>`.
+    ///
+    /// At most `max` bytes are read. If the body is larger than that, an error of kind
+    /// `InvalidData` is returned instead, so that a malicious client can't exhaust the memory of
+    /// the server.
+    ///
+    /// Like `data()`, the body can only be retrieved once. Returns `None` if the body has already
+    /// been retrieved before.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     match request.data_to_bytes(1024 * 1024) {
+    ///         Some(Ok(body)) => Response::text(format!("received {} bytes", body.len())),
+    ///         Some(Err(_)) => Response::text("Body too large or unreadable").with_status_code(400),
+    ///         None => Response::text("Body already retrieved").with_status_code(500),
+    ///     }
+    /// }
+    /// ```
+    pub fn data_to_bytes(&self, max: usize) -> Option<Result<Vec<u8>, IoError>> {
+        let body = self.data()?;
+
+        let mut out = Vec::new();
+        if let Err(err) = body
+            .take(max.saturating_add(1) as u64)
+            .read_to_end(&mut out)
+        {
+            return Some(Err(err));
+        }
+
+        if out.len() > max {
+            return Some(Err(IoError::new(
+                IoErrorKind::InvalidData,
+                "the request body exceeds the limit",
+            )));
+        }
+
+        Some(Ok(out))
+    }
+
+    /// Reads the whole body of the request into a `String`.
+    ///
+    /// This does the same as `data_to_bytes`, but also returns an error of kind `InvalidData` if
+    /// the body is not valid UTF-8.
+    pub fn data_to_string(&self, max: usize) -> Option<Result<String, IoError>> {
+        self.data_to_bytes(max).map(|body| {
+            body.and_then(|body| {
+                String::from_utf8(body).map_err(|err| IoError::new(IoErrorKind::InvalidData, err))
+            })
+        })
+    }
+
     /// Returns the address of the client that made this request.
     ///
+    /// If the server is behind a reverse proxy, this is the address of the proxy. See
+    /// `remote_addr_forwarded` in that situation. For requests received on a Unix domain
+    /// socket, this is `0.0.0.0:0`.
+    ///
     /// # Example
     ///
     /// ```
@@ -1031,6 +1991,216 @@ impl Request {
             .as_ref()
             .expect("Unexpected Unix socket for request")
     }
+
+    /// Returns the IP address of the client, taking into account the `X-Forwarded-For` header
+    /// added by the given trusted reverse proxies.
+    ///
+    /// If the request doesn't come from one of `trusted_proxies`, the header is ignored and the IP
+    /// of `remote_addr()` is returned. Otherwise the addresses of `X-Forwarded-For` are walked
+    /// from right to left, and the first one that isn't a trusted proxy is returned. Entries on
+    /// its left were provided by the client and can't be trusted.
+    ///
+    /// If an entry can't be parsed, or if all the entries are trusted proxies, the last trusted
+    /// address that was walked through is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::net::IpAddr;
+    /// use rouille::{Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     let load_balancer: IpAddr = "10.0.0.1".parse().unwrap();
+    ///     let ip = request.remote_addr_forwarded(&[load_balancer]);
+    ///     Response::text(format!("Your IP is: {}", ip))
+    /// }
+    /// ```
+    pub fn remote_addr_forwarded(&self, trusted_proxies: &[IpAddr]) -> IpAddr {
+        let mut addr = self.remote_addr().ip();
+        if !trusted_proxies.contains(&addr) {
+            return addr;
+        }
+
+        let forwarded = self
+            .headers
+            .iter()
+            .filter(|&(k, _)| k.eq_ignore_ascii_case("X-Forwarded-For"))
+            .flat_map(|(_, v)| v.split(','))
+            .collect::<Vec<_>>();
+
+        for entry in forwarded.into_iter().rev() {
+            let entry = entry.trim();
+            addr = match entry.parse::<IpAddr>() {
+                Ok(ip) => ip,
+                // Some proxies include the port.
+                Err(_) => match entry.parse::<SocketAddr>() {
+                    Ok(socket) => socket.ip(),
+                    Err(_) => break,
+                },
+            };
+
+            if !trusted_proxies.contains(&addr) {
+                break;
+            }
+        }
+
+        addr
+    }
+
+    // Returns the protocol of the request, for example `HTTP/1.1`. Fake requests use HTTP/1.1.
+    pub(crate) fn protocol(&self) -> String {
+        format!("HTTP/{}.{}", self.http_version.0, self.http_version.1)
+    }
+
+    /// Returns the time when the server received the request.
+    ///
+    /// More precisely, this is when the headers of the request have been parsed, before the
+    /// request waits for a thread to be available. For fake requests, this is when the request
+    /// was built.
+    #[inline]
+    pub fn received_at(&self) -> Instant {
+        self.received
+    }
+
+    /// Returns the time elapsed since the server received the request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     let response = Response::text("hello world");
+    ///     let elapsed = request.elapsed();
+    ///     response.with_additional_header(
+    ///         "X-Response-Time",
+    ///         format!("{}ms", elapsed.as_millis()),
+    ///     )
+    /// }
+    /// ```
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        self.received.elapsed()
+    }
+
+    /// Returns `false` if the server noticed that the client has disconnected.
+    ///
+    /// See the documentation of `ConnectionStatus` for when a disconnection is detected. Always
+    /// returns `true` for fake requests.
+    #[inline]
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Returns an object that can be used to check whether the client is still connected, after
+    /// the handler has returned.
+    ///
+    /// This is meant to be used by the code that produces the body of a streaming response, for
+    /// example in a separate thread.
+    #[inline]
+    pub fn connection_status(&self) -> ConnectionStatus {
+        ConnectionStatus {
+            connected: self.connected.clone(),
+        }
+    }
+}
+
+/// Indicates whether the client of a request is still connected.
+///
+/// Obtained with `Request::connection_status()`.
+///
+/// The server can't observe the socket while the handler is running. A disconnection is only
+/// detected when writing the response to the client fails, and then takes effect for the rest of
+/// the body. For long-lived responses such as server-sent events or long-polling, you should make
+/// sure that the body regularly produces data (for example heartbeats) so that a client that went
+/// away is noticed. The reader of the body is also dropped when this happens.
+///
+/// # Example
+///
+/// ```
+/// use std::thread;
+/// use rouille::{Request, Response};
+///
+/// fn handle(request: &Request) -> Response {
+///     let status = request.connection_status();
+///     thread::spawn(move || {
+///         for _step in 0..100 {
+///             if !status.is_connected() {
+///                 // Nobody is going to read the result, stop early.
+///                 return;
+///             }
+///             // ... expensive work, whose result is streamed in the body of the response ...
+///         }
+///     });
+///     Response::empty_204()
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConnectionStatus {
+    connected: Arc<AtomicBool>,
+}
+
+impl ConnectionStatus {
+    /// Returns `false` if the server noticed that the client has disconnected.
+    #[inline]
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+}
+
+// Maximum number of bytes of a chunked body that the server reads and discards after the
+// handler returns.
+const MAX_DISCARDED_BODY: u64 = 16 * 1024;
+
+/// Returns the names of the headers listed in the `Connection` headers among `headers`.
+///
+/// These headers only concern the current connection and must not be forwarded.
+fn connection_options<'a, I>(headers: I) -> Vec<String>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    headers
+        .into_iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Connection"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// If the path of the raw URL `url` starts with `prefix` once percent-decoded, returns the length
+/// of that prefix in `url`.
+///
+/// `%2F` is decoded to a `/` that doesn't match a `/` in `prefix`.
+fn raw_prefix_len(url: &str, prefix: &str) -> Option<usize> {
+    fn hex(c: u8) -> Option<u8> {
+        (c as char).to_digit(16).map(|d| d as u8)
+    }
+
+    let url = url.as_bytes();
+    let mut pos = 0;
+
+    for &expected in prefix.as_bytes() {
+        let (byte, len) = match url.get(pos) {
+            None | Some(b'?') => return None,
+            Some(b'%') => match (url.get(pos + 1), url.get(pos + 2)) {
+                (Some(&a), Some(&b)) => match (hex(a), hex(b)) {
+                    (Some(a), Some(b)) if a * 16 + b == b'/' => return None,
+                    (Some(a), Some(b)) => (a * 16 + b, 3),
+                    _ => (b'%', 1),
+                },
+                _ => (b'%', 1),
+            },
+            Some(&byte) => (byte, 1),
+        };
+
+        if byte != expected {
+            return None;
+        }
+        pos += len;
+    }
+
+    Some(pos)
 }
 
 /// Iterator to the list of headers in a request.
@@ -1055,24 +2225,226 @@ impl<'a> Iterator for HeadersIter<'a> {
 
 impl<'a> ExactSizeIterator for HeadersIter<'a> {}
 
-/// Gives access to the body of a request.
-///
-/// In order to obtain this object, call `request.data()`.
-pub struct RequestBody<'a> {
-    body: Box<dyn Read + Send>,
-    marker: PhantomData<&'a ()>,
+/// Iterator to the parameters of the query string. Returned by `Request::query_pairs()`.
+#[derive(Debug, Clone)]
+pub struct QueryPairsIter<'a> {
+    iter: std::str::Split<'a, &'static [char]>,
 }
 
-impl<'a> Read for RequestBody<'a> {
-    #[inline]
-    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
-        self.body.read(buf)
-    }
-}
+impl<'a> Iterator for QueryPairsIter<'a> {
+    type Item = (String, String);
 
-#[cfg(test)]
-mod tests {
-    use Request;
+    fn next(&mut self) -> Option<Self::Item> {
+        fn decode(s: &str) -> String {
+            percent_encoding::percent_decode(s.replace('+', " ").as_bytes())
+                .decode_utf8_lossy()
+                .into_owned()
+        }
+
+        loop {
+            let pair = self.iter.next()?;
+            if pair.is_empty() {
+                continue;
+            }
+
+            let mut split = pair.splitn(2, '=');
+            let name = split.next().unwrap_or("");
+            let value = split.next().unwrap_or("");
+            return Some((decode(name), decode(value)));
+        }
+    }
+}
+
+/// Parsed value of a `Content-Type` header.
+///
+/// In order to obtain this object, call `request.content_type()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    /// The type and subtype, for example `text/html`. Always lowercase.
+    pub mime: String,
+    /// The parameters that follow the MIME type, for example `charset` or `boundary`. The names
+    /// are lowercase and the values are unquoted.
+    pub params: HashMap<String, String>,
+}
+
+impl ContentType {
+    /// Parses the value of a `Content-Type` header, such as `text/html; charset=utf-8`.
+    ///
+    /// Parameters may be quoted, in which case `;` is allowed inside the value and `\` escapes
+    /// the next character. Malformed parameters are ignored.
+    pub fn parse(header: &str) -> ContentType {
+        let mut parts = header.splitn(2, ';');
+        let mime = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let mut rest = parts.next().unwrap_or("");
+
+        let mut params = HashMap::new();
+        while !rest.is_empty() {
+            let (name, after_name) = match rest.find(['=', ';']) {
+                Some(pos) if rest.as_bytes()[pos] == b'=' => (&rest[..pos], &rest[pos + 1..]),
+                Some(pos) => {
+                    rest = &rest[pos + 1..];
+                    continue;
+                }
+                None => break,
+            };
+
+            let after_name = after_name.trim_start();
+            let (value, after_value) = if let Some(quoted) = after_name.strip_prefix('"') {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((pos, c)) = chars.next() {
+                    match c {
+                        '"' => {
+                            end = pos + 1;
+                            break;
+                        }
+                        '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                        c => value.push(c),
+                    }
+                }
+                let after = &quoted[end..];
+                let after = after.find(';').map_or("", |pos| &after[pos + 1..]);
+                (value, after)
+            } else {
+                match after_name.find(';') {
+                    Some(pos) => (after_name[..pos].trim().to_owned(), &after_name[pos + 1..]),
+                    None => (after_name.trim().to_owned(), ""),
+                }
+            };
+
+            let name = name.trim();
+            if !name.is_empty() {
+                params.insert(name.to_ascii_lowercase(), value);
+            }
+            rest = after_value;
+        }
+
+        ContentType { mime, params }
+    }
+
+    /// Returns the value of a parameter. The name is case-insensitive.
+    #[inline]
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(&name.to_ascii_lowercase()).map(|v| &v[..])
+    }
+}
+
+/// Method of a request.
+///
+/// In order to obtain this object, call `request.parsed_method()`. Methods are case-sensitive,
+/// so for example `get` is parsed as `Method::Other("get")`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    /// `GET`
+    Get,
+    /// `POST`
+    Post,
+    /// `PUT`
+    Put,
+    /// `DELETE`
+    Delete,
+    /// `PATCH`
+    Patch,
+    /// `HEAD`
+    Head,
+    /// `OPTIONS`
+    Options,
+    /// Any other method.
+    Other(String),
+}
+
+impl Method {
+    /// Returns the method as it appears in a request, for example `GET`.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        match *self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Head => "HEAD",
+            Method::Options => "OPTIONS",
+            Method::Other(ref method) => method,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Method {
+    fn from(method: &'a str) -> Method {
+        match method {
+            "GET" => Method::Get,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "HEAD" => Method::Head,
+            "OPTIONS" => Method::Options,
+            other => Method::Other(other.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        fmt.write_str(self.as_str())
+    }
+}
+
+/// Gives access to the body of a request.
+///
+/// In order to obtain this object, call `request.data()`.
+pub struct RequestBody<'a> {
+    body: Box<dyn Read + Send>,
+    marker: PhantomData<&'a ()>,
+}
+
+impl<'a> Read for RequestBody<'a> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        self.body.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::IpAddr;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::num::NonZeroUsize;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use websocket;
+    use ContentType;
+    use Method;
+    use Request;
+    use Response;
+    use ResponseBody;
+    use Server;
+
+    #[test]
+    fn with_header() {
+        let request = Request::fake_http("GET", "/", vec![], vec![])
+            .with_header("Host", "example.com")
+            .with_header("X-Forwarded-For", "1.2.3.4")
+            .with_header("X-Forwarded-For", "5.6.7.8");
+
+        assert_eq!(request.header("Host"), Some("example.com"));
+        assert_eq!(
+            request.headers().collect::<Vec<_>>(),
+            vec![
+                ("Host", "example.com"),
+                ("X-Forwarded-For", "1.2.3.4"),
+                ("X-Forwarded-For", "5.6.7.8"),
+            ]
+        );
+    }
 
     #[test]
     fn header() {
@@ -1086,6 +2458,262 @@ mod tests {
         assert_eq!(request.header("host"), Some("localhost"));
     }
 
+    #[test]
+    fn url_raw_segments() {
+        let request = Request::fake_http("GET", "/hello%2Fworld/foo", vec![], vec![]);
+        assert_eq!(request.url(), "/hello/world/foo");
+        assert_eq!(request.url_raw_segments(), vec!["hello/world", "foo"]);
+
+        let request = Request::fake_http("GET", "/hello/world/?a=%2F", vec![], vec![]);
+        assert_eq!(request.url_raw_segments(), vec!["hello", "world", ""]);
+
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        assert_eq!(request.url_raw_segments(), vec![""]);
+    }
+
+    #[test]
+    fn remove_prefix() {
+        let request = Request::fake_http("GET", "/static/foo?a=b", vec![], vec![]);
+        let request = request.remove_prefix("/static").unwrap();
+        assert_eq!(request.raw_url(), "/foo?a=b");
+        assert!(request.remove_prefix("/bar").is_none());
+        assert!(request.remove_prefix("/foo/bar").is_none());
+        assert!(request.remove_prefix("/foo?").is_none());
+    }
+
+    #[test]
+    fn remove_prefix_shares_headers() {
+        let request = Request::fake_http(
+            "GET",
+            "/static/foo",
+            vec![("Host".to_owned(), "localhost".to_owned())],
+            vec![],
+        );
+        let prefixed = request.remove_prefix("/static").unwrap();
+        assert!(Arc::ptr_eq(&request.headers, &prefixed.headers));
+        assert_eq!(prefixed.header("Host"), Some("localhost"));
+    }
+
+    #[test]
+    fn remove_prefix_encoded_space() {
+        let request = Request::fake_http("GET", "/my%20path/foo%20bar", vec![], vec![]);
+        let request = request.remove_prefix("/my path").unwrap();
+        assert_eq!(request.raw_url(), "/foo%20bar");
+        assert_eq!(request.url(), "/foo bar");
+    }
+
+    #[test]
+    fn remove_prefix_unicode() {
+        let request = Request::fake_http("GET", "/caf%C3%A9/menu", vec![], vec![]);
+        let request = request.remove_prefix("/café").unwrap();
+        assert_eq!(request.raw_url(), "/menu");
+
+        let request = Request::fake_http("GET", "/café/menu", vec![], vec![]);
+        assert_eq!(request.remove_prefix("/café").unwrap().raw_url(), "/menu");
+    }
+
+    #[test]
+    fn remove_prefix_encoded_slash() {
+        let request = Request::fake_http("GET", "/a%2Fb/c", vec![], vec![]);
+        assert!(request.remove_prefix("/a/b").is_none());
+        assert!(request.remove_prefix("/a").is_some());
+    }
+
+    #[test]
+    fn content_type_json() {
+        let request = Request::fake_http(
+            "POST",
+            "/",
+            vec![(
+                "Content-Type".to_owned(),
+                "application/json; charset=utf-8".to_owned(),
+            )],
+            vec![],
+        );
+        let content_type = request.content_type().unwrap();
+        assert_eq!(content_type.mime, "application/json");
+        assert_eq!(content_type.param("charset"), Some("utf-8"));
+        assert_eq!(content_type.param("Charset"), Some("utf-8"));
+        assert_eq!(content_type.params.len(), 1);
+    }
+
+    #[test]
+    fn elapsed() {
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let first = request.elapsed();
+        thread::sleep(Duration::from_millis(10));
+        let second = request.elapsed();
+        assert!(second >= first);
+        assert!(second >= Duration::from_millis(10));
+
+        // Requests created by `remove_prefix` keep the original time.
+        let request = Request::fake_http("GET", "/foo/bar", vec![], vec![]);
+        let sub = request.remove_prefix("/foo").unwrap();
+        assert_eq!(sub.received_at(), request.received_at());
+    }
+
+    #[test]
+    fn parsed_method() {
+        let request = Request::fake_http("PATCH", "/", vec![], vec![]);
+        assert_eq!(request.parsed_method(), Method::Patch);
+        assert_eq!(request.parsed_method().as_str(), "PATCH");
+
+        let request = Request::fake_http("OPTIONS", "/", vec![], vec![]);
+        assert_eq!(request.parsed_method(), Method::Options);
+    }
+
+    #[test]
+    fn parsed_method_unknown() {
+        let request = Request::fake_http("PROPFIND", "/", vec![], vec![]);
+        assert_eq!(
+            request.parsed_method(),
+            Method::Other("PROPFIND".to_owned())
+        );
+        assert_eq!(request.parsed_method().to_string(), "PROPFIND");
+
+        // Methods are case-sensitive.
+        let request = Request::fake_http("get", "/", vec![], vec![]);
+        assert_eq!(request.parsed_method(), Method::Other("get".to_owned()));
+    }
+
+    #[test]
+    fn content_type_multipart() {
+        let request = Request::fake_http(
+            "POST",
+            "/",
+            vec![(
+                "Content-Type".to_owned(),
+                "Multipart/Form-Data; boundary=xyz".to_owned(),
+            )],
+            vec![],
+        );
+        let content_type = request.content_type().unwrap();
+        assert_eq!(content_type.mime, "multipart/form-data");
+        assert_eq!(content_type.param("boundary"), Some("xyz"));
+    }
+
+    #[test]
+    fn content_type_quoted() {
+        let content_type = ContentType::parse(r#"multipart/mixed; boundary="a;b\"c" ; foo=bar"#);
+        assert_eq!(content_type.param("boundary"), Some(r#"a;b"c"#));
+        assert_eq!(content_type.param("foo"), Some("bar"));
+    }
+
+    #[test]
+    fn content_type_missing() {
+        let request = Request::fake_http("POST", "/", vec![], vec![]);
+        assert!(request.content_type().is_none());
+
+        let content_type = ContentType::parse("text/plain;;invalid; a=b");
+        assert_eq!(content_type.mime, "text/plain");
+        assert_eq!(content_type.param("a"), Some("b"));
+        assert_eq!(content_type.params.len(), 1);
+    }
+
+    #[test]
+    fn content_length() {
+        let request = Request::fake_http(
+            "POST",
+            "/",
+            vec![("Content-Length".to_owned(), "5".to_owned())],
+            b"hello".to_vec(),
+        );
+        assert_eq!(request.content_length(), Some(5));
+
+        let request = Request::fake_http("POST", "/", vec![], b"hello".to_vec());
+        assert_eq!(request.content_length(), None);
+
+        let request = Request::fake_http(
+            "POST",
+            "/",
+            vec![("Content-Length".to_owned(), "five".to_owned())],
+            b"hello".to_vec(),
+        );
+        assert_eq!(request.content_length(), None);
+    }
+
+    #[test]
+    fn content_length_chunked() {
+        let request = Request::fake_http(
+            "POST",
+            "/",
+            vec![
+                ("Transfer-Encoding".to_owned(), "chunked".to_owned()),
+                ("Content-Length".to_owned(), "5".to_owned()),
+            ],
+            b"hello".to_vec(),
+        );
+        assert_eq!(request.content_length(), None);
+    }
+
+    #[test]
+    fn data_to_bytes() {
+        let request = Request::fake_http("POST", "/", vec![], b"hello".to_vec());
+        assert_eq!(request.data_to_bytes(5).unwrap().unwrap(), b"hello");
+        assert!(request.data_to_bytes(5).is_none());
+    }
+
+    #[test]
+    fn data_to_bytes_limit_exceeded() {
+        let request = Request::fake_http("POST", "/", vec![], b"hello world".to_vec());
+        let err = request.data_to_bytes(5).unwrap().unwrap_err();
+        assert_eq!(err.kind(), ::std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn data_to_string() {
+        let request = Request::fake_http("POST", "/", vec![], b"hello".to_vec());
+        assert_eq!(request.data_to_string(1024).unwrap().unwrap(), "hello");
+
+        let request = Request::fake_http("POST", "/", vec![], vec![0xff, 0xfe]);
+        assert!(request.data_to_string(1024).unwrap().is_err());
+    }
+
+    #[test]
+    fn host() {
+        let host = |value: &str| {
+            Request::fake_http("GET", "/", vec![], vec![])
+                .with_header("Host", value)
+                .host()
+        };
+
+        assert_eq!(host("example.com"), Some(("example.com".to_owned(), None)));
+        assert_eq!(
+            host("example.com:8443"),
+            Some(("example.com".to_owned(), Some(8443)))
+        );
+        assert_eq!(host("[::1]:80"), Some(("::1".to_owned(), Some(80))));
+        assert_eq!(host("[::1]"), Some(("::1".to_owned(), None)));
+        assert_eq!(host("EXAMPLE.com:"), Some(("example.com".to_owned(), None)));
+        assert_eq!(
+            host("127.0.0.1:8000"),
+            Some(("127.0.0.1".to_owned(), Some(8000)))
+        );
+    }
+
+    #[test]
+    fn host_invalid() {
+        assert_eq!(Request::fake_http("GET", "/", vec![], vec![]).host(), None);
+
+        for value in &[
+            "",
+            ":80",
+            "evil.com/x",
+            "user@evil.com",
+            "[::1",
+            "[::1]x",
+            "[]:80",
+            "example.com:http",
+            "example.com:+80",
+            "example.com:99999",
+            "[::1]:80:80",
+        ] {
+            let request =
+                Request::fake_http("GET", "/", vec![], vec![]).with_header("Host", *value);
+            assert_eq!(request.host(), None, "{:?}", value);
+        }
+    }
+
     #[test]
     fn get_param() {
         let request = Request::fake_http("GET", "/?p=hello", vec![], vec![]);
@@ -1139,6 +2767,59 @@ mod tests {
         assert_eq!(request.get_param("flag"), Some("".to_owned()));
     }
 
+    #[test]
+    fn get_param_value_with_equals() {
+        let request = Request::fake_http("GET", "/?p=a=b", vec![], vec![]);
+        assert_eq!(request.get_param("p"), Some("a=b".to_owned()));
+    }
+
+    #[test]
+    fn get_param_encoded_name() {
+        let request = Request::fake_http("GET", "/?my%20param=1", vec![], vec![]);
+        assert_eq!(request.get_param("my param"), Some("1".to_owned()));
+    }
+
+    #[test]
+    fn query_pairs() {
+        let request = Request::fake_http("GET", "/?a=1&b=2&a=3", vec![], vec![]);
+        assert_eq!(
+            request.query_pairs().collect::<Vec<_>>(),
+            vec![
+                ("a".to_owned(), "1".to_owned()),
+                ("b".to_owned(), "2".to_owned()),
+                ("a".to_owned(), "3".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn query_pairs_semicolon() {
+        let request = Request::fake_http("GET", "/?a=1;b=2", vec![], vec![]);
+        assert_eq!(
+            request.query_pairs().collect::<Vec<_>>(),
+            vec![("a".to_owned(), "1;b=2".to_owned())]
+        );
+        assert_eq!(
+            request.query_pairs_with_semicolons().collect::<Vec<_>>(),
+            vec![
+                ("a".to_owned(), "1".to_owned()),
+                ("b".to_owned(), "2".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn query_pairs_empty() {
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        assert_eq!(request.query_pairs().count(), 0);
+
+        let request = Request::fake_http("GET", "/?&&flag&", vec![], vec![]);
+        assert_eq!(
+            request.query_pairs().collect::<Vec<_>>(),
+            vec![("flag".to_owned(), "".to_owned())]
+        );
+    }
+
     #[test]
     fn body_twice() {
         let request = Request::fake_http("GET", "/", vec![], vec![62, 62, 62]);
@@ -1182,6 +2863,939 @@ mod tests {
         assert_eq!(request.url(), "/hello+world");
     }
 
+    #[test]
+    fn expect_100_continue() {
+        let server = serve(|request| {
+            let mut body = Vec::new();
+            request.data().unwrap().read_to_end(&mut body).unwrap();
+            Response::text(format!("{}", body.len()))
+        });
+
+        let (mut stream, mut reader) = server.connect();
+        stream
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2048\r\n\
+                  Expect: 100-continue\r\n\r\n",
+            )
+            .unwrap();
+
+        // The server must acknowledge before the client sends the body.
+        let head = read_head(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 100"), "{:?}", head);
+
+        stream.write_all(&[b'a'; 2048]).unwrap();
+        let (status, body) = read_response(&mut reader);
+        assert!(status.starts_with("HTTP/1.1 200"), "{:?}", status);
+        assert_eq!(body, "2048");
+    }
+
+    #[test]
+    fn expect_unknown() {
+        let server = serve(|_| -> Response { panic!("the handler shouldn't be called") });
+
+        // tiny-http answers unknown expectations itself and closes the connection.
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\
+                  Expect: something-else\r\n\r\ntest",
+            )
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 417"), "{:?}", response);
+    }
+
+    #[test]
+    fn default_pool_size() {
+        assert_eq!(super::default_pool_size(NonZeroUsize::new(1)), 8);
+        assert_eq!(super::default_pool_size(NonZeroUsize::new(4)), 32);
+        assert_eq!(super::default_pool_size(None), 8);
+    }
+
+    #[test]
+    fn pool_size_one() {
+        let server = Server::new("localhost:0", |request| Response::text(request.url()))
+            .unwrap()
+            .pool_size(1);
+        let server = TestServer::new(server);
+
+        let mut streams = (0..3)
+            .map(|n| {
+                let mut stream = TcpStream::connect(server.addr()).unwrap();
+                write!(stream, "GET /{} HTTP/1.0\r\n\r\n", n).unwrap();
+                stream
+            })
+            .collect::<Vec<_>>();
+
+        for (n, stream) in streams.iter_mut().enumerate() {
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.0 200"), "{:?}", response);
+            assert!(response.ends_with(&format!("/{}", n)), "{:?}", response);
+        }
+    }
+
+    // Server running in the background, stopped when this is dropped.
+    pub(crate) struct TestServer {
+        addr: Option<SocketAddr>,
+        stop: Option<(thread::JoinHandle<()>, mpsc::Sender<()>)>,
+    }
+
+    impl TestServer {
+        pub(crate) fn new<F>(server: Server<F>) -> TestServer
+        where
+            F: Send + Sync + 'static + Fn(&Request) -> Response,
+        {
+            TestServer {
+                addr: server.server.server_addr().to_ip(),
+                stop: Some(server.stoppable()),
+            }
+        }
+
+        pub(crate) fn addr(&self) -> SocketAddr {
+            self.addr.expect("Unexpected Unix socket listener")
+        }
+
+        // Opens a connection to the server. Reads time out after 5 seconds, so that a missing
+        // response fails the test instead of blocking it.
+        pub(crate) fn connect(&self) -> (TcpStream, BufReader<TcpStream>) {
+            let stream = TcpStream::connect(self.addr()).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            (stream, reader)
+        }
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            let (handle, stop) = self.stop.take().unwrap();
+            let _ = stop.send(());
+            if !thread::panicking() {
+                handle.join().unwrap();
+            }
+        }
+    }
+
+    pub(crate) fn serve<F>(handler: F) -> TestServer
+    where
+        F: Send + Sync + 'static + Fn(&Request) -> Response,
+    {
+        TestServer::new(Server::new("localhost:0", handler).unwrap())
+    }
+
+    // Reads the status line and the headers of a response, up to the empty line.
+    pub(crate) fn read_head<R: BufRead>(reader: &mut R) -> String {
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                return head;
+            }
+            head.push_str(&line);
+        }
+    }
+
+    // Reads a response with a `Content-Length`, and returns its status line and its body.
+    pub(crate) fn read_response<R: BufRead>(reader: &mut R) -> (String, String) {
+        let head = read_head(reader);
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .map_or(0, |value| value.trim().parse().unwrap());
+
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        let status = head.lines().next().unwrap_or("").to_owned();
+        (status, String::from_utf8(body).unwrap())
+    }
+
+    // Response body that never ends. Each read returns at most 1 kiB after waiting for `delay`.
+    #[derive(Clone, Default)]
+    struct Endless {
+        delay: Duration,
+        reads: Arc<AtomicUsize>,
+        bytes: Arc<AtomicUsize>,
+        dropped: Arc<AtomicUsize>,
+    }
+
+    impl Endless {
+        fn new(delay: Duration) -> Endless {
+            let mut endless = Endless::default();
+            endless.delay = delay;
+            endless
+        }
+    }
+
+    impl Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            thread::sleep(self.delay);
+            let len = buf.len().min(1024);
+            for b in &mut buf[..len] {
+                *b = b'a';
+            }
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            self.bytes.fetch_add(len, Ordering::SeqCst);
+            Ok(len)
+        }
+    }
+
+    impl Drop for Endless {
+        fn drop(&mut self) {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn try_or_500() {
+        fn handle(result: Result<u32, ()>) -> Response {
+            let value = try_or_500!(result);
+            Response::text(value.to_string())
+        }
+
+        assert_eq!(handle(Ok(5)).status_code, 200);
+        assert_eq!(handle(Err(())).status_code, 500);
+
+        let mut logged = None;
+        let response = (|| {
+            try_or_500!(Err::<(), _>("db is down"), |err| logged = Some(err));
+            Response::empty_204()
+        })();
+        assert_eq!(response.status_code, 500);
+        assert_eq!(logged, Some("db is down"));
+    }
+
+    #[test]
+    fn try_or_json() {
+        fn handle(result: Result<u32, &'static str>, not_found: bool) -> Response {
+            let value = if not_found {
+                try_or_404_json!(result)
+            } else {
+                try_or_500_json!(result)
+            };
+            Response::text(value.to_string())
+        }
+
+        assert_eq!(handle(Ok(5), true).status_code, 200);
+        assert_eq!(handle(Ok(5), false).status_code, 200);
+
+        let response = handle(Err("secret"), true);
+        assert_eq!(response.status_code, 404);
+        let (mut body, _) = response.data.into_reader_and_size();
+        let mut content = String::new();
+        body.read_to_string(&mut content).unwrap();
+        assert_eq!(content, r#"{"error":"Not Found"}"#);
+
+        let response = handle(Err("secret"), false);
+        assert_eq!(response.status_code, 500);
+        let (mut body, _) = response.data.into_reader_and_size();
+        let mut content = String::new();
+        body.read_to_string(&mut content).unwrap();
+        assert_eq!(content, r#"{"error":"Internal Server Error"}"#);
+    }
+
+    #[test]
+    fn connection_status() {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let server = serve(move |request| {
+            assert!(request.is_connected());
+            tx.lock()
+                .unwrap()
+                .send(request.connection_status())
+                .unwrap();
+            Response {
+                status_code: 200,
+                headers: vec![],
+                data: ResponseBody::from_reader(Endless::new(Duration::from_millis(1))),
+                upgrade: None,
+            }
+        });
+
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 4096];
+        stream.read_exact(&mut buf).unwrap();
+
+        let status = rx.recv().unwrap();
+        assert!(status.is_connected());
+        drop(stream);
+
+        for _ in 0..500 {
+            if !status.is_connected() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!status.is_connected());
+    }
+
+    #[test]
+    fn body_dropped_on_disconnect() {
+        let endless = Endless::new(Duration::from_millis(1));
+        let (reads, dropped) = (endless.reads.clone(), endless.dropped.clone());
+        let server = serve(move |_| Response {
+            status_code: 200,
+            headers: vec![],
+            data: ResponseBody::from_reader(endless.clone()),
+            upgrade: None,
+        });
+
+        // The client goes away after the first chunk of the body.
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 1024];
+        stream.read_exact(&mut buf).unwrap();
+        drop(stream);
+
+        // Once writing to the client fails, the body must stop being read and be dropped.
+        for _ in 0..500 {
+            if dropped.load(Ordering::SeqCst) != 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+        // A few reads may have happened before the failure was noticed, but not many more.
+        assert!(reads.load(Ordering::SeqCst) < 1000);
+    }
+
+    #[test]
+    fn slow_client_backpressure() {
+        let endless = Endless::default();
+        let produced = endless.bytes.clone();
+        let server = serve(move |_| {
+            Response::from_parts(200, vec![], ResponseBody::from_reader(endless.clone()))
+        });
+
+        // The client never reads the body.
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+        // Once the socket buffers are full, the server stops reading from the body.
+        let mut previous = 0;
+        for _ in 0..100 {
+            thread::sleep(Duration::from_millis(50));
+            let now = produced.load(Ordering::SeqCst);
+            if now != 0 && now == previous {
+                break;
+            }
+            previous = now;
+        }
+        let now = produced.load(Ordering::SeqCst);
+        assert_eq!(now, previous);
+        assert!(now < 256 * 1024 * 1024, "{}", now);
+
+        drop(stream);
+    }
+
+    #[test]
+    fn fake_request_connected() {
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        assert!(request.is_connected());
+        assert!(request.connection_status().is_connected());
+    }
+
+    #[test]
+    fn remote_addr_forwarded_untrusted_peer() {
+        let request = Request::fake_http_from(
+            "203.0.113.5:1234".parse().unwrap(),
+            "GET",
+            "/",
+            vec![("X-Forwarded-For".to_owned(), "198.51.100.1".to_owned())],
+            vec![],
+        );
+
+        let proxy = "10.0.0.1".parse().unwrap();
+        assert_eq!(
+            request.remote_addr_forwarded(&[proxy]),
+            "203.0.113.5".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn remote_addr_forwarded_trusted_peer() {
+        let proxies = ["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+        let request = |forwarded: &str| {
+            Request::fake_http_from(
+                "10.0.0.1:1234".parse().unwrap(),
+                "GET",
+                "/",
+                vec![("X-Forwarded-For".to_owned(), forwarded.to_owned())],
+                vec![],
+            )
+        };
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        // The client pretends to be 1.1.1.1, but the last untrusted hop is what we return.
+        let r = request("1.1.1.1, 198.51.100.1, 10.0.0.2");
+        assert_eq!(r.remote_addr_forwarded(&proxies), ip("198.51.100.1"));
+
+        let r = request("[2001:db8::1]:4711");
+        assert_eq!(r.remote_addr_forwarded(&proxies), ip("2001:db8::1"));
+
+        let r = request("garbage, 10.0.0.2");
+        assert_eq!(r.remote_addr_forwarded(&proxies), ip("10.0.0.2"));
+
+        let r = request("");
+        assert_eq!(r.remote_addr_forwarded(&proxies), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn panic_doesnt_break_next_request() {
+        // With a single worker, the next request is processed by the thread where the handler
+        // panicked.
+        let server = Server::new("localhost:0", |request| {
+            if request.url() == "/panic" {
+                panic!("oops");
+            }
+            Response::text("ok")
+        })
+        .unwrap()
+        .pool_size(1);
+        let server = TestServer::new(server);
+
+        for (url, expected) in &[
+            ("/panic", "500"),
+            ("/", "200"),
+            ("/panic", "500"),
+            ("/", "200"),
+        ] {
+            let (mut stream, mut reader) = server.connect();
+            write!(stream, "GET {} HTTP/1.1\r\n\r\n", url).unwrap();
+            let (status, _) = read_response(&mut reader);
+            assert!(
+                status.starts_with(&format!("HTTP/1.1 {}", expected)),
+                "{:?}",
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn handler_timeout() {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let fast_handled = Arc::new(AtomicUsize::new(0));
+        let fast_handled2 = fast_handled.clone();
+        let server = Server::new("localhost:0", move |request| {
+            if request.url() == "/slow" {
+                thread::sleep(Duration::from_millis(500));
+                tx.lock().unwrap().send(request.is_connected()).unwrap();
+                return Response::text("too late");
+            }
+            fast_handled2.fetch_add(1, Ordering::SeqCst);
+            Response::text("fast")
+        })
+        .unwrap()
+        .with_handler_timeout(Duration::from_millis(100));
+        let server = TestServer::new(server);
+
+        let (mut stream, mut reader) = server.connect();
+
+        let start = Instant::now();
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let head = read_head(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 503"), "{:?}", head);
+        assert!(head.contains("\r\nConnection: close\r\n"), "{:?}", head);
+        assert!(start.elapsed() < Duration::from_millis(400));
+
+        // The server closes the connection, and requests sent anyway on it aren't handled.
+        let _ = stream.write_all(b"GET /fast HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let mut rest = Vec::new();
+        if let Err(err) = reader.read_to_end(&mut rest) {
+            assert_eq!(err.kind(), ::std::io::ErrorKind::ConnectionReset);
+        }
+        assert!(rest.is_empty(), "{:?}", String::from_utf8_lossy(&rest));
+
+        assert!(!rx.recv().unwrap());
+        assert_eq!(fast_handled.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn panic_handler() {
+        let server = Server::new("localhost:0", |request| {
+            if request.url() == "/panic" {
+                panic!("oops");
+            }
+            Response::text("ok")
+        })
+        .unwrap()
+        .with_panic_handler(|request, payload| {
+            let message = payload.downcast_ref::<&str>().unwrap();
+            Response::text(format!("{} failed: {}", request.url(), message)).with_status_code(500)
+        });
+        let server = TestServer::new(server);
+
+        let (mut stream, mut reader) = server.connect();
+        stream
+            .write_all(b"GET /panic HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n")
+            .unwrap();
+
+        let (status, body) = read_response(&mut reader);
+        assert!(status.starts_with("HTTP/1.1 500"), "{:?}", status);
+        assert_eq!(body, "/panic failed: oops");
+        let (status, body) = read_response(&mut reader);
+        assert!(status.starts_with("HTTP/1.1 200"), "{:?}", status);
+        assert_eq!(body, "ok");
+    }
+
+    #[test]
+    fn panic_handler_panics() {
+        let server = Server::new("localhost:0", |_| panic!("oops"))
+            .unwrap()
+            .with_panic_handler(|_, _| panic!("oops again"));
+        let server = TestServer::new(server);
+
+        let (mut stream, mut reader) = server.connect();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+        let (status, body) = read_response(&mut reader);
+        assert!(status.starts_with("HTTP/1.1 500"), "{:?}", status);
+        assert!(body.contains("Internal Server Error"), "{:?}", body);
+    }
+
+    #[test]
+    fn duplicate_headers() {
+        let server = serve(|request| {
+            let values = request
+                .headers()
+                .filter(|&(k, _)| k.eq_ignore_ascii_case("x-cookie"))
+                .map(|(_, v)| v)
+                .collect::<Vec<_>>();
+            Response::text(values.join("|"))
+        });
+
+        let (mut stream, mut reader) = server.connect();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nX-Cookie: a=1\r\nx-cookie: b=2\r\n\r\n")
+            .unwrap();
+
+        let (status, body) = read_response(&mut reader);
+        assert!(status.starts_with("HTTP/1.1 200"), "{:?}", status);
+        assert_eq!(body, "a=1|b=2");
+    }
+
+    #[test]
+    fn header_value_whitespace() {
+        let server =
+            serve(|request| Response::text(format!("[{}]", request.header("X-Value").unwrap())));
+
+        let (mut stream, mut reader) = server.connect();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nX-Value: \t hello world  \r\n\r\n")
+            .unwrap();
+
+        let (status, body) = read_response(&mut reader);
+        assert!(status.starts_with("HTTP/1.1 200"), "{:?}", status);
+        assert_eq!(body, "[hello world]");
+    }
+
+    #[test]
+    fn header_obs_fold_rejected() {
+        let server = serve(|_| Response::text("hello"));
+
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nX-Value: hello\r\n world\r\n\r\n")
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400"), "{:?}", response);
+    }
+
+    #[test]
+    fn from_listener() {
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::from_listener(listener, |_| Response::text("hello")).unwrap();
+        assert_eq!(server.server_addr(), addr);
+        let server = TestServer::new(server);
+
+        let (mut stream, mut reader) = server.connect();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let (status, body) = read_response(&mut reader);
+        assert!(status.starts_with("HTTP/1.1 200"), "{:?}", status);
+        assert_eq!(body, "hello");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn unix_socket() {
+        use std::os::unix::net::UnixStream;
+
+        let path =
+            ::std::env::temp_dir().join(format!("rouille-test-{}.sock", ::std::process::id()));
+        let _ = ::std::fs::remove_file(&path);
+
+        let server = Server::new_unix(&path, |request| {
+            Response::text(request.remote_addr().to_string())
+        })
+        .unwrap();
+        let server = TestServer::new(server);
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{:?}", response);
+        assert!(response.ends_with("\r\n\r\n0.0.0.0:0"), "{:?}", response);
+
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn single_threaded() {
+        let main_thread = thread::current().id();
+        let server = Server::new("localhost:0", move |request| {
+            assert_eq!(thread::current().id(), main_thread);
+            Response::text(request.url())
+        })
+        .unwrap()
+        .single_threaded();
+
+        let mut stream = TcpStream::connect(server.server_addr()).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        for url in &["/a", "/b"] {
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", url).unwrap();
+            server.poll_timeout(Duration::from_millis(100));
+
+            // The response must already be available.
+            stream.set_nonblocking(true).unwrap();
+            let (status, body) = read_response(&mut reader);
+            stream.set_nonblocking(false).unwrap();
+            assert!(status.starts_with("HTTP/1.1 200"), "{:?}", status);
+            assert_eq!(&body, url);
+        }
+    }
+
+    #[test]
+    fn pipelined_requests() {
+        let server = serve(|request| {
+            // Making the first request slower so that the responses would be swapped if they
+            // were written as soon as the handlers return.
+            if request.url() == "/a" {
+                thread::sleep(Duration::from_millis(100));
+            }
+            Response::text(request.url())
+        });
+
+        let (mut stream, mut reader) = server.connect();
+        stream
+            .write_all(
+                b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                  GET /b HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            )
+            .unwrap();
+
+        for url in &["/a", "/b"] {
+            let (status, body) = read_response(&mut reader);
+            assert!(status.starts_with("HTTP/1.1 200"), "{} {:?}", url, status);
+            assert_eq!(&body, url);
+        }
+    }
+
+    #[test]
+    fn connection_options_not_sent() {
+        let server = serve(|_| {
+            Response::text("hello")
+                .with_additional_header("Connection", "close, X-Custom")
+                .with_additional_header("x-custom", "secret")
+                .with_additional_header("X-Other", "ok")
+        });
+
+        let (mut stream, mut reader) = server.connect();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let head = read_head(&mut reader);
+        assert!(!head.to_lowercase().contains("x-custom"), "{:?}", head);
+        assert!(head.contains("X-Other: ok\r\n"), "{:?}", head);
+    }
+
+    #[test]
+    fn content_length_and_transfer_encoding() {
+        let called = Arc::new(AtomicUsize::new(0));
+        let called2 = called.clone();
+        let server = serve(move |_| {
+            called2.fetch_add(1, Ordering::SeqCst);
+            Response::text("hello")
+        });
+
+        let (stream, reader) = server.connect();
+        assert_smuggled_request_rejected(stream, reader);
+        assert_eq!(called.load(Ordering::SeqCst), 0);
+
+        // Requests with only one of the two headers are fine.
+        let (mut stream, mut reader) = server.connect();
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\ntest")
+            .unwrap();
+        let (status, body) = read_response(&mut reader);
+        assert!(status.starts_with("HTTP/1.1 200"), "{:?}", status);
+        assert_eq!(body, "hello");
+        assert_eq!(called.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn content_length_and_transfer_encoding_unix_socket() {
+        use std::os::unix::net::UnixStream;
+
+        // Connections of Unix sockets don't have an address, and must be closed all the same.
+        let path = ::std::env::temp_dir().join(format!(
+            "rouille-test-smuggling-{}.sock",
+            ::std::process::id()
+        ));
+        let _ = ::std::fs::remove_file(&path);
+        let called = Arc::new(AtomicUsize::new(0));
+        let called2 = called.clone();
+        let server = Server::new_unix(&path, move |_| {
+            called2.fetch_add(1, Ordering::SeqCst);
+            Response::text("hello")
+        })
+        .unwrap();
+        let _server = TestServer::new(server);
+
+        let stream = UnixStream::connect(&path).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        assert_smuggled_request_rejected(stream, reader);
+        assert_eq!(called.load(Ordering::SeqCst), 0);
+    }
+
+    // Sends a request with both a `Content-Length` and a `Transfer-Encoding`, followed by a
+    // request smuggled in its body, which must never be answered.
+    fn assert_smuggled_request_rejected<S: Write, R: BufRead>(mut stream: S, mut reader: R) {
+        stream
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\
+                  Transfer-Encoding: chunked\r\n\r\n0\r\n\r\n\
+                  GET /smuggled HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            )
+            .unwrap();
+        let head = read_head(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 400"), "{:?}", head);
+        assert!(head.contains("\r\nConnection: close\r\n"), "{:?}", head);
+
+        // The connection is closed after the body of the response, possibly with a reset since
+        // the body of the request wasn't read.
+        let mut rest = Vec::new();
+        if let Err(err) = reader.read_to_end(&mut rest) {
+            assert_eq!(err.kind(), ::std::io::ErrorKind::ConnectionReset);
+        }
+        let rest = String::from_utf8_lossy(&rest);
+        assert!(!rest.contains("HTTP/1.1"), "{:?}", rest);
+    }
+
+    #[test]
+    fn response_reader_and_size() {
+        let server = serve(|request| {
+            let data = ::std::io::repeat(b'a').take(10);
+            let data = if request.url() == "/sized" {
+                ResponseBody::from_reader_and_size(data, 10)
+            } else {
+                ResponseBody::from_reader(data)
+            };
+            Response::from_parts(200, vec![], data)
+        });
+
+        let head = |url: &str| {
+            let (mut stream, mut reader) = server.connect();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", url).unwrap();
+            read_head(&mut reader)
+        };
+
+        let sized = head("/sized");
+        assert!(sized.contains("Content-Length: 10\r\n"), "{:?}", sized);
+        let unsized_ = head("/unsized");
+        assert!(!unsized_.contains("Content-Length"), "{:?}", unsized_);
+        assert!(
+            unsized_.contains("Transfer-Encoding: chunked\r\n"),
+            "{:?}",
+            unsized_
+        );
+    }
+
+    #[test]
+    fn small_responses_not_delayed() {
+        let server = serve(|_| Response::text("ok"));
+
+        let (mut stream, mut reader) = server.connect();
+        stream.set_nodelay(true).unwrap();
+
+        // If the response were written in several small segments, Nagle's algorithm combined
+        // with delayed ACKs would add around 40ms to each request.
+        let start = Instant::now();
+        for _ in 0..50 {
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let (status, body) = read_response(&mut reader);
+            assert!(status.starts_with("HTTP/1.1 200"), "{:?}", status);
+            assert_eq!(body, "ok");
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn unread_body_keep_alive() {
+        let server = serve(|request| Response::text(request.url()));
+
+        let (mut stream, mut reader) = server.connect();
+        stream
+            .write_all(
+                b"POST /a HTTP/1.1\r\nHost: localhost\r\nContent-Length: 11\r\n\r\nhello world\
+                  POST /b HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n\
+                  GET /c HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            )
+            .unwrap();
+
+        for url in &["/a", "/b", "/c"] {
+            let (status, body) = read_response(&mut reader);
+            assert!(status.starts_with("HTTP/1.1 200"), "{} {:?}", url, status);
+            assert_eq!(&body, url);
+        }
+    }
+
+    #[test]
+    fn unread_large_chunked_body() {
+        let handled = Arc::new(AtomicUsize::new(0));
+        let handled2 = handled.clone();
+        let server = serve(move |request| {
+            handled2.fetch_add(1, Ordering::SeqCst);
+            Response::text(request.url())
+        });
+
+        // The body is too large to be discarded, so the server can't find the next request.
+        let (mut stream, mut reader) = server.connect();
+        write!(
+            stream,
+            "POST /a HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+             {:x}\r\n{}\r\n0\r\n\r\n\
+             GET /b HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            20_000,
+            "a".repeat(20_000)
+        )
+        .unwrap();
+
+        let head = read_head(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 200"), "{:?}", head);
+        assert!(head.contains("\r\nConnection: close\r\n"), "{:?}", head);
+        let mut body = [0; 2];
+        reader.read_exact(&mut body).unwrap();
+        assert_eq!(&body, b"/a");
+
+        // The connection is closed, possibly with a reset since the body wasn't read entirely.
+        let mut rest = Vec::new();
+        if let Err(err) = reader.read_to_end(&mut rest) {
+            assert_eq!(err.kind(), ::std::io::ErrorKind::ConnectionReset);
+        }
+        assert!(rest.is_empty(), "{:?}", String::from_utf8_lossy(&rest));
+        assert_eq!(handled.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn websocket_handshake() {
+        let server = serve(|request| {
+            let (response, _websocket) = websocket::start(request, None::<&str>).unwrap();
+            response
+        });
+
+        let (mut stream, mut reader) = server.connect();
+        stream
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\n\
+                  Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            )
+            .unwrap();
+
+        let head = read_head(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 101"), "{:?}", head);
+        assert!(
+            head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"),
+            "{:?}",
+            head
+        );
+    }
+
+    #[test]
+    fn chunked_request_body() {
+        let server = serve(|request| {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+            Response::text(body)
+        });
+
+        // The body is decoded by tiny-http before reaching `data()`, including chunk extensions
+        // and the final empty chunk.
+        let (mut stream, mut reader) = server.connect();
+        stream
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  5;name=value\r\nhello\r\nA\r\n, chunked!\r\n0\r\n\r\n\
+                  GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            )
+            .unwrap();
+
+        let (status, body) = read_response(&mut reader);
+        assert!(status.starts_with("HTTP/1.1 200"), "{:?}", status);
+        assert_eq!(body, "hello, chunked!");
+
+        // The connection is still usable after the terminating chunk.
+        let (status, body) = read_response(&mut reader);
+        assert!(status.starts_with("HTTP/1.1 200"), "{:?}", status);
+        assert_eq!(body, "");
+    }
+
+    #[test]
+    fn http10_connection_close() {
+        let server = serve(|_| Response::text("hello"));
+
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+
+        // The server must close the connection after the response.
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200"), "{:?}", response);
+        assert!(response.ends_with("hello"), "{:?}", response);
+    }
+
+    #[test]
+    fn http10_keep_alive() {
+        let server = serve(|_| Response::text("hello"));
+
+        let (mut stream, mut reader) = server.connect();
+        for _ in 0..2 {
+            stream
+                .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+                .unwrap();
+
+            let head = read_head(&mut reader);
+            assert!(head.starts_with("HTTP/1.0 200"), "{:?}", head);
+
+            let mut body = [0; 5];
+            reader.read_exact(&mut body).unwrap();
+            assert_eq!(&body, b"hello");
+        }
+    }
+
     #[test]
     fn dnt() {
         let request =
@@ -7,13 +7,19 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
+use std::io::Read;
+use std::io::Result as IoResult;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::io::Write;
+use std::net::SocketAddr;
 use std::panic;
 use std::time::Duration;
 use std::time::Instant;
 
 use chrono;
 
+use response::ResponseBodyData;
 use Request;
 use Response;
 
@@ -21,7 +27,12 @@ use Response;
 ///
 /// Writes a line to the given "writer" after processing each request.
 /// Log line info has the format:
-/// `"{%Y-%m-%d %H:%M%S%.6f} UTC - {METHOD} {URL} - {ELAPSED_TIME} - {RESP_SATUS}"`
+/// `"{%Y-%m-%d %H:%M%S%.6f} UTC - {METHOD} {URL} - {ELAPSED_TIME} - {RESP_SATUS} - {BYTES}"`
+///
+/// `{ELAPSED_TIME}` is the time taken by the handler, and `{BYTES}` is the number of bytes of the
+/// body of the response that have been sent to the client. The line is therefore written once
+/// the body has been sent (or once sending it failed). If the handler panics, the line is written
+/// immediately and ends with `PANIC!` instead.
 ///
 /// If you would like to customize the log output or functionality (such as integrating
 /// with the [`log`](https://docs.rs/log) crate, see [`rouille::log_custom`](fn.log_custom.html))
@@ -40,7 +51,7 @@ use Response;
 /// ```
 pub fn log<W, F>(rq: &Request, mut output: W, f: F) -> Response
 where
-    W: Write,
+    W: Write + Send + 'static,
     F: FnOnce() -> Response,
 {
     let start_instant = Instant::now();
@@ -60,12 +71,13 @@ where
 
     match response {
         Ok(response) => {
-            let _ = writeln!(
-                output,
-                "{} - {} - {}",
-                rq_line, elapsed_time, response.status_code
-            );
-            response
+            let format = move |entry: &LogEntry| {
+                format!(
+                    "{} - {} - {} - {}",
+                    rq_line, elapsed_time, entry.status_code, entry.bytes
+                )
+            };
+            log_after_body(rq, response, output, format)
         }
         Err(payload) => {
             // There is probably no point in printing the payload, as this is done by the panic
@@ -133,6 +145,237 @@ where
     }
 }
 
+/// Adds a log entry to the given writer for each request, using the given format.
+///
+/// Contrary to `log`, the line is written once the body of the response has been sent to the
+/// client (or once sending it failed), so that it can contain the number of bytes sent and the
+/// total time taken by the request.
+///
+/// The format can be one of the presets of `LogFormat`, or a closure that turns a `LogEntry` into
+/// a line. In both cases the line shouldn't contain the final line break.
+///
+/// If the handler panics, nothing is logged.
+///
+/// # Example
+///
+/// ```
+/// use std::io;
+/// use rouille::{LogEntry, LogFormat, Request, Response};
+///
+/// fn handle(request: &Request) -> Response {
+///     rouille::log_with_format(request, io::stdout(), LogFormat::Combined, || {
+///         Response::text("hello world")
+///     })
+/// }
+///
+/// // With a custom format.
+/// fn handle_json(request: &Request) -> Response {
+///     let format = |entry: &LogEntry| {
+///         format!(
+///             r#"{{"url":{:?},"status":{},"bytes":{},"ms":{}}}"#,
+///             entry.url, entry.status_code, entry.bytes, entry.elapsed.as_millis()
+///         )
+///     };
+///
+///     rouille::log_with_format(request, io::stdout(), format, || {
+///         Response::text("hello world")
+///     })
+/// }
+/// ```
+pub fn log_with_format<W, L, F>(rq: &Request, output: W, format: L, f: F) -> Response
+where
+    W: Write + Send + 'static,
+    L: LogFormatter + Send + 'static,
+    F: FnOnce() -> Response,
+{
+    let response = f();
+    log_after_body(rq, response, output, format)
+}
+
+// Wraps the body of `response` so that a line built with `format` is written to `output` once
+// the body has been sent.
+fn log_after_body<W, L>(rq: &Request, mut response: Response, output: W, format: L) -> Response
+where
+    W: Write + Send + 'static,
+    L: LogFormatter + Send + 'static,
+{
+    let entry = LogEntry {
+        time: chrono::Utc::now(),
+        remote_addr: *rq.remote_addr(),
+        method: rq.method().to_owned(),
+        url: rq.raw_url().to_owned(),
+        protocol: rq.protocol(),
+        referer: rq.header("Referer").map(|h| h.to_owned()),
+        user_agent: rq.header("User-Agent").map(|h| h.to_owned()),
+        status_code: response.status_code,
+        bytes: 0,
+        elapsed: Duration::new(0, 0),
+    };
+
+    let received = rq.received_at();
+    response.data = response.data.map_data(move |data| {
+        // Bodies that can seek must still be able to, for example to answer `Range` requests.
+        match data {
+            ResponseBodyData::Reader(inner) => ResponseBodyData::Reader(Box::new(LoggedBody {
+                inner,
+                received,
+                entry,
+                format,
+                output,
+            })),
+            ResponseBodyData::Seekable(inner) => ResponseBodyData::Seekable(Box::new(LoggedBody {
+                inner,
+                received,
+                entry,
+                format,
+                output,
+            })),
+        }
+    });
+    response
+}
+
+/// Information about a request and its response, passed to the formatter of `log_with_format`.
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    /// Time when the handler returned.
+    pub time: chrono::DateTime<chrono::Utc>,
+    /// Address of the client. See `Request::remote_addr`.
+    pub remote_addr: SocketAddr,
+    /// Method of the request.
+    pub method: String,
+    /// Raw URL of the request, including the query string.
+    pub url: String,
+    /// Protocol of the request, for example `HTTP/1.1`.
+    pub protocol: String,
+    /// Value of the `Referer` header of the request.
+    pub referer: Option<String>,
+    /// Value of the `User-Agent` header of the request.
+    pub user_agent: Option<String>,
+    /// Status code of the response.
+    pub status_code: u16,
+    /// Number of bytes of the body of the response that have been sent, excluding the headers.
+    pub bytes: u64,
+    /// Time between the moment the request was received and the end of the response.
+    pub elapsed: Duration,
+}
+
+/// Predefined formats for `log_with_format`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The Common Log Format used by many web servers, for example
+    /// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326`.
+    Common,
+    /// Same as `Common`, followed by the `Referer` and `User-Agent` headers of the request.
+    Combined,
+}
+
+/// Turns a `LogEntry` into a line of log.
+///
+/// Implemented on `LogFormat` and on closures.
+pub trait LogFormatter {
+    /// Builds the line, without the final line break.
+    fn format(&self, entry: &LogEntry) -> String;
+}
+
+impl LogFormatter for LogFormat {
+    fn format(&self, entry: &LogEntry) -> String {
+        let bytes = if entry.bytes == 0 {
+            "-".to_owned()
+        } else {
+            entry.bytes.to_string()
+        };
+
+        let mut line = format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            entry.remote_addr.ip(),
+            entry.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            escape(&entry.method),
+            escape(&entry.url),
+            entry.protocol,
+            entry.status_code,
+            bytes
+        );
+
+        if *self == LogFormat::Combined {
+            line.push_str(&format!(
+                " \"{}\" \"{}\"",
+                escape(entry.referer.as_deref().unwrap_or("-")),
+                escape(entry.user_agent.as_deref().unwrap_or("-"))
+            ));
+        }
+
+        line
+    }
+}
+
+impl<F> LogFormatter for F
+where
+    F: Fn(&LogEntry) -> String,
+{
+    #[inline]
+    fn format(&self, entry: &LogEntry) -> String {
+        self(entry)
+    }
+}
+
+// Escapes the values that are put between quotes in the log formats.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Body of a response that counts the bytes read from it, and writes the log line when dropped.
+struct LoggedBody<D, W, L>
+where
+    W: Write,
+    L: LogFormatter,
+{
+    inner: D,
+    received: Instant,
+    entry: LogEntry,
+    format: L,
+    output: W,
+}
+
+impl<D, W, L> Read for LoggedBody<D, W, L>
+where
+    D: Read,
+    W: Write,
+    L: LogFormatter,
+{
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let n = self.inner.read(buf)?;
+        self.entry.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+impl<D, W, L> Seek for LoggedBody<D, W, L>
+where
+    D: Seek,
+    W: Write,
+    L: LogFormatter,
+{
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<D, W, L> Drop for LoggedBody<D, W, L>
+where
+    W: Write,
+    L: LogFormatter,
+{
+    fn drop(&mut self) {
+        self.entry.elapsed = self.received.elapsed();
+        let mut line = self.format.format(&self.entry);
+        line.push('\n');
+        // Written at once, so that lines of requests processed in parallel aren't mixed.
+        let _ = self.output.write_all(line.as_bytes());
+    }
+}
+
 fn format_time(duration: Duration) -> String {
     let secs_part = match duration.as_secs().checked_mul(1_000_000_000) {
         Some(v) => v,
@@ -151,3 +394,170 @@ fn format_time(duration: Duration) -> String {
         format!("{:.1}s", duration_in_ns as f64 / 1_000_000_000.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::log;
+    use super::log_with_format;
+    use super::LogEntry;
+    use super::LogFormat;
+    use std::io;
+    use std::io::Read;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use Request;
+    use Response;
+    use ResponseBody;
+
+    // Writer whose content can be read after it has been moved.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn content(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn request() -> Request {
+        Request::fake_http_from(
+            "1.2.3.4:5678".parse().unwrap(),
+            "GET",
+            "/foo?bar=1",
+            vec![
+                ("Referer".to_owned(), "http://example.com/".to_owned()),
+                ("User-Agent".to_owned(), "test \"agent\"".to_owned()),
+            ],
+            vec![],
+        )
+    }
+
+    // Reads the body of the response the same way the server does.
+    fn send(response: Response) {
+        let (mut data, _) = response.data.into_reader_and_size();
+        io::copy(&mut data, &mut io::sink()).unwrap();
+    }
+
+    #[test]
+    fn log_status() {
+        let output = Output::default();
+        let response = log(&request(), output.clone(), Response::empty_404);
+        assert_eq!(response.status_code, 404);
+        send(response);
+        let line = output.content();
+        assert!(line.contains(" UTC - GET /foo?bar=1 - "), "{:?}", line);
+        assert!(line.ends_with(" - 404 - 0\n"), "{:?}", line);
+    }
+
+    #[test]
+    fn log_bytes_sent() {
+        let output = Output::default();
+        let response = log(&request(), output.clone(), || {
+            let mut response = Response::text("");
+            response.data = ResponseBody::from_reader(&b"hello"[..]);
+            response
+        });
+
+        // Nothing is logged before the body has been sent.
+        assert_eq!(output.content(), "");
+        send(response);
+        let line = output.content();
+        assert!(line.ends_with(" - 200 - 5\n"), "{:?}", line);
+    }
+
+    #[test]
+    fn combined() {
+        let output = Output::default();
+        let response = log_with_format(&request(), output.clone(), LogFormat::Combined, || {
+            Response::text("hello").with_status_code(201)
+        });
+        assert_eq!(response.status_code, 201);
+
+        // Nothing is logged before the body has been sent.
+        assert_eq!(output.content(), "");
+        send(response);
+
+        let line = output.content();
+        assert!(line.starts_with("1.2.3.4 - - ["), "{:?}", line);
+        assert!(
+            line.ends_with(
+                "] \"GET /foo?bar=1 HTTP/1.1\" 201 5 \"http://example.com/\" \"test \\\"agent\\\"\"\n"
+            ),
+            "{:?}",
+            line
+        );
+    }
+
+    #[test]
+    fn common_empty_body() {
+        let output = Output::default();
+        let response = log_with_format(&request(), output.clone(), LogFormat::Common, || {
+            Response::empty_404()
+        });
+        send(response);
+
+        let line = output.content();
+        assert!(
+            line.ends_with("] \"GET /foo?bar=1 HTTP/1.1\" 404 -\n"),
+            "{:?}",
+            line
+        );
+    }
+
+    #[test]
+    fn custom_format() {
+        let output = Output::default();
+        let format =
+            |entry: &LogEntry| format!("{} {} {}", entry.url, entry.status_code, entry.bytes);
+        let response = log_with_format(&request(), output.clone(), format, || {
+            Response::from_data("application/octet-stream", vec![0; 100])
+        });
+        assert_eq!(response.data.into_reader_and_size().1, Some(100));
+        assert_eq!(output.content(), "/foo?bar=1 200 0\n");
+
+        let output = Output::default();
+        let response = log_with_format(&request(), output.clone(), format, || {
+            Response::from_data("application/octet-stream", vec![0; 100])
+        });
+        let (mut data, _) = response.data.into_reader_and_size();
+        let mut buf = [0; 30];
+        data.read_exact(&mut buf).unwrap();
+        drop(data);
+        assert_eq!(output.content(), "/foo?bar=1 200 30\n");
+    }
+
+    #[test]
+    fn body_keeps_length_and_seeks() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("Range".to_owned(), "bytes=2-4".to_owned())],
+            vec![],
+        );
+        let output = Output::default();
+        let format = |entry: &LogEntry| entry.bytes.to_string();
+        let response = log_with_format(&request, output.clone(), format, || {
+            Response::from_data("text/plain", b"0123456789".to_vec())
+        })
+        .with_ranges(&request);
+        assert_eq!(response.status_code, 206);
+
+        let (mut data, size) = response.data.into_reader_and_size();
+        assert_eq!(size, Some(3));
+        let mut body = String::new();
+        data.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "234");
+        drop(data);
+        assert_eq!(output.content(), "3\n");
+    }
+}
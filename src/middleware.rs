@@ -0,0 +1,651 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Composing handlers out of reusable layers.
+//!
+//! Logging, authentication or CORS all follow the same pattern: do something with the request,
+//! call the rest of the handler, then possibly do something with the response. A `Middleware`
+//! captures this pattern. It receives the request and a `next` function that calls the rest of
+//! the chain, and returns a response.
+//!
+//! Use `apply()` to run a handler behind a list of middlewares. The first middleware of the list
+//! is the outermost one, which means that it sees the request first and the response last.
+//!
+//! # Example
+//!
+//! ```
+//! use std::io;
+//! use rouille::middleware::{self, BasicAuth, Cors, Log};
+//! use rouille::Request;
+//! use rouille::Response;
+//!
+//! let log = Log::new(io::stdout());
+//! let cors = Cors::any();
+//! let auth = BasicAuth::new("admin area", |credentials| {
+//!     credentials.login == "admin" && credentials.password == "GT5GeKyLvKLxuc7mjF5h"
+//! });
+//!
+//! let handler = move |request: &Request| {
+//!     middleware::apply(request, &[&log, &cors, &auth], |_| {
+//!         Response::text("You are in a secret area")
+//!     })
+//! };
+//! # let _ = handler;
+//! ```
+//!
+//! Closures that take a `&Request` and a `next` function can also be used as middlewares:
+//!
+//! ```
+//! use rouille::middleware::{self, Next};
+//! use rouille::Request;
+//! use rouille::Response;
+//!
+//! fn handle(request: &Request) -> Response {
+//!     let powered_by = |request: &Request, next: Next| {
+//!         next(request).with_unique_header("X-Powered-By", "rouille")
+//!     };
+//!
+//!     middleware::apply(request, &[&powered_by], |_| Response::text("hello"))
+//! }
+//! ```
+
+use std::io;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use input::basic_http_auth;
+use input::HttpAuthCredentials;
+use log;
+use Request;
+use Response;
+
+/// Function that calls the rest of the chain of middlewares and the handler.
+pub type Next<'a> = &'a dyn Fn(&Request) -> Response;
+
+/// A layer around a handler.
+///
+/// See [the module-level documentation](index.html).
+pub trait Middleware {
+    /// Processes a request. Call `next` in order to get the response of the rest of the chain,
+    /// or return a response directly to stop the request there.
+    ///
+    /// The request passed to `next` doesn't need to be `request`. For example you can pass the
+    /// result of `Request::remove_prefix`.
+    fn handle(&self, request: &Request, next: Next) -> Response;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&Request, Next) -> Response,
+{
+    #[inline]
+    fn handle(&self, request: &Request, next: Next) -> Response {
+        self(request, next)
+    }
+}
+
+/// Calls `handler` behind the given middlewares.
+///
+/// The first middleware of the list is the outermost one.
+pub fn apply<H>(request: &Request, middlewares: &[&dyn Middleware], handler: H) -> Response
+where
+    H: Fn(&Request) -> Response,
+{
+    apply_inner(request, middlewares, &handler)
+}
+
+fn apply_inner(
+    request: &Request,
+    middlewares: &[&dyn Middleware],
+    handler: &dyn Fn(&Request) -> Response,
+) -> Response {
+    match middlewares.split_first() {
+        Some((first, rest)) => first.handle(request, &|rq| apply_inner(rq, rest, handler)),
+        None => handler(request),
+    }
+}
+
+/// Middleware that writes a line for each request, like [`rouille::log`](../fn.log.html).
+pub struct Log<W> {
+    output: Arc<Mutex<W>>,
+}
+
+impl<W> Log<W>
+where
+    W: Write,
+{
+    /// Builds a `Log` that writes to the given output.
+    #[inline]
+    pub fn new(output: W) -> Log<W> {
+        Log {
+            output: Arc::new(Mutex::new(output)),
+        }
+    }
+}
+
+impl<W> Middleware for Log<W>
+where
+    W: Write + Send + 'static,
+{
+    fn handle(&self, request: &Request, next: Next) -> Response {
+        let output = LogOutput {
+            line: Vec::new(),
+            output: self.output.clone(),
+        };
+        log(request, output, || next(request))
+    }
+}
+
+// Output passed to `log`. The line is built in a buffer and written to the output of the `Log`
+// when dropped, so that the output isn't locked while the body of the response is sent.
+struct LogOutput<W>
+where
+    W: Write,
+{
+    line: Vec<u8>,
+    output: Arc<Mutex<W>>,
+}
+
+impl<W> Write for LogOutput<W>
+where
+    W: Write,
+{
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W> Drop for LogOutput<W>
+where
+    W: Write,
+{
+    fn drop(&mut self) {
+        if let Ok(mut output) = self.output.lock() {
+            let _ = output.write_all(&self.line);
+        }
+    }
+}
+
+/// Middleware that allows cross-origin requests.
+///
+/// Adds an `Access-Control-Allow-Origin` header to the responses, and answers CORS preflight
+/// requests (`OPTIONS` requests with an `Access-Control-Request-Method` header) without calling
+/// the rest of the chain.
+///
+/// Requests whose `Origin` isn't allowed are passed through unchanged, which makes the browser
+/// block them.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    // `None` means that all origins are allowed.
+    origins: Option<Vec<String>>,
+    methods: String,
+}
+
+impl Cors {
+    /// Builds a `Cors` that allows all origins.
+    #[inline]
+    pub fn any() -> Cors {
+        Cors {
+            origins: None,
+            methods: "GET, HEAD, POST, PUT, DELETE, PATCH, OPTIONS".to_owned(),
+        }
+    }
+
+    /// Builds a `Cors` that only allows the given origins, for example `https://example.com`.
+    pub fn with_origins<I, S>(origins: I) -> Cors
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Cors {
+            origins: Some(origins.into_iter().map(Into::into).collect()),
+            ..Cors::any()
+        }
+    }
+
+    /// Sets the methods that are allowed in cross-origin requests. Defaults to all the usual
+    /// methods.
+    pub fn with_methods(mut self, methods: &[&str]) -> Cors {
+        self.methods = methods.join(", ");
+        self
+    }
+
+    // Answers a request coming from an allowed origin.
+    fn allow(&self, request: &Request, next: Next, allowed_origin: String) -> Response {
+        let preflight = request.method() == "OPTIONS"
+            && request.header("Access-Control-Request-Method").is_some();
+
+        let response = if preflight {
+            let mut response = Response::empty_204()
+                .with_unique_header("Access-Control-Allow-Methods", self.methods.clone());
+            if let Some(headers) = request.header("Access-Control-Request-Headers") {
+                response =
+                    response.with_unique_header("Access-Control-Allow-Headers", headers.to_owned());
+            }
+            response
+        } else {
+            next(request)
+        };
+
+        response.with_unique_header("Access-Control-Allow-Origin", allowed_origin)
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: &Request, next: Next) -> Response {
+        let allowed_origin = match (&self.origins, request.header("Origin")) {
+            (None, _) => Some("*".to_owned()),
+            (Some(origins), Some(origin)) if origins.iter().any(|o| o == origin) => {
+                Some(origin.to_owned())
+            }
+            _ => None,
+        };
+
+        let response = match allowed_origin {
+            Some(allowed_origin) => self.allow(request, next, allowed_origin),
+            None => next(request),
+        };
+
+        // When only some origins are allowed, the response depends on `Origin`, including when
+        // it has no CORS headers, so caches must not reuse it for other origins.
+        if self.origins.is_some() {
+            response.with_additional_header("Vary", "Origin")
+        } else {
+            response
+        }
+    }
+}
+
+/// Middleware that requires basic HTTP authentication.
+///
+/// Requests without credentials, or whose credentials are rejected by the check function, are
+/// answered with `Response::basic_http_auth_login_required` without calling the rest of the
+/// chain.
+pub struct BasicAuth<F> {
+    realm: String,
+    check: F,
+}
+
+impl<F> BasicAuth<F>
+where
+    F: Fn(&HttpAuthCredentials) -> bool,
+{
+    /// Builds a `BasicAuth`. The check function returns `true` if the credentials are valid.
+    #[inline]
+    pub fn new<S>(realm: S, check: F) -> BasicAuth<F>
+    where
+        S: Into<String>,
+    {
+        BasicAuth {
+            realm: realm.into(),
+            check,
+        }
+    }
+}
+
+impl<F> Middleware for BasicAuth<F>
+where
+    F: Fn(&HttpAuthCredentials) -> bool,
+{
+    fn handle(&self, request: &Request, next: Next) -> Response {
+        match basic_http_auth(request) {
+            Some(ref credentials) if (self.check)(credentials) => next(request),
+            _ => Response::basic_http_auth_login_required(&self.realm),
+        }
+    }
+}
+
+/// Middleware that adds headers hardening the responses against common attacks.
+///
+/// By default, the following headers are added:
+///
+/// - `X-Content-Type-Options: nosniff`
+/// - `X-Frame-Options: DENY`
+/// - `Referrer-Policy: no-referrer`
+///
+/// A `Content-Security-Policy` and a `Strict-Transport-Security` header can be configured as
+/// well. The latter is only sent over HTTPS, as browsers ignore it otherwise.
+///
+/// Each header can be changed or removed. Headers that the response already has are left
+/// untouched, which lets handlers override them for specific responses.
+///
+/// # Example
+///
+/// ```
+/// use rouille::middleware::{self, SecurityHeaders};
+/// use rouille::Request;
+/// use rouille::Response;
+///
+/// let security = SecurityHeaders::new()
+///     .with_content_security_policy("default-src 'self'")
+///     .with_frame_options("SAMEORIGIN");
+///
+/// let handler = move |request: &Request| {
+///     middleware::apply(request, &[&security], |_| Response::html("<p>hello</p>"))
+/// };
+/// # let _ = handler;
+/// ```
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    headers: Vec<(&'static str, String)>,
+}
+
+impl SecurityHeaders {
+    /// Builds a `SecurityHeaders` with the default headers.
+    pub fn new() -> SecurityHeaders {
+        SecurityHeaders {
+            headers: vec![
+                ("X-Content-Type-Options", "nosniff".to_owned()),
+                ("X-Frame-Options", "DENY".to_owned()),
+                ("Referrer-Policy", "no-referrer".to_owned()),
+            ],
+        }
+    }
+
+    /// Sets the value of the `X-Frame-Options` header. Defaults to `DENY`.
+    #[inline]
+    pub fn with_frame_options<S>(self, value: S) -> SecurityHeaders
+    where
+        S: Into<String>,
+    {
+        self.with("X-Frame-Options", value.into())
+    }
+
+    /// Sets the value of the `Referrer-Policy` header. Defaults to `no-referrer`.
+    #[inline]
+    pub fn with_referrer_policy<S>(self, value: S) -> SecurityHeaders
+    where
+        S: Into<String>,
+    {
+        self.with("Referrer-Policy", value.into())
+    }
+
+    /// Adds a `Content-Security-Policy` header with the given value.
+    #[inline]
+    pub fn with_content_security_policy<S>(self, value: S) -> SecurityHeaders
+    where
+        S: Into<String>,
+    {
+        self.with("Content-Security-Policy", value.into())
+    }
+
+    /// Adds a `Strict-Transport-Security` header with the given value to the responses to
+    /// HTTPS requests, for example `max-age=31536000; includeSubDomains`.
+    #[inline]
+    pub fn with_strict_transport_security<S>(self, value: S) -> SecurityHeaders
+    where
+        S: Into<String>,
+    {
+        self.with("Strict-Transport-Security", value.into())
+    }
+
+    /// Stops adding the given header, for example `X-Frame-Options`. The name is
+    /// case-insensitive.
+    pub fn without(mut self, header: &str) -> SecurityHeaders {
+        self.headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(header));
+        self
+    }
+
+    fn with(mut self, header: &'static str, value: String) -> SecurityHeaders {
+        match self.headers.iter_mut().find(|(name, _)| *name == header) {
+            Some(entry) => entry.1 = value,
+            None => self.headers.push((header, value)),
+        }
+        self
+    }
+}
+
+impl Default for SecurityHeaders {
+    #[inline]
+    fn default() -> SecurityHeaders {
+        SecurityHeaders::new()
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    fn handle(&self, request: &Request, next: Next) -> Response {
+        let mut response = next(request);
+
+        for (name, value) in &self.headers {
+            if *name == "Strict-Transport-Security" && !request.is_secure() {
+                continue;
+            }
+
+            if !response
+                .headers
+                .iter()
+                .any(|(h, _)| h.eq_ignore_ascii_case(name))
+            {
+                response
+                    .headers
+                    .push(((*name).into(), value.clone().into()));
+            }
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, BasicAuth, Cors, Log, Next, SecurityHeaders};
+    use std::io;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use Request;
+    use Response;
+
+    fn request(method: &str, headers: Vec<(&str, &str)>) -> Request {
+        let headers = headers
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        Request::fake_http(method, "/", headers, vec![])
+    }
+
+    fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|h| h.0.eq_ignore_ascii_case(name))
+            .map(|h| &*h.1)
+    }
+
+    #[test]
+    fn order() {
+        let calls = Mutex::new(Vec::new());
+        let first = |rq: &Request, next: Next| {
+            calls.lock().unwrap().push("first");
+            next(rq).with_additional_header("X-Test", "first")
+        };
+        let second = |rq: &Request, next: Next| {
+            calls.lock().unwrap().push("second");
+            next(rq).with_additional_header("X-Test", "second")
+        };
+
+        let response = apply(&request("GET", vec![]), &[&first, &second], |_| {
+            calls.lock().unwrap().push("handler");
+            Response::text("")
+        });
+
+        assert_eq!(*calls.lock().unwrap(), vec!["first", "second", "handler"]);
+        let added = response
+            .headers
+            .iter()
+            .filter(|h| h.0 == "X-Test")
+            .map(|h| &*h.1)
+            .collect::<Vec<_>>();
+        assert_eq!(added, vec!["second", "first"]);
+    }
+
+    #[test]
+    fn short_circuit() {
+        let deny = |_: &Request, _: Next| Response::empty_404();
+        let response = apply(&request("GET", vec![]), &[&deny], |_| {
+            panic!("the handler shouldn't be called")
+        });
+        assert_eq!(response.status_code, 404);
+    }
+
+    #[test]
+    fn cors_and_basic_auth() {
+        let cors = Cors::with_origins(vec!["https://example.com"]);
+        let auth = BasicAuth::new("test", |c| c.login == "foo" && c.password == "bar");
+        let handler = |rq: &Request| apply(rq, &[&cors, &auth], |_| Response::text("secret"));
+
+        // "foo:bar"
+        let response = handler(&request(
+            "GET",
+            vec![
+                ("Origin", "https://example.com"),
+                ("Authorization", "Basic Zm9vOmJhcg=="),
+            ],
+        ));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin"),
+            Some("https://example.com")
+        );
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+
+        // The failed authentication still gets the CORS headers, as `cors` is outermost.
+        let response = handler(&request("GET", vec![("Origin", "https://example.com")]));
+        assert_eq!(response.status_code, 401);
+        assert_eq!(
+            header(&response, "WWW-Authenticate"),
+            Some("Basic realm=\"test\"")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin"),
+            Some("https://example.com")
+        );
+
+        // Preflight requests don't need credentials.
+        let response = handler(&request(
+            "OPTIONS",
+            vec![
+                ("Origin", "https://example.com"),
+                ("Access-Control-Request-Method", "PUT"),
+                ("Access-Control-Request-Headers", "Authorization"),
+            ],
+        ));
+        assert_eq!(response.status_code, 204);
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Headers"),
+            Some("Authorization")
+        );
+
+        // Unknown origins don't get CORS headers.
+        let response = handler(&request(
+            "GET",
+            vec![
+                ("Origin", "https://evil.com"),
+                ("Authorization", "Basic Zm9vOmJhcg=="),
+            ],
+        ));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+
+        // Neither do requests without an origin.
+        let response = handler(&request(
+            "GET",
+            vec![("Authorization", "Basic Zm9vOmJhcg==")],
+        ));
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn security_headers_default() {
+        let security = SecurityHeaders::new();
+        let response = apply(&request("GET", vec![]), &[&security], |_| {
+            Response::text("hello")
+        });
+
+        assert_eq!(header(&response, "X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(header(&response, "X-Frame-Options"), Some("DENY"));
+        assert_eq!(header(&response, "Referrer-Policy"), Some("no-referrer"));
+        assert_eq!(header(&response, "Content-Security-Policy"), None);
+        assert_eq!(header(&response, "Strict-Transport-Security"), None);
+        assert_eq!(response.headers.len(), 4);
+    }
+
+    #[test]
+    fn security_headers_configured() {
+        let security = SecurityHeaders::new()
+            .with_frame_options("SAMEORIGIN")
+            .with_content_security_policy("default-src 'self'")
+            .with_strict_transport_security("max-age=60")
+            .without("referrer-policy");
+        let handler = |rq: &Request| {
+            apply(rq, &[&security], |_| {
+                Response::text("hello").with_additional_header("x-content-type-options", "custom")
+            })
+        };
+
+        let response = handler(&request("GET", vec![]));
+        assert_eq!(header(&response, "X-Content-Type-Options"), Some("custom"));
+        assert_eq!(header(&response, "X-Frame-Options"), Some("SAMEORIGIN"));
+        assert_eq!(header(&response, "Referrer-Policy"), None);
+        assert_eq!(
+            header(&response, "Content-Security-Policy"),
+            Some("default-src 'self'")
+        );
+        assert_eq!(header(&response, "Strict-Transport-Security"), None);
+
+        let response = handler(&Request::fake_https("GET", "/", vec![], vec![]));
+        assert_eq!(
+            header(&response, "Strict-Transport-Security"),
+            Some("max-age=60")
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn log_and_cors() {
+        let output = Output::default();
+        let log = Log::new(output.clone());
+        let cors = Cors::any();
+
+        let response = apply(&request("GET", vec![]), &[&log, &cors], |_| {
+            Response::empty_404()
+        });
+        assert_eq!(response.status_code, 404);
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("*"));
+
+        // The line is written once the body has been sent.
+        let (mut data, _) = response.data.into_reader_and_size();
+        io::copy(&mut data, &mut io::sink()).unwrap();
+        drop(data);
+        let line = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(line.contains(" UTC - GET / - "), "{:?}", line);
+        assert!(line.ends_with(" - 404 - 0\n"), "{:?}", line);
+    }
+}
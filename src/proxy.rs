@@ -26,6 +26,14 @@
 //! the request before it was called. Since this indicates a logic error in the code, it is a good
 //! idea to `unwrap()` the `Result` returned by `full_proxy()`.
 //!
+//! # WebSockets
+//!
+//! Upgrade requests, such as websocket handshakes, are not forwarded as such: the `Connection`
+//! header is always replaced with `Connection: close`, and the target server will usually answer
+//! with an error. Bridging an upgraded connection requires reading from and writing to the client
+//! at the same time, but the connection handed over by tiny-http after an upgrade is a single
+//! `ReadWrite` object that can't be split between two threads.
+//!
 //! # Example
 //!
 //! You can for example dispatch to a different server depending on the host requested by the
@@ -41,6 +49,7 @@
 //!             proxy::ProxyConfig {
 //!                 addr: "domain1.handler.localnetwork",
 //!                 replace_host: None,
+//!                 preserve_host: false,
 //!             }
 //!         },
 //!
@@ -48,6 +57,7 @@
 //!             proxy::ProxyConfig {
 //!                 addr: "domain2.handler.localnetwork",
 //!                 replace_host: None,
+//!                 preserve_host: false,
 //!             }
 //!         },
 //!
@@ -70,6 +80,7 @@ use std::net::TcpStream;
 use std::net::ToSocketAddrs;
 use std::time::Duration;
 
+use connection_options;
 use Request;
 use Response;
 use ResponseBody;
@@ -125,8 +136,13 @@ impl fmt::Display for ProxyError {
 pub struct ProxyConfig<A> {
     /// The address to connect to. For example `example.com:80`.
     pub addr: A,
-    /// If `Some`, the `Host` header will be replaced with this value.
+    /// If `Some`, the `Host` header sent to the target server is this value. This is usually the
+    /// name of the target server. If `None`, the `Host` header contains the IP address and port
+    /// that `addr` resolved to.
     pub replace_host: Option<Cow<'static, str>>,
+    /// If `true`, the `Host` header of the request is passed through as it is and `replace_host`
+    /// is ignored. This is useful if the target server serves multiple domains.
+    pub preserve_host: bool,
 }
 
 /// Sends the request to another HTTP server using the configuration.
@@ -135,6 +151,17 @@ pub struct ProxyConfig<A> {
 /// to instead return a response with a status code such as 502 (`Bad Gateway`) or 504
 /// (`Gateway Time-out`), see `full_proxy`.
 ///
+/// The target server receives the original client information in the following headers:
+///
+/// - `X-Forwarded-For` contains the IP address of the client. If the request already had this
+///   header, for example because it went through another proxy, the address is appended to the
+///   existing list.
+/// - `X-Forwarded-Proto` contains `http` or `https` depending on `Request::is_secure`.
+/// - `X-Forwarded-Host` contains the original `Host` header of the request, if any.
+///
+/// The `Connection` header of the request and the headers that it lists aren't forwarded, as they
+/// only concern the connection between the client and the proxy.
+///
 /// > **Note**: Implementation is very hacky for the moment.
 ///
 /// > **Note**: SSL is not supported.
@@ -154,22 +181,51 @@ where
 
     socket
         .write_all(format!("{} {} HTTP/1.1\r\n", request.method(), request.raw_url()).as_bytes())?;
+
+    let host = if config.preserve_host {
+        request.header("Host").map(Cow::from)
+    } else if let Some(replace) = config.replace_host {
+        Some(replace)
+    } else {
+        Some(socket.peer_addr()?.to_string().into())
+    };
+    if let Some(host) = host {
+        socket.write_all(format!("Host: {}\r\n", host).as_bytes())?;
+    }
+
+    // Headers listed in the `Connection` header only concern the connection with the client.
+    let connection_options = connection_options(request.headers());
+
+    let mut forwarded_for = Vec::new();
     for (header, value) in request.headers() {
-        let value = if header == "Host" {
-            if let Some(ref replace) = config.replace_host {
-                &**replace
-            } else {
-                value
-            }
-        } else {
-            value
-        };
-        if header == "Connection" {
+        if header.eq_ignore_ascii_case("Host")
+            || header.eq_ignore_ascii_case("Connection")
+            || header.eq_ignore_ascii_case("X-Forwarded-Proto")
+            || header.eq_ignore_ascii_case("X-Forwarded-Host")
+            || connection_options
+                .iter()
+                .any(|name| header.eq_ignore_ascii_case(name))
+        {
+            continue;
+        }
+
+        if header.eq_ignore_ascii_case("X-Forwarded-For") {
+            forwarded_for.push(value);
             continue;
         }
 
         socket.write_all(format!("{}: {}\r\n", header, value).as_bytes())?;
     }
+
+    let client_ip = request.remote_addr().ip().to_string();
+    forwarded_for.push(&client_ip);
+    socket.write_all(format!("X-Forwarded-For: {}\r\n", forwarded_for.join(", ")).as_bytes())?;
+    let proto = if request.is_secure() { "https" } else { "http" };
+    socket.write_all(format!("X-Forwarded-Proto: {}\r\n", proto).as_bytes())?;
+    if let Some(host) = request.header("Host") {
+        socket.write_all(format!("X-Forwarded-Host: {}\r\n", host).as_bytes())?;
+    }
+
     socket.write_all(b"Connection: close\r\n\r\n")?;
     io::copy(&mut data, &mut socket)?;
 
@@ -218,12 +274,11 @@ where
         }
     }
 
-    Ok(Response {
+    Ok(Response::from_parts(
         status_code,
         headers,
-        data: ResponseBody::from_reader(socket),
-        upgrade: None,
-    })
+        ResponseBody::from_reader(socket),
+    ))
 }
 
 /// Error that can happen when calling `full_proxy`.
@@ -264,3 +319,199 @@ where
         Err(ProxyError::BodyAlreadyExtracted) => Err(FullProxyError::BodyAlreadyExtracted),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::proxy;
+    use super::ProxyConfig;
+    use std::io::BufRead;
+    use std::io::BufReader;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+    use Request;
+
+    // Accepts a single request, and answers with the headers it received as body.
+    fn mock_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut headers = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                headers.push_str(&line);
+            }
+
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                headers.len(),
+                headers
+            )
+            .unwrap();
+        });
+
+        addr
+    }
+
+    fn upstream_headers(
+        request: &Request,
+        replace_host: Option<&'static str>,
+        preserve_host: bool,
+    ) -> String {
+        let config = ProxyConfig {
+            addr: mock_upstream(),
+            replace_host: replace_host.map(Into::into),
+            preserve_host,
+        };
+        let response = proxy(request, config).unwrap();
+        assert_eq!(response.status_code, 200);
+
+        let mut body = String::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_string(&mut body)
+            .unwrap();
+        body
+    }
+
+    #[test]
+    fn forwarded_headers() {
+        let request = Request::fake_http_from(
+            "1.2.3.4:5678".parse().unwrap(),
+            "GET",
+            "/foo?bar",
+            vec![
+                ("Host".to_owned(), "example.com".to_owned()),
+                ("X-Forwarded-Proto".to_owned(), "https".to_owned()),
+            ],
+            vec![],
+        );
+
+        let headers = upstream_headers(&request, Some("upstream.local"), false);
+        assert!(
+            headers.starts_with("GET /foo?bar HTTP/1.1\r\n"),
+            "{}",
+            headers
+        );
+        assert!(
+            headers.contains("\r\nHost: upstream.local\r\n"),
+            "{}",
+            headers
+        );
+        assert!(
+            headers.contains("\r\nX-Forwarded-For: 1.2.3.4\r\n"),
+            "{}",
+            headers
+        );
+        assert!(
+            headers.contains("\r\nX-Forwarded-Proto: http\r\n"),
+            "{}",
+            headers
+        );
+        assert!(
+            headers.contains("\r\nX-Forwarded-Host: example.com\r\n"),
+            "{}",
+            headers
+        );
+        assert!(!headers.contains("https"), "{}", headers);
+    }
+
+    #[test]
+    fn host_defaults_to_upstream() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("Host".to_owned(), "example.com".to_owned())],
+            vec![],
+        );
+
+        let headers = upstream_headers(&request, None, false);
+        assert!(headers.contains("\r\nHost: 127.0.0.1:"), "{}", headers);
+        assert_eq!(headers.matches("Host: ").count(), 2, "{}", headers);
+        assert!(
+            headers.contains("\r\nX-Forwarded-Host: example.com\r\n"),
+            "{}",
+            headers
+        );
+    }
+
+    #[test]
+    fn preserve_host() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("Host".to_owned(), "example.com".to_owned())],
+            vec![],
+        );
+
+        let headers = upstream_headers(&request, Some("upstream.local"), true);
+        assert!(headers.contains("\r\nHost: example.com\r\n"), "{}", headers);
+        assert!(!headers.contains("upstream.local"), "{}", headers);
+    }
+
+    #[test]
+    fn forwarded_for_appended() {
+        let request = Request::fake_https_from(
+            "1.2.3.4:5678".parse().unwrap(),
+            "GET",
+            "/",
+            vec![
+                ("Host".to_owned(), "example.com".to_owned()),
+                (
+                    "X-Forwarded-For".to_owned(),
+                    "10.0.0.1, 10.0.0.2".to_owned(),
+                ),
+                ("x-forwarded-for".to_owned(), "10.0.0.3".to_owned()),
+            ],
+            vec![],
+        );
+
+        let headers = upstream_headers(&request, None, true);
+        assert!(headers.contains("\r\nHost: example.com\r\n"), "{}", headers);
+        assert!(
+            headers.contains("\r\nX-Forwarded-For: 10.0.0.1, 10.0.0.2, 10.0.0.3, 1.2.3.4\r\n"),
+            "{}",
+            headers
+        );
+        assert!(
+            headers.contains("\r\nX-Forwarded-Proto: https\r\n"),
+            "{}",
+            headers
+        );
+        assert_eq!(headers.matches("X-Forwarded-For").count(), 1);
+    }
+
+    #[test]
+    fn connection_options_not_forwarded() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![
+                ("Host".to_owned(), "example.com".to_owned()),
+                ("Connection".to_owned(), "keep-alive, X-Secret".to_owned()),
+                ("x-secret".to_owned(), "1".to_owned()),
+                ("Keep-Alive".to_owned(), "timeout=5".to_owned()),
+                ("X-Other".to_owned(), "2".to_owned()),
+            ],
+            vec![],
+        );
+
+        let headers = upstream_headers(&request, None, false);
+        assert!(!headers.to_lowercase().contains("x-secret"), "{}", headers);
+        assert!(!headers.contains("Keep-Alive"), "{}", headers);
+        assert!(headers.contains("\r\nX-Other: 2\r\n"), "{}", headers);
+        assert!(headers.contains("\r\nConnection: close\r\n"), "{}", headers);
+    }
+}
@@ -7,15 +7,23 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
+use assets::extension_to_mime;
+use chrono;
+use filetime;
 use percent_encoding;
 use serde;
 use serde_json;
 use std::borrow::Cow;
 use std::fmt;
+use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::Cursor;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::SystemTime;
 use Request;
 use Upgrade;
 
@@ -41,6 +49,10 @@ pub struct Response {
     /// Additionally, the `Upgrade` header is ignored as well unless the `upgrade` field of the
     /// `Response` is set to something.
     ///
+    /// The headers whose names are listed in a `Connection` header are ignored too, as they only
+    /// concern a single connection. This is mostly useful when forwarding a response from another
+    /// server.
+    ///
     /// The reason for this is that these headers are too low-level and are directly handled by
     /// the underlying HTTP response system.
     ///
@@ -102,6 +114,36 @@ impl Response {
         !self.is_success()
     }
 
+    /// Builds a `Response` from a status code, a list of headers and a body.
+    ///
+    /// This is mostly useful when converting a response from another library. See the
+    /// documentation of the `headers` field for the headers that are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{Response, ResponseBody};
+    ///
+    /// let response = Response::from_parts(
+    ///     418,
+    ///     vec![("Content-Type".into(), "text/plain".into())],
+    ///     ResponseBody::from_string("I'm a teapot"),
+    /// );
+    /// ```
+    #[inline]
+    pub fn from_parts(
+        status_code: u16,
+        headers: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+        data: ResponseBody,
+    ) -> Response {
+        Response {
+            status_code,
+            headers,
+            data,
+            upgrade: None,
+        }
+    }
+
     /// Builds a `Response` that redirects the user to another URL with a 301 status code. This
     /// semantically means a permanent redirect.
     ///
@@ -243,6 +285,40 @@ impl Response {
         }
     }
 
+    /// Builds a `Response` that redirects the client to the `https://` equivalent of the URL of
+    /// the request, with a 308 status code. This is typically the only handler of a server
+    /// listening on port 80.
+    ///
+    /// The URL is rebuilt from the `Host` header of the request, followed by its path and query
+    /// string. The port in the `Host` header, if any, is removed so that the client uses the
+    /// default HTTPS port. A 400 response is returned if the `Host` header is missing or invalid.
+    /// See `Request::host`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{Request, Response};
+    ///
+    /// let request = Request::fake_http(
+    ///     "GET",
+    ///     "/foo?a=b",
+    ///     vec![("Host".to_owned(), "example.com".to_owned())],
+    ///     vec![],
+    /// );
+    /// let response = Response::redirect_to_https(&request);
+    /// assert_eq!(response.status_code, 308);
+    /// ```
+    pub fn redirect_to_https(request: &Request) -> Response {
+        // `host()` rejects the values that could be used to redirect to another website.
+        let hostname = match request.host() {
+            Some((name, _)) if name.contains(':') => format!("[{}]", name),
+            Some((name, _)) => name,
+            None => return Response::empty_400(),
+        };
+
+        Response::redirect_308(format!("https://{}{}", hostname, request.raw_url()))
+    }
+
     /// Builds a 200 `Response` with data.
     ///
     /// # Example
@@ -289,6 +365,65 @@ impl Response {
         }
     }
 
+    /// Builds a `Response` that outputs the file at the given path.
+    ///
+    /// The `Content-Type` is guessed from the extension of the file with `extension_to_mime`, and
+    /// the `ETag` and `Last-Modified` headers are set based on the metadata of the file. If the
+    /// request already has a matching `If-None-Match` or `If-Modified-Since` header, an empty 304
+    /// response is returned instead.
+    ///
+    /// Returns `Response::empty_404()` if the path doesn't exist, isn't a regular file or can't be
+    /// opened.
+    ///
+    /// > **Note**: The path is used as-is. Never build it from the URL of the request, or a
+    /// > client could read any file of the server. Use `match_assets` for this instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    /// use rouille::Response;
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     Response::from_path(request, "favicon.ico")
+    /// }
+    /// ```
+    pub fn from_path<P>(request: &Request, path: P) -> Response
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        let metadata = match fs::metadata(path) {
+            Ok(m) if m.is_file() => m,
+            _ => return Response::empty_404(),
+        };
+
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(_) => return Response::empty_404(),
+        };
+
+        let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+
+        // The ETag combines the size and the modification time of the file, so that replacing a
+        // file with another one of the same size still invalidates the client's cache.
+        let mtime = filetime::FileTime::from_last_modification_time(&metadata);
+        let etag = format!(
+            "\"{:x}-{:x}.{:x}\"",
+            metadata.len(),
+            mtime.unix_seconds(),
+            mtime.nanoseconds()
+        );
+
+        let response = Response::from_file(extension_to_mime(extension), file);
+        let response = match metadata.modified() {
+            Ok(modified) => response.with_last_modified(request, modified),
+            Err(_) => response,
+        };
+        response.with_etag(request, etag)
+    }
+
     /// Builds a `Response` that outputs HTML.
     ///
     /// # Example
@@ -391,6 +526,34 @@ impl Response {
         }
     }
 
+    /// Builds a `Response` with the given status code whose body is a JSON object of the form
+    /// `{"error": "<message>"}`.
+    ///
+    /// This is useful for APIs whose clients expect errors to be JSON as well. See also the
+    /// `try_or_404_json!` and `try_or_500_json!` macros.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     let page = match request.get_param("page").map(|p| p.parse::<u32>()) {
+    ///         Some(Ok(page)) => page,
+    ///         _ => return Response::json_error(400, "missing or invalid `page` parameter"),
+    ///     };
+    ///
+    ///     Response::json(&page)
+    /// }
+    ///
+    /// let request = Request::fake_http("GET", "/?page=foo", vec![], vec![]);
+    /// let response = handle(&request);
+    /// assert_eq!(response.status_code, 400);
+    /// ```
+    pub fn json_error(status_code: u16, message: &str) -> Response {
+        Response::json(&serde_json::json!({ "error": message })).with_status_code(status_code)
+    }
+
     /// Builds a `Response` that returns a `401 Not Authorized` status
     /// and a `WWW-Authenticate` header.
     ///
@@ -422,6 +585,23 @@ impl Response {
     /// use rouille::Response;
     /// let response = Response::empty_204();
     /// ```
+    ///
+    /// Browsers request `/favicon.ico` for every website they visit. If you don't have one,
+    /// answering with a 204 is cheaper than going through the rest of the handler and avoids
+    /// filling the logs with 404s:
+    ///
+    /// ```
+    /// use rouille::{Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     if request.url() == "/favicon.ico" {
+    ///         return Response::empty_204();
+    ///     }
+    ///
+    ///     // ...
+    /// # Response::empty_404()
+    /// }
+    /// ```
     #[inline]
     pub fn empty_204() -> Response {
         Response {
@@ -450,6 +630,45 @@ impl Response {
         }
     }
 
+    /// Builds an empty `Response` with a 401 status code.
+    ///
+    /// Note that a 401 response is supposed to contain a `WWW-Authenticate` header that indicates
+    /// how to authenticate. See `basic_http_auth_login_required` for basic HTTP authentication.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::empty_401();
+    /// ```
+    #[inline]
+    pub fn empty_401() -> Response {
+        Response {
+            status_code: 401,
+            headers: vec![],
+            data: ResponseBody::empty(),
+            upgrade: None,
+        }
+    }
+
+    /// Builds an empty `Response` with a 403 status code.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::empty_403();
+    /// ```
+    #[inline]
+    pub fn empty_403() -> Response {
+        Response {
+            status_code: 403,
+            headers: vec![],
+            data: ResponseBody::empty(),
+            upgrade: None,
+        }
+    }
+
     /// Builds an empty `Response` with a 404 status code.
     ///
     /// # Example
@@ -468,6 +687,25 @@ impl Response {
         }
     }
 
+    /// Builds an empty `Response` with a 405 status code and an `Allow` header containing the
+    /// given list of methods.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::empty_405(&["GET", "HEAD"]);
+    /// ```
+    #[inline]
+    pub fn empty_405(allowed_methods: &[&str]) -> Response {
+        Response {
+            status_code: 405,
+            headers: vec![("Allow".into(), allowed_methods.join(", ").into())],
+            data: ResponseBody::empty(),
+            upgrade: None,
+        }
+    }
+
     /// Builds an empty `Response` with a 406 status code.
     ///
     /// # Example
@@ -486,6 +724,44 @@ impl Response {
         }
     }
 
+    /// Builds an empty `Response` with a 500 status code.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::empty_500();
+    /// ```
+    #[inline]
+    pub fn empty_500() -> Response {
+        Response {
+            status_code: 500,
+            headers: vec![],
+            data: ResponseBody::empty(),
+            upgrade: None,
+        }
+    }
+
+    /// Builds an empty `Response` with a 503 status code.
+    ///
+    /// You can add a `Retry-After` header to indicate when the client should try again.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::empty_503();
+    /// ```
+    #[inline]
+    pub fn empty_503() -> Response {
+        Response {
+            status_code: 503,
+            headers: vec![],
+            data: ResponseBody::empty(),
+            upgrade: None,
+        }
+    }
+
     /// Changes the status code of the response.
     ///
     /// # Example
@@ -500,7 +776,70 @@ impl Response {
         self
     }
 
+    /// Replaces the body of the response with the given text.
+    ///
+    /// If the response doesn't have a `Content-Type` header, `text/plain; charset=utf-8` is
+    /// added. An existing `Content-Type` is left untouched.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    ///
+    /// let response = Response::redirect_303("/login").with_body_text("Please log in first");
+    /// ```
+    pub fn with_body_text<S>(self, text: S) -> Response
+    where
+        S: Into<String>,
+    {
+        self.with_body(ResponseBody::from_string(text), "text/plain; charset=utf-8")
+    }
+
+    /// Replaces the body of the response with the given data.
+    ///
+    /// If the response doesn't have a `Content-Type` header, `application/octet-stream` is
+    /// added. An existing `Content-Type` is left untouched.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    ///
+    /// let response = Response::html("").with_body_bytes(b"<p>Hello</p>".to_vec());
+    /// ```
+    pub fn with_body_bytes<D>(self, data: D) -> Response
+    where
+        D: Into<Vec<u8>>,
+    {
+        self.with_body(ResponseBody::from_data(data), "application/octet-stream")
+    }
+
+    fn with_body(mut self, data: ResponseBody, default_content_type: &'static str) -> Response {
+        self.data = data;
+        if self
+            .headers
+            .iter()
+            .any(|(h, _)| h.eq_ignore_ascii_case("Content-Type"))
+        {
+            self
+        } else {
+            self.with_additional_header("Content-Type", default_content_type)
+        }
+    }
+
     /// Removes all headers from the response that match `header`.
+    ///
+    /// Header names are compared case-insensitively. This is useful to remove a header added by
+    /// the constructor of the response.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    ///
+    /// let response = Response::text("hello world").without_header("content-type");
+    /// assert!(response.headers.is_empty());
+    /// ```
     pub fn without_header(mut self, header: &str) -> Response {
         self.headers
             .retain(|(h, _)| !h.eq_ignore_ascii_case(header));
@@ -518,7 +857,20 @@ impl Response {
         self
     }
 
-    /// Removes all headers from the response whose names are `header`, and replaces them .
+    /// Sets a header of the response, replacing all the existing headers with the same name.
+    ///
+    /// Header names are compared case-insensitively. If the response already has such a header,
+    /// its first occurrence is kept in place with its original name and gets the new value, and
+    /// the other occurrences are removed. Otherwise the header is added at the end.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    ///
+    /// let response = Response::text("hello world")
+    ///     .with_unique_header("Cache-Control", "no-store");
+    /// ```
     pub fn with_unique_header<H, V>(mut self, header: H, value: V) -> Response
     where
         H: Into<Cow<'static, str>>,
@@ -591,22 +943,22 @@ impl Response {
 
     /// Turns the response into an empty 304 response if the `ETag` that is stored in it matches a
     /// `If-None-Match` header of the request.
+    ///
+    /// `If-None-Match` can contain a comma-separated list of ETags, and matches if any of them
+    /// is the same as the `ETag` of the response. As required for this header, the comparison is
+    /// weak, meaning that the `W/` prefix of weak ETags is ignored. `If-None-Match: *` matches
+    /// every response that has an `ETag`.
     pub fn simplify_if_etag_match(mut self, request: &Request) -> Response {
         if self.status_code < 200 || self.status_code >= 300 {
             return self;
         }
 
-        let mut not_modified = false;
-        for (key, etag) in &self.headers {
-            if !key.eq_ignore_ascii_case("ETag") {
-                continue;
-            }
-
-            not_modified = request
-                .header("If-None-Match")
-                .map(|header| header == etag)
-                .unwrap_or(false);
-        }
+        let not_modified = self
+            .headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("ETag"))
+            .map(|(_, etag)| if_none_match_matches(request, etag))
+            .unwrap_or(false);
 
         if not_modified {
             self.data = ResponseBody::empty();
@@ -628,6 +980,145 @@ impl Response {
         self.with_unique_header("ETag", etag)
     }
 
+    /// Adds a `Last-Modified` header to the response, or replaces an existing header if there is
+    /// one.
+    ///
+    /// If the request has an `If-Modified-Since` header and no `If-None-Match` header, and if the
+    /// resource wasn't modified since that date, the response is turned into an empty 304
+    /// response. HTTP dates have a precision of one second.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::time::SystemTime;
+    /// use rouille::Request;
+    /// use rouille::Response;
+    ///
+    /// fn handle(request: &Request, last_change: SystemTime) -> Response {
+    ///     Response::text("hello world").with_last_modified(request, last_change)
+    /// }
+    /// ```
+    pub fn with_last_modified(self, request: &Request, time: SystemTime) -> Response {
+        let time = chrono::DateTime::<chrono::Utc>::from(time);
+        let mut response = self.with_unique_header(
+            "Last-Modified",
+            time.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        );
+
+        if response.status_code < 200 || response.status_code >= 300 {
+            return response;
+        }
+
+        if request.header("If-None-Match").is_some() {
+            return response;
+        }
+
+        let not_modified = request
+            .header("If-Modified-Since")
+            .and_then(|since| chrono::DateTime::parse_from_rfc2822(since).ok())
+            .map(|since| time.timestamp() <= since.timestamp())
+            .unwrap_or(false);
+
+        if not_modified {
+            response.data = ResponseBody::empty();
+            response.status_code = 304;
+        }
+
+        response
+    }
+
+    /// Answers the `Range` header of the request, if any.
+    ///
+    /// If the request asks for a single range of bytes, the body is seeked to the start of that
+    /// range and limited to its length, and the response becomes a `206 Partial Content` response
+    /// with a `Content-Range` header. If the range can't be satisfied, the response becomes an
+    /// empty `416 Range Not Satisfiable` response. Requests for multiple ranges, malformed `Range`
+    /// headers, and `If-Range` headers that don't match the `ETag` or the `Last-Modified` header
+    /// of the response are answered with the full response.
+    ///
+    /// Only successful responses whose body can seek and whose length is known are affected,
+    /// which is the case of the bodies built with `from_file` and `from_data`. The
+    /// `Accept-Ranges: bytes` header is added to these responses. Other responses are returned
+    /// unchanged and ignore `Range`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use rouille::Request;
+    /// use rouille::Response;
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     let file = File::open("video.webm").unwrap();
+    ///     Response::from_file("video/webm", file).with_ranges(request)
+    /// }
+    /// ```
+    pub fn with_ranges(mut self, request: &Request) -> Response {
+        if self.status_code != 200 {
+            return self;
+        }
+
+        let total = match (&self.data.data, self.data.data_length) {
+            (&ResponseBodyData::Seekable(_), Some(len)) => len as u64,
+            _ => return self,
+        };
+
+        self = self.with_unique_header("Accept-Ranges", "bytes");
+
+        let range = match request.header("Range") {
+            Some(r) => r,
+            None => return self,
+        };
+
+        if let Some(if_range) = request.header("If-Range") {
+            let matches = self.headers.iter().any(|(key, value)| {
+                (key.eq_ignore_ascii_case("ETag") || key.eq_ignore_ascii_case("Last-Modified"))
+                    && value == if_range
+            });
+            if !matches {
+                return self;
+            }
+        }
+
+        let (start, end) = match parse_range_header(range, total) {
+            Some(Some(range)) => range,
+            Some(None) => {
+                return Response {
+                    status_code: 416,
+                    data: ResponseBody::empty(),
+                    ..self
+                }
+                .with_unique_header("Content-Range", format!("bytes */{}", total));
+            }
+            None => return self,
+        };
+
+        let mut data = match self.data.data {
+            ResponseBodyData::Seekable(data) => data,
+            ResponseBodyData::Reader(_) => unreachable!(),
+        };
+
+        if data.seek(SeekFrom::Start(start)).is_err() {
+            return Response {
+                status_code: 500,
+                headers: vec![],
+                data: ResponseBody::empty(),
+                upgrade: None,
+            };
+        }
+
+        let len = end - start + 1;
+        Response {
+            status_code: 206,
+            data: ResponseBody::from_reader_and_size(data.take(len), len as usize),
+            ..self
+        }
+        .with_unique_header(
+            "Content-Range",
+            format!("bytes {}-{}/{}", start, end, total),
+        )
+    }
+
     /// Adds or replace a `Content-Disposition` header of the response. Tells the browser that the
     /// body of the request should fire a download popup instead of being shown in the browser.
     ///
@@ -704,12 +1195,177 @@ impl Response {
 
     /// Adds or replaces a `Cache-Control` header that specifies that the client must not cache
     /// the resource.
+    ///
+    /// For the sake of HTTP/1.0 clients and proxies, this also adds or replaces the `Pragma` and
+    /// `Expires` headers.
     #[inline]
     pub fn with_no_cache(self) -> Response {
-        self.with_unique_header("Cache-Control", "no-cache, no-store, must-revalidate")
+        self.with_unique_header("Cache-Control", "no-store, no-cache, must-revalidate")
             .with_unique_header("Expires", "0")
             .with_unique_header("Pragma", "no-cache")
     }
+
+    /// Adds or replaces an `X-Content-Type-Options: nosniff` header, which forbids browsers
+    /// from guessing the type of the content when it doesn't match the `Content-Type`.
+    ///
+    /// Without it, a file uploaded by a user as an image could for example be interpreted as a
+    /// script by some browsers.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    ///
+    /// let response = Response::from_data("image/png", vec![]).with_nosniff();
+    /// ```
+    #[inline]
+    pub fn with_nosniff(self) -> Response {
+        self.with_unique_header("X-Content-Type-Options", "nosniff")
+    }
+
+    /// Adds or replaces a `Strict-Transport-Security` header, which tells browsers to only
+    /// connect to this domain through HTTPS for the next `max_age_seconds` seconds.
+    ///
+    /// If `include_subdomains` is true, the rule also applies to all the subdomains. `preload`
+    /// indicates that the domain can be added to the preload lists of browsers, which also
+    /// requires `include_subdomains` and a `max_age_seconds` of at least one year.
+    ///
+    /// This header should only be sent in responses to HTTPS requests, as browsers ignore it
+    /// otherwise. Use `with_hsts_if_secure` to only add it when this is the case.
+    ///
+    /// > **Note**: Browsers remember this header. Start with a small `max_age_seconds`, as
+    /// > clients won't be able to reach the HTTP version of the website anymore until it expires.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    ///
+    /// let response = Response::text("hello").with_hsts(31536000, true, false);
+    /// ```
+    pub fn with_hsts(
+        self,
+        max_age_seconds: u64,
+        include_subdomains: bool,
+        preload: bool,
+    ) -> Response {
+        let mut value = format!("max-age={}", max_age_seconds);
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if preload {
+            value.push_str("; preload");
+        }
+        self.with_unique_header("Strict-Transport-Security", value)
+    }
+
+    /// Same as `with_hsts`, but only adds the header if the request was made through HTTPS.
+    ///
+    /// See `Request::is_secure`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    /// use rouille::Response;
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     Response::text("hello").with_hsts_if_secure(request, 31536000, true, false)
+    /// }
+    /// ```
+    pub fn with_hsts_if_secure(
+        self,
+        request: &Request,
+        max_age_seconds: u64,
+        include_subdomains: bool,
+        preload: bool,
+    ) -> Response {
+        if request.is_secure() {
+            self.with_hsts(max_age_seconds, include_subdomains, preload)
+        } else {
+            self
+        }
+    }
+}
+
+// Returns true if one of the `If-None-Match` headers of the request matches `etag`, using the weak
+// comparison.
+fn if_none_match_matches(request: &Request, etag: &str) -> bool {
+    fn opaque_tag(tag: &str) -> &str {
+        let tag = tag.trim();
+        tag.strip_prefix("W/").unwrap_or(tag)
+    }
+
+    let etag = opaque_tag(etag);
+
+    request
+        .headers()
+        .filter(|(key, _)| key.eq_ignore_ascii_case("If-None-Match"))
+        .flat_map(|(_, value)| split_etags(value))
+        .any(|tag| tag == "*" || opaque_tag(tag) == etag)
+}
+
+// Splits a comma-separated list of ETags. Commas between double quotes are part of the ETag.
+pub(crate) fn split_etags(value: &str) -> Vec<&str> {
+    let mut tags = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+
+    for (pos, c) in value.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                tags.push(value[start..pos].trim());
+                start = pos + 1;
+            }
+            _ => (),
+        }
+    }
+
+    tags.push(value[start..].trim());
+    tags.retain(|tag| !tag.is_empty());
+    tags
+}
+
+// Parses the value of a `Range` header for a body of `total` bytes.
+//
+// Returns `None` if the header should be ignored, `Some(None)` if the range can't be satisfied, and
+// the first and last byte of the range otherwise.
+fn parse_range_header(header: &str, total: u64) -> Option<Option<(u64, u64)>> {
+    let range = header.trim().strip_prefix("bytes=")?;
+    if range.contains(',') {
+        return None;
+    }
+
+    let mut parts = range.splitn(2, '-');
+    let first = parts.next()?.trim();
+    let last = parts.next()?.trim();
+
+    if first.is_empty() {
+        // Suffix range, for example `bytes=-500` for the last 500 bytes.
+        let suffix: u64 = last.parse().ok()?;
+        if suffix == 0 || total == 0 {
+            return Some(None);
+        }
+        return Some(Some((total.saturating_sub(suffix), total - 1)));
+    }
+
+    let start: u64 = first.parse().ok()?;
+    let end = if last.is_empty() {
+        None
+    } else {
+        Some(last.parse::<u64>().ok()?)
+    };
+
+    if end.map_or(false, |end| end < start) {
+        return None;
+    }
+    if start >= total {
+        return Some(None);
+    }
+
+    let end = end.map_or(total - 1, |end| end.min(total - 1));
+    Some(Some((start, end)))
 }
 
 /// An opaque type that represents the body of a response.
@@ -724,10 +1380,19 @@ impl Response {
 /// let body = ResponseBody::from_string("hello world");
 /// ```
 pub struct ResponseBody {
-    data: Box<dyn Read + Send>,
+    data: ResponseBodyData,
     data_length: Option<usize>,
 }
 
+pub(crate) enum ResponseBodyData {
+    Reader(Box<dyn Read + Send>),
+    // Bodies that can seek, which makes it possible to answer `Range` requests.
+    Seekable(Box<dyn ReadSeek + Send>),
+}
+
+pub(crate) trait ReadSeek: Read + Seek {}
+impl<T> ReadSeek for T where T: Read + Seek {}
+
 impl ResponseBody {
     /// Builds a `ResponseBody` that doesn't return any data.
     ///
@@ -740,7 +1405,7 @@ impl ResponseBody {
     #[inline]
     pub fn empty() -> ResponseBody {
         ResponseBody {
-            data: Box::new(io::empty()),
+            data: ResponseBodyData::Reader(Box::new(io::empty())),
             data_length: Some(0),
         }
     }
@@ -750,6 +1415,11 @@ impl ResponseBody {
     /// Note that this is suboptimal compared to other constructors because the length
     /// isn't known in advance.
     ///
+    /// The server reads from `data` only as fast as the client receives the body. When the client
+    /// is slow, writing to the socket blocks once the buffers of the operating system are full,
+    /// and the reader isn't called again until there is room. The part of the body that is
+    /// waiting in memory is therefore bounded by the size of these buffers.
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -765,16 +1435,20 @@ impl ResponseBody {
         R: Read + Send + 'static,
     {
         ResponseBody {
-            data: Box::new(data),
+            data: ResponseBodyData::Reader(Box::new(data)),
             data_length: None,
         }
     }
 
-    /// Builds a new `ResponseBody` that will read the data from a `Read`.
+    /// Builds a new `ResponseBody` that will read the data from a `Read`, whose length is known
+    /// in advance.
+    ///
+    /// Contrary to `from_reader`, this makes it possible to send a `Content-Length` header. Note
+    /// that the server may still decide to use chunked encoding, for example for large bodies.
     ///
-    /// The caller must provide the content length. It is unspecified
-    /// what will happen if the content length does not match the actual
-    /// length of the data returned from the reader.
+    /// The caller is responsible for providing the right length. It is unspecified what will
+    /// happen if the content length does not match the actual length of the data returned from
+    /// the reader, but the client will most likely receive a broken response.
     ///
     /// # Example
     ///
@@ -791,7 +1465,7 @@ impl ResponseBody {
         R: Read + Send + 'static,
     {
         ResponseBody {
-            data: Box::new(data),
+            data: ResponseBodyData::Reader(Box::new(data)),
             data_length: Some(size),
         }
     }
@@ -813,11 +1487,31 @@ impl ResponseBody {
         let len = data.len();
 
         ResponseBody {
-            data: Box::new(Cursor::new(data)),
+            data: ResponseBodyData::Seekable(Box::new(Cursor::new(data))),
             data_length: Some(len),
         }
     }
 
+    /// Builds a new `ResponseBody` that returns the given static data, without copying it.
+    ///
+    /// This is typically used with `include_bytes!` in order to serve files embedded in the
+    /// executable.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::ResponseBody;
+    /// static LOGO: &[u8] = b"\x89PNG...";
+    /// let body = ResponseBody::from_static(LOGO);
+    /// ```
+    #[inline]
+    pub fn from_static(data: &'static [u8]) -> ResponseBody {
+        ResponseBody {
+            data: ResponseBodyData::Seekable(Box::new(Cursor::new(data))),
+            data_length: Some(data.len()),
+        }
+    }
+
     /// Builds a new `ResponseBody` that returns the content of the given file.
     ///
     /// # Example
@@ -834,7 +1528,7 @@ impl ResponseBody {
         let len = file.metadata().map(|metadata| metadata.len() as usize).ok();
 
         ResponseBody {
-            data: Box::new(file),
+            data: ResponseBodyData::Seekable(Box::new(file)),
             data_length: len,
         }
     }
@@ -861,13 +1555,401 @@ impl ResponseBody {
     /// unknown.
     #[inline]
     pub fn into_reader_and_size(self) -> (Box<dyn Read + Send>, Option<usize>) {
-        (self.data, self.data_length)
+        let data: Box<dyn Read + Send> = match self.data {
+            ResponseBodyData::Reader(data) => data,
+            ResponseBodyData::Seekable(data) => Box::new(data),
+        };
+
+        (data, self.data_length)
+    }
+
+    // Replaces the data of the body with the result of `wrap`, keeping its length.
+    pub(crate) fn map_data<F>(self, wrap: F) -> ResponseBody
+    where
+        F: FnOnce(ResponseBodyData) -> ResponseBodyData,
+    {
+        ResponseBody {
+            data: wrap(self.data),
+            data_length: self.data_length,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+    use Request;
     use Response;
+    use ResponseBody;
+
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!(
+            "rouille-response-{}-{}",
+            ::std::process::id(),
+            name
+        ));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| &v[..])
+    }
+
+    #[test]
+    fn empty_204() {
+        let response = Response::empty_204();
+        assert_eq!(response.status_code, 204);
+        assert!(response.headers.is_empty());
+        assert_eq!(response.data.data_length, Some(0));
+        assert_eq!(body_of(response), b"");
+    }
+
+    #[test]
+    fn empty_statuses() {
+        let responses = [
+            (Response::empty_401(), 401),
+            (Response::empty_500(), 500),
+            (Response::empty_503(), 503),
+        ];
+        for (response, status) in responses {
+            assert_eq!(response.status_code, status);
+            assert!(response.headers.is_empty());
+            assert_eq!(body_of(response), b"");
+        }
+    }
+
+    #[test]
+    fn with_nosniff() {
+        let response = Response::text("hello")
+            .with_additional_header("x-content-type-options", "foo")
+            .with_nosniff();
+        assert_eq!(header(&response, "X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(response.headers.len(), 2);
+    }
+
+    #[test]
+    fn with_hsts() {
+        let cases = [
+            (false, false, "max-age=60"),
+            (true, false, "max-age=60; includeSubDomains"),
+            (false, true, "max-age=60; preload"),
+            (true, true, "max-age=60; includeSubDomains; preload"),
+        ];
+        for &(include_subdomains, preload, expected) in &cases {
+            let response = Response::text("").with_hsts(60, include_subdomains, preload);
+            assert_eq!(
+                header(&response, "Strict-Transport-Security"),
+                Some(expected)
+            );
+        }
+
+        let response = Response::text("")
+            .with_hsts(60, false, false)
+            .with_hsts(31536000, true, true);
+        assert_eq!(
+            header(&response, "Strict-Transport-Security"),
+            Some("max-age=31536000; includeSubDomains; preload")
+        );
+        assert_eq!(response.headers.len(), 2);
+    }
+
+    #[test]
+    fn with_hsts_if_secure() {
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let response = Response::text("").with_hsts_if_secure(&request, 60, true, false);
+        assert_eq!(header(&response, "Strict-Transport-Security"), None);
+
+        let request = Request::fake_https("GET", "/", vec![], vec![]);
+        let response = Response::text("").with_hsts_if_secure(&request, 60, true, false);
+        assert_eq!(
+            header(&response, "Strict-Transport-Security"),
+            Some("max-age=60; includeSubDomains")
+        );
+    }
+
+    #[test]
+    fn with_body_text() {
+        let response = Response::redirect_303("/foo").with_body_text("See /foo");
+        assert_eq!(response.status_code, 303);
+        assert_eq!(header(&response, "Location"), Some("/foo"));
+        assert_eq!(
+            header(&response, "Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(response.data.data_length, Some(8));
+        assert_eq!(body_of(response), b"See /foo");
+    }
+
+    #[test]
+    fn with_body_bytes() {
+        let response = Response::redirect_302("/foo").with_body_bytes(vec![1, 2, 3]);
+        assert_eq!(response.status_code, 302);
+        assert_eq!(
+            header(&response, "Content-Type"),
+            Some("application/octet-stream")
+        );
+        assert_eq!(body_of(response), [1, 2, 3]);
+
+        // An existing `Content-Type` is kept.
+        let response = Response::html("<p>old</p>").with_body_bytes(&b"<p>new</p>"[..]);
+        assert_eq!(
+            header(&response, "Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(response.headers.len(), 1);
+        assert_eq!(body_of(response), b"<p>new</p>");
+    }
+
+    #[test]
+    fn redirect_to_https() {
+        let request = |host: &str| {
+            Request::fake_http(
+                "POST",
+                "/foo/bar?a=b&c=d",
+                vec![("Host".to_owned(), host.to_owned())],
+                vec![],
+            )
+        };
+
+        let response = Response::redirect_to_https(&request("example.com"));
+        assert_eq!(response.status_code, 308);
+        assert_eq!(
+            header(&response, "Location"),
+            Some("https://example.com/foo/bar?a=b&c=d")
+        );
+
+        let response = Response::redirect_to_https(&request("example.com:8080"));
+        assert_eq!(
+            header(&response, "Location"),
+            Some("https://example.com/foo/bar?a=b&c=d")
+        );
+
+        let response = Response::redirect_to_https(&request("[::1]:80"));
+        assert_eq!(
+            header(&response, "Location"),
+            Some("https://[::1]/foo/bar?a=b&c=d")
+        );
+    }
+
+    #[test]
+    fn redirect_to_https_bad_host() {
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        assert_eq!(Response::redirect_to_https(&request).status_code, 400);
+
+        for host in &["", "evil.com/x", "user@evil.com", "[::1"] {
+            let request = Request::fake_http(
+                "GET",
+                "/",
+                vec![("Host".to_owned(), host.to_string())],
+                vec![],
+            );
+            assert_eq!(Response::redirect_to_https(&request).status_code, 400);
+        }
+    }
+
+    #[test]
+    fn from_path() {
+        let path = temp_file("page.html", b"<p>hello</p>");
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+
+        let response = Response::from_path(&request, &path);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            header(&response, "Content-Type"),
+            Some("text/html; charset=utf8")
+        );
+        assert!(header(&response, "ETag").is_some());
+        assert!(header(&response, "Last-Modified").is_some());
+
+        let (mut body, len) = response.data.into_reader_and_size();
+        assert_eq!(len, Some(12));
+        let mut content = Vec::new();
+        body.read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"<p>hello</p>");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn from_path_not_modified() {
+        let path = temp_file("style.css", b"body {}");
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let response = Response::from_path(&request, &path);
+
+        let etag = header(&response, "ETag").unwrap().to_owned();
+        let request =
+            Request::fake_http("GET", "/", vec![("If-None-Match".to_owned(), etag)], vec![]);
+        assert_eq!(Response::from_path(&request, &path).status_code, 304);
+
+        let last_modified = header(&response, "Last-Modified").unwrap().to_owned();
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("If-Modified-Since".to_owned(), last_modified)],
+            vec![],
+        );
+        assert_eq!(Response::from_path(&request, &path).status_code, 304);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn from_path_missing() {
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let response = Response::from_path(&request, "/this/file/does/not/exist.txt");
+        assert_eq!(response.status_code, 404);
+
+        let response = Response::from_path(&request, env::temp_dir());
+        assert_eq!(response.status_code, 404);
+    }
+
+    fn body_of(response: Response) -> Vec<u8> {
+        let mut content = Vec::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_end(&mut content)
+            .unwrap();
+        content
+    }
+
+    fn range_request(range: &str) -> Request {
+        Request::fake_http(
+            "GET",
+            "/",
+            vec![("Range".to_owned(), range.to_owned())],
+            vec![],
+        )
+    }
+
+    #[test]
+    fn from_static() {
+        static DATA: &[u8] = b"0123456789";
+
+        let response = Response::from_parts(200, vec![], ResponseBody::from_static(DATA));
+        assert_eq!(response.data.data_length, Some(10));
+        assert_eq!(body_of(response), DATA);
+
+        let response = Response::from_parts(200, vec![], ResponseBody::from_static(DATA))
+            .with_ranges(&range_request("bytes=2-5"));
+        assert_eq!(response.status_code, 206);
+        assert_eq!(body_of(response), b"2345");
+    }
+
+    #[test]
+    fn ranges_file() {
+        let path = temp_file("ranges.txt", b"0123456789");
+        let file = fs::File::open(&path).unwrap();
+
+        let response =
+            Response::from_file("text/plain", file).with_ranges(&range_request("bytes=2-5"));
+        assert_eq!(response.status_code, 206);
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 2-5/10"));
+        assert_eq!(header(&response, "Accept-Ranges"), Some("bytes"));
+        assert_eq!(response.data.data_length, Some(4));
+        assert_eq!(body_of(response), b"2345");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ranges_forms() {
+        let response = Response::text("0123456789").with_ranges(&range_request("bytes=7-"));
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 7-9/10"));
+        assert_eq!(body_of(response), b"789");
+
+        let response = Response::text("0123456789").with_ranges(&range_request("bytes=-3"));
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 7-9/10"));
+        assert_eq!(body_of(response), b"789");
+
+        let response = Response::text("0123456789").with_ranges(&range_request("bytes=8-100"));
+        assert_eq!(header(&response, "Content-Range"), Some("bytes 8-9/10"));
+        assert_eq!(body_of(response), b"89");
+    }
+
+    #[test]
+    fn ranges_not_satisfiable() {
+        let response = Response::text("0123456789").with_ranges(&range_request("bytes=10-"));
+        assert_eq!(response.status_code, 416);
+        assert_eq!(header(&response, "Content-Range"), Some("bytes */10"));
+        assert_eq!(body_of(response), b"");
+    }
+
+    #[test]
+    fn ranges_ignored() {
+        for range in &["bytes=0-1,4-5", "bytes=5-2", "lines=1-2", "bytes=a-b"] {
+            let response = Response::text("0123456789").with_ranges(&range_request(range));
+            assert_eq!(response.status_code, 200);
+            assert_eq!(body_of(response), b"0123456789");
+        }
+
+        // Bodies that can't seek ignore the range.
+        let response = Response {
+            data: ResponseBody::from_reader(&b"0123456789"[..]),
+            ..Response::text("")
+        }
+        .with_ranges(&range_request("bytes=2-5"));
+        assert_eq!(response.status_code, 200);
+        assert!(header(&response, "Accept-Ranges").is_none());
+        assert_eq!(body_of(response), b"0123456789");
+    }
+
+    #[test]
+    fn ranges_if_range() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![
+                ("Range".to_owned(), "bytes=2-5".to_owned()),
+                ("If-Range".to_owned(), "\"abc\"".to_owned()),
+            ],
+            vec![],
+        );
+
+        let response = Response::text("0123456789")
+            .with_etag_keep("\"abc\"")
+            .with_ranges(&request);
+        assert_eq!(response.status_code, 206);
+
+        let response = Response::text("0123456789")
+            .with_etag_keep("\"def\"")
+            .with_ranges(&request);
+        assert_eq!(response.status_code, 200);
+    }
+
+    #[test]
+    fn last_modified() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let response = Response::text("hello").with_last_modified(&request, time);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            header(&response, "Last-Modified"),
+            Some("Sun, 06 Nov 1994 08:49:37 GMT")
+        );
+
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![(
+                "If-Modified-Since".to_owned(),
+                "Sun, 06 Nov 1994 08:49:36 GMT".to_owned(),
+            )],
+            vec![],
+        );
+        let response = Response::text("hello").with_last_modified(&request, time);
+        assert_eq!(response.status_code, 200);
+    }
 
     #[test]
     fn unique_header_adds() {
@@ -912,4 +1994,150 @@ mod tests {
         assert_eq!(r.headers.len(), 1);
         assert_eq!(r.headers[0], ("foo".into(), "Bar".into()));
     }
+
+    #[test]
+    fn json_error() {
+        let response = Response::json_error(404, "no such \"user\"");
+        assert_eq!(response.status_code, 404);
+        assert_eq!(
+            header(&response, "Content-Type"),
+            Some("application/json; charset=utf-8")
+        );
+        assert_eq!(body_of(response), br#"{"error":"no such \"user\""}"#);
+    }
+
+    #[test]
+    fn from_parts() {
+        let response = Response::from_parts(
+            418,
+            vec![("X-Teapot".into(), "yes".into())],
+            ResponseBody::from_string("I'm a teapot"),
+        );
+        assert_eq!(response.status_code, 418);
+        assert_eq!(header(&response, "X-Teapot"), Some("yes"));
+        assert!(response.upgrade.is_none());
+        assert_eq!(body_of(response), b"I'm a teapot");
+    }
+
+    #[test]
+    fn without_header() {
+        let r = Response::text("hello")
+            .with_additional_header("X-Foo", "a")
+            .with_additional_header("x-foo", "b")
+            .without_header("CONTENT-TYPE")
+            .without_header("X-FOO");
+
+        assert!(r.headers.is_empty(), "{:?}", r.headers);
+    }
+
+    fn if_none_match_status(etag: &str, if_none_match: &[&str]) -> u16 {
+        let headers = if_none_match
+            .iter()
+            .map(|v| ("If-None-Match".to_owned(), v.to_string()))
+            .collect();
+        let request = Request::fake_http("GET", "/", headers, vec![]);
+        Response::text("hello")
+            .with_etag(&request, etag.to_owned())
+            .status_code
+    }
+
+    #[test]
+    fn etag_list() {
+        assert_eq!(if_none_match_status("\"b\"", &["\"a\", \"b\", \"c\""]), 304);
+        assert_eq!(if_none_match_status("\"b\"", &["\"a\",\"c\""]), 200);
+        assert_eq!(if_none_match_status("\"b\"", &["\"a\"", "\"b\""]), 304);
+        assert_eq!(if_none_match_status("\"a,b\"", &["\"a,b\""]), 304);
+        assert_eq!(if_none_match_status("\"b\"", &["\"a,b\""]), 200);
+    }
+
+    #[test]
+    fn etag_weak() {
+        assert_eq!(if_none_match_status("\"a\"", &["W/\"a\""]), 304);
+        assert_eq!(if_none_match_status("W/\"a\"", &["\"a\""]), 304);
+        assert_eq!(if_none_match_status("W/\"a\"", &["\"x\", W/\"a\""]), 304);
+        assert_eq!(if_none_match_status("W/\"a\"", &["W/\"b\""]), 200);
+    }
+
+    #[test]
+    fn etag_star() {
+        assert_eq!(if_none_match_status("\"a\"", &["*"]), 304);
+        assert_eq!(if_none_match_status("\"a\"", &[]), 200);
+
+        // Only successful responses are affected.
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("If-None-Match".to_owned(), "*".to_owned())],
+            vec![],
+        );
+        let response = Response::empty_404().with_etag(&request, "\"a\"");
+        assert_eq!(response.status_code, 404);
+    }
+
+    #[test]
+    fn etag_keep_replaces() {
+        let r = Response::text("hello")
+            .with_additional_header("etag", "\"a\"")
+            .with_additional_header("ETAG", "\"b\"")
+            .with_etag_keep("\"c\"");
+
+        let etags = r
+            .headers
+            .iter()
+            .filter(|(h, _)| h.eq_ignore_ascii_case("ETag"))
+            .collect::<Vec<_>>();
+        assert_eq!(etags.len(), 1);
+        assert_eq!(etags[0].1, "\"c\"");
+    }
+
+    #[test]
+    fn no_cache() {
+        let r = Response::text("hello")
+            .with_public_cache(60)
+            .with_additional_header("pragma", "foo")
+            .with_no_cache();
+
+        assert_eq!(
+            header(&r, "Cache-Control"),
+            Some("no-store, no-cache, must-revalidate")
+        );
+        assert_eq!(header(&r, "Pragma"), Some("no-cache"));
+        assert_eq!(header(&r, "Expires"), Some("0"));
+        assert_eq!(r.headers.len(), 4);
+    }
+
+    #[test]
+    fn public_cache() {
+        let r = Response::text("hello")
+            .with_no_cache()
+            .with_public_cache(3600);
+
+        assert_eq!(header(&r, "Cache-Control"), Some("public, max-age=3600"));
+        assert_eq!(header(&r, "Pragma"), None);
+        assert_eq!(header(&r, "Expires"), None);
+    }
+
+    #[test]
+    fn json() {
+        #[derive(Serialize)]
+        struct Data {
+            name: &'static str,
+            values: Vec<u32>,
+        }
+
+        let r = Response::json(&Data {
+            name: "hello \"world\"",
+            values: vec![1, 2],
+        });
+
+        assert_eq!(r.status_code, 200);
+        assert_eq!(
+            header(&r, "Content-Type"),
+            Some("application/json; charset=utf-8")
+        );
+        assert_eq!(
+            body_of(r),
+            &b"{\"name\":\"hello \\\"world\\\"\",\"values\":[1,2]}"[..]
+        );
+    }
 }
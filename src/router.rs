@@ -7,6 +7,9 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
+//! Everything in this module is private, but is still publicly accessible from the outside
+//! because of the `router!` macro.
+
 /// Equivalent to a `match` expression but for routes.
 ///
 /// # Example
@@ -50,8 +53,8 @@
 /// If you use parameters inside `{}`, then a variable with the same name will be available in the
 /// code in the body.
 /// Each parameter gets parsed through the `FromStr` trait. If the parsing fails, the route is
-/// ignored. If you get an error because the type of the parameter couldn't be inferred, you can
-/// also specify the type inside the brackets:
+/// ignored and the next routes are tried. If you get an error because the type of the parameter
+/// couldn't be inferred, you can also specify the type inside the brackets:
 ///
 /// ```ignore
 /// (GET) (/{id: u32}/foo) => {
@@ -59,6 +62,43 @@
 /// },
 /// ```
 ///
+/// Any type that implements `FromStr` can be used, such as the integer types, `String` (which
+/// accepts any segment), or for example the `Uuid` type of the `uuid` crate:
+///
+/// ```
+/// # #[macro_use] extern crate rouille; fn main() {
+/// # let request = rouille::Request::fake_http("GET", "/users/abc", vec![], vec![]);
+/// let result = router!(request,
+///     (GET) (/users/{id: u32}) => { format!("user number {}", id) },
+///     (GET) (/users/{name: String}) => { format!("user named {}", name) },
+///     _ => String::new()
+/// );
+/// assert_eq!(result, "user named abc");
+/// # }
+/// ```
+///
+/// The last segment of a route can also be a catch-all parameter written `{..name}`. It matches
+/// the rest of the URL, including the slashes, and binds it to a `String`:
+///
+/// ```ignore
+/// (GET) (/files/{..path}) => {
+///     // a request to `/files/a/b/c.txt` gives `path == "a/b/c.txt"`
+///     ...
+/// },
+/// ```
+///
+/// A catch-all parameter is only allowed as the last segment of a route:
+///
+/// ```compile_fail
+/// # #[macro_use] extern crate rouille; fn main() {
+/// # let request = rouille::Request::fake_http("GET", "/", vec![], vec![]);
+/// router!(request,
+///     (GET) (/files/{..path}/foo) => { () },
+///     _ => ()
+/// );
+/// # }
+/// ```
+///
 ///
 /// # Alternative syntax (**string-style**)
 ///
@@ -82,6 +122,70 @@
 /// },
 /// ```
 ///
+/// Catch-all parameters are supported as well, for example
+/// `(GET) ["/files/{..path}", path: String]`.
+///
+/// # Method not allowed
+///
+/// By default, if the URL of the request matches a route but its method doesn't, the route is
+/// ignored like any other non-matching route and the default handler is eventually called.
+///
+/// You can instead add a `405 => ...` handler right before the default handler. It must be a
+/// function or a closure that is called with the list of the methods of all the routes that match
+/// the URL of the request, and is used if none of the routes match both the URL and the method.
+/// This is typically used to return a `405 Method Not Allowed` response with an `Allow` header:
+///
+/// ```
+/// # #[macro_use] extern crate rouille; fn main() {
+/// use rouille::Response;
+/// # let request = rouille::Request::fake_http("POST", "/hello", vec![], vec![]);
+///
+/// let response = router!(request,
+///     (GET) (/hello) => { Response::text("hello") },
+///     (PUT) (/hello) => { Response::empty_204() },
+///     405 => Response::empty_405,
+///     _ => Response::empty_404()
+/// );
+///
+/// assert_eq!(response.status_code, 405);
+/// # }
+/// ```
+///
+/// # Methods
+///
+/// The method of a route can be any method name, including `PATCH` or `OPTIONS` (for example to
+/// answer CORS preflight requests). Methods are case-sensitive.
+///
+/// A route can accept several methods by separating them with `|`:
+///
+/// ```
+/// # #[macro_use] extern crate rouille; fn main() {
+/// # let request = rouille::Request::fake_http("PUT", "/hello", vec![], vec![]);
+/// let result = router!(request,
+///     (POST|PUT) (/hello) => { "write" },
+///     _ => "other"
+/// );
+/// assert_eq!(result, "write");
+/// # }
+/// ```
+///
+/// # HEAD requests
+///
+/// A `HEAD` request is routed exactly like a `GET` request, so that a `(GET)` route also answers
+/// `HEAD` requests. The server then sends the headers of the response, including its
+/// `Content-Length`, but not its body.
+///
+/// # Trailing slashes
+///
+/// A route whose pattern doesn't end with a `/` also matches the same URL followed by a trailing
+/// `/`. For example `(GET) (/foo)` or `(GET) ["/foo"]` match both `/foo` and `/foo/`.
+///
+/// On the other hand a route whose pattern ends with a `/`, such as `(GET) (/foo/)`, only
+/// matches URLs that end with a `/`.
+///
+/// A catch-all `{..name}` parameter is not affected by this rule: the trailing `/` is part of
+/// the captured value.
+///
 /// # Some other things to note
 ///
 /// - The right of the `=>` must be a block (must be surrounded by `{` and `}`).
@@ -93,6 +197,7 @@
 /// - When using URL parameters with **string-style** syntax, the parameter names in the URL and `identity: type`
 ///   pairs must be the same, e.g. `... ["/users/{name}", name: String] ...` .
 ///   This can't be checked at compile time so bad route definitions will cause a runtime `panic`.
+///   The same goes for a catch-all `{..name}` parameter that is not the last segment of the URL.
 ///
 #[macro_export]
 macro_rules! router {
@@ -100,7 +205,8 @@ macro_rules! router {
     // --- New style ---
     // -----------------
     ($request:expr,
-     $(($method:ident) [$url_pattern:expr $(, $param:ident: $param_type:ty)*] => $handle:expr,)*
+     $(($($method:ident)|+) [$url_pattern:expr $(, $param:ident: $param_type:ty)*] => $handle:expr,)*
+     $(405 => $not_allowed:expr,)?
      _ => $default:expr $(,)*) => {
         {
             let request = &$request;
@@ -114,11 +220,31 @@ macro_rules! router {
 
             let mut ret = None;
             $({
-                if ret.is_none() && request.method() == stringify!($method) {
+                if ret.is_none() && $crate::router!(__method_matches request, $($method)|+) {
                     ret = $crate::router!(__param_dispatch request_url, $url_pattern => $handle ; $($param: $param_type),*);
                 }
             })+
 
+            // Methods of the routes whose url matches the request, if a `405` handler is present.
+            let mut allowed: Vec<&'static str> = Vec::new();
+            if ret.is_none() && $crate::router!(__has_not_allowed $($not_allowed)?) {
+                $({
+                    if $crate::router!(__param_dispatch request_url, $url_pattern => { $(let _ = &$param;)* } ; $($param: $param_type),*).is_some() {
+                        $crate::router!(__push_allowed allowed, $($method)|+);
+                    }
+                })+
+
+                if allowed.contains(&"GET") && !allowed.contains(&"HEAD") {
+                    allowed.push("HEAD");
+                }
+            }
+
+            $(
+                if ret.is_none() && !allowed.is_empty() {
+                    ret = Some($crate::router::call_not_allowed($not_allowed, &allowed));
+                }
+            )?
+
             if let Some(ret) = ret {
                 ret
             } else {
@@ -127,6 +253,27 @@ macro_rules! router {
         }
     };
 
+    // `HEAD` requests are routed like `GET` requests. The body of the response is then discarded
+    // when it is sent to the client.
+    (__method_matches $request:ident, $($method:ident)|+) => {
+        {
+            let method = $request.method();
+            false $(|| method == stringify!($method)
+                || (stringify!($method) == "GET" && method == "HEAD"))+
+        }
+    };
+
+    (__push_allowed $allowed:ident, $($method:ident)|+) => {
+        $(
+            if !$allowed.contains(&stringify!($method)) {
+                $allowed.push(stringify!($method));
+            }
+        )+
+    };
+
+    (__has_not_allowed $not_allowed:expr) => { true };
+    (__has_not_allowed) => { false };
+
     // No url parameters, just check the url and evaluate the `$handle`
     (__param_dispatch $request_url:ident, $url_pattern:expr => $handle:expr ; ) => {
         $crate::router!(__check_url_match $request_url, $url_pattern => $handle)
@@ -137,11 +284,17 @@ macro_rules! router {
         $crate::router!(__check_parse_pattern $request_url, $url_pattern => $handle ; $($param: $param_type),*)
     };
 
+    // A pattern without a trailing `/` also matches the url with a trailing `/`.
     (__check_url_match $request_url:ident, $url_pattern:expr => $handle:expr) => {
-        if $request_url == $url_pattern {
-            Some($handle)
-        } else {
-            None
+        {
+            let url_pattern: &str = $url_pattern;
+            if $request_url == url_pattern
+                || (!url_pattern.ends_with('/') && $request_url.strip_suffix('/') == Some(url_pattern))
+            {
+                Some($handle)
+            } else {
+                None
+            }
         }
     };
 
@@ -154,11 +307,29 @@ macro_rules! router {
     //       and we can't actually "return None" since we'd be returning from whatever scope the macro is being used in.
     (__check_parse_pattern $request_url_str:ident, $url_pattern:expr => $handle:expr ; $($param:ident: $param_type:ty),*) => {
         {
-            let request_url = $request_url_str.split("/")
+            let mut request_url = $request_url_str.split("/")
                 .map(|s| $crate::percent_encoding::percent_decode(s.as_bytes()).decode_utf8_lossy().into_owned())
                 .collect::<Vec<_>>();
             let url_pattern = $url_pattern.split("/").collect::<Vec<_>>();
-            if request_url.len() != url_pattern.len() {
+            // A `{..name}` segment captures the rest of the url and must be the last one.
+            let catch_all = url_pattern.iter().position(|s| s.starts_with("{.."));
+            if catch_all.map_or(false, |pos| pos + 1 != url_pattern.len()) {
+                panic!("Catch-all url parameter must be the last segment of url: {:?}", $url_pattern);
+            }
+            // A pattern without a trailing `/` also matches the url with a trailing `/`.
+            if catch_all.is_none()
+                && request_url.len() == url_pattern.len() + 1
+                && request_url.last().map_or(false, |s| s.is_empty())
+                && url_pattern.last().map_or(false, |s| !s.is_empty())
+            {
+                request_url.pop();
+            }
+            let len_matches = if catch_all.is_some() {
+                request_url.len() >= url_pattern.len()
+            } else {
+                request_url.len() == url_pattern.len()
+            };
+            if !len_matches {
                 None
             } else {
                 struct RouilleUrlParams {
@@ -173,10 +344,14 @@ macro_rules! router {
                 }
                 let url_params = (|| {
                     let mut url_params = RouilleUrlParams::new();
-                    for (actual, desired) in request_url.iter().zip(url_pattern.iter()) {
-                        if let Some(key) = desired.strip_prefix("{").and_then(|d| d.strip_suffix("}")) {
+                    for (index, desired) in url_pattern.iter().enumerate() {
+                        if let Some(key) = desired.strip_prefix("{..").and_then(|d| d.strip_suffix("}")) {
+                            let actual = request_url[index..].join("/");
                             $crate::router!(__insert_param $request_url_str, url_params, key, actual ; $($param: $param_type)*)
-                        } else if actual != desired {
+                        } else if let Some(key) = desired.strip_prefix("{").and_then(|d| d.strip_suffix("}")) {
+                            let actual = &request_url[index];
+                            $crate::router!(__insert_param $request_url_str, url_params, key, actual ; $($param: $param_type)*)
+                        } else if request_url[index] != *desired {
                             return None
                         }
                     }
@@ -247,7 +422,9 @@ macro_rules! router {
     // -----------------
     // --- Old style ---
     // -----------------
-    ($request:expr, $(($method:ident) ($($pat:tt)+) => $value:block,)* _ => $def:expr $(,)*) => {
+    ($request:expr, $(($($method:ident)|+) ($($pat:tt)+) => $value:block,)*
+     $(405 => $not_allowed:expr,)?
+     _ => $def:expr $(,)*) => {
         {
             let request = &$request;
 
@@ -260,12 +437,37 @@ macro_rules! router {
 
             let mut ret = None;
 
+            // Methods of the routes whose url matches the request, if a `405` handler is present.
+            // The url of a route whose method doesn't match is checked with the same pattern as
+            // the handler, so that its parameters are parsed with the same types.
+            let mut allowed: Vec<&'static str> = Vec::new();
+            let has_not_allowed = $crate::router!(__has_not_allowed $($not_allowed)?);
             $({
-                if ret.is_none() && request.method() == stringify!($method) {
-                    ret = $crate::router!(__check_pattern request_url $value $($pat)+);
+                let method_matches = $crate::router!(__method_matches request, $($method)|+);
+                if ret.is_none() && (method_matches || has_not_allowed) {
+                    let route = $crate::router!(__check_pattern request_url {
+                        if method_matches { Some($value) } else { None }
+                    } $($pat)+);
+                    match route {
+                        Some(Some(response)) => ret = Some(response),
+                        Some(None) => {
+                            $crate::router!(__push_allowed allowed, $($method)|+);
+                        }
+                        None => (),
+                    }
                 }
             })+
 
+            if allowed.contains(&"GET") && !allowed.contains(&"HEAD") {
+                allowed.push("HEAD");
+            }
+
+            $(
+                if ret.is_none() && !allowed.is_empty() {
+                    ret = Some($crate::router::call_not_allowed($not_allowed, &allowed));
+                }
+            )?
+
             if let Some(ret) = ret {
                 ret
             } else {
@@ -274,34 +476,36 @@ macro_rules! router {
         }
     };
 
-    (__check_pattern $url:ident $value:block /{$p:ident} $($rest:tt)*) => (
+    (__check_pattern $url:ident $value:block /{..$p:ident}) => (
         if let Some(url) = $url.strip_prefix('/') {
-            let url = &$url[1..];
-            let pat_end = url.find('/').unwrap_or(url.len());
-            let rest_url = &url[pat_end..];
-
-            if let Ok($p) = url[0 .. pat_end].parse() {
-                $crate::router!(__check_pattern rest_url $value $($rest)*)
-            } else {
-                None
-            }
+            let $p: String = $crate::percent_encoding::percent_decode(url.as_bytes())
+                .decode_utf8_lossy()
+                .into_owned();
+            Some($value)
         } else {
             None
         }
     );
 
+    (__check_pattern $url:ident $value:block /{..$p:ident} $($rest:tt)+) => (
+        compile_error!("a catch-all `{..}` parameter must be the last segment of a route")
+    );
+
+    // Without an explicit type, the type of the parameter is inferred from the body.
+    (__check_pattern $url:ident $value:block /{$p:ident} $($rest:tt)*) => (
+        $crate::router!(__check_pattern $url $value /{$p: _} $($rest)*)
+    );
+
+    // If the segment fails to parse as `$t`, the route doesn't match.
     (__check_pattern $url:ident $value:block /{$p:ident: $t:ty} $($rest:tt)*) => (
         if let Some(url) = $url.strip_prefix('/') {
-            let url = &$url[1..];
             let pat_end = url.find('/').unwrap_or(url.len());
             let rest_url = &url[pat_end..];
 
-            if let Ok($p) = $crate::percent_encoding::percent_decode(url[0 .. pat_end].as_bytes())
-                .decode_utf8_lossy().parse() {
-                let $p: $t = $p;
-                $crate::router!(__check_pattern rest_url $value $($rest)*)
-            } else {
-                None
+            match $crate::percent_encoding::percent_decode(url[0 .. pat_end].as_bytes())
+                .decode_utf8_lossy().parse::<$t>() {
+                Ok($p) => $crate::router!(__check_pattern rest_url $value $($rest)*),
+                Err(_) => None,
             }
         } else {
             None
@@ -329,8 +533,9 @@ macro_rules! router {
         }
     );
 
+    // A pattern without a trailing `/` also matches the url with a trailing `/`.
     (__check_pattern $url:ident $value:block) => (
-        if $url.len() == 0 { Some($value) } else { None }
+        if $url.is_empty() || $url == "/" { Some($value) } else { None }
     );
 
     (__check_pattern $url:ident $value:block /) => (
@@ -347,6 +552,16 @@ macro_rules! router {
             }
         }
     );
+
+}
+
+/// Calls the `405` handler of the `router!` macro with the list of allowed methods.
+#[inline]
+pub fn call_not_allowed<F, R>(handler: F, allowed: &[&'static str]) -> R
+where
+    F: FnOnce(&[&'static str]) -> R,
+{
+    handler(allowed)
 }
 
 #[allow(unused_variables)]
@@ -370,6 +585,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn old_style_typed_params() {
+        let route = |url: &str| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            router!(request,
+                (GET) (/users/{id: u32}) => { format!("u32 {}", id) },
+                (GET) (/users/{id: i64}) => { format!("i64 {}", id) },
+                (GET) (/users/{name: String}) => { format!("String {}", name) },
+                _ => String::new()
+            )
+        };
+
+        assert_eq!(route("/users/12"), "u32 12");
+        assert_eq!(route("/users/-12"), "i64 -12");
+        assert_eq!(route("/users/99999999999"), "i64 99999999999");
+        assert_eq!(route("/users/abc"), "String abc");
+        assert_eq!(route("/users/a%20b"), "String a b");
+    }
+
+    #[test]
+    fn old_style_untyped_param_decoded() {
+        let request = Request::fake_http("GET", "/hello/a%20b", vec![], vec![]);
+
+        assert_eq!(
+            "a b",
+            router!(request,
+                (GET) (/hello/{name}) => { let name: String = name; name },
+                _ => String::new()
+            )
+        );
+    }
+
     #[test]
     fn old_style_dash() {
         let request = Request::fake_http("GET", "/a-b", vec![], vec![]);
@@ -400,6 +647,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn old_style_catch_all() {
+        let request = Request::fake_http("GET", "/files/a/b/c.txt", vec![], vec![]);
+
+        assert_eq!(
+            "a/b/c.txt",
+            router!(request,
+                (GET) (/files/{_id: u32}) => { String::new() },
+                (GET) (/files/{..path}) => { path },
+                _ => String::new()
+            )
+        );
+    }
+
+    #[test]
+    fn old_style_catch_all_requires_slash() {
+        let request = Request::fake_http("GET", "/files", vec![], vec![]);
+
+        assert_eq!(
+            0,
+            router!(request,
+                (GET) (/files/{..path}) => { 1 },
+                _ => 0
+            )
+        );
+    }
+
     #[test]
     fn old_style_trailing_comma() {
         let request = Request::fake_http("GET", "/hello/5", vec![], vec![]);
@@ -430,6 +704,166 @@ mod tests {
         );
     }
 
+    #[test]
+    fn old_style_trailing_slash() {
+        let route = |url: &str| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            router!(request,
+                (GET) (/foo) => { 1 },
+                (GET) (/a/b) => { 2 },
+                (GET) (/c/) => { 3 },
+                (GET) (/d/{id: u32}) => { id },
+                _ => 0
+            )
+        };
+
+        assert_eq!(route("/foo"), 1);
+        assert_eq!(route("/foo/"), 1);
+        assert_eq!(route("/foo//"), 0);
+        assert_eq!(route("/a/b"), 2);
+        assert_eq!(route("/a/b/"), 2);
+        assert_eq!(route("/a/"), 0);
+        assert_eq!(route("/c/"), 3);
+        assert_eq!(route("/c"), 0);
+        assert_eq!(route("/d/12/"), 12);
+    }
+
+    #[test]
+    fn old_style_method_not_allowed() {
+        let route = |method: &str, url: &str| {
+            let request = Request::fake_http(method, url, vec![], vec![]);
+            router!(request,
+                (GET) (/hello) => { "get".to_owned() },
+                (PUT) (/hello) => { "put".to_owned() },
+                (GET) (/user/{id: u32}) => { format!("user {}", id) },
+                (DELETE) (/user/{id: u32}) => { format!("delete {}", id) },
+                (GET) (/hello) => { "duplicate".to_owned() },
+                405 => |allowed| allowed.join(", "),
+                _ => "default".to_owned()
+            )
+        };
+
+        assert_eq!(route("GET", "/hello"), "get");
+        assert_eq!(route("POST", "/hello"), "GET, PUT, HEAD");
+        assert_eq!(route("POST", "/hello/"), "GET, PUT, HEAD");
+        assert_eq!(route("POST", "/user/5"), "GET, DELETE, HEAD");
+        assert_eq!(route("POST", "/user/abc"), "default");
+        assert_eq!(route("POST", "/other"), "default");
+    }
+
+    #[test]
+    fn old_style_method_not_allowed_inferred_param() {
+        let route = |method: &str, url: &str| {
+            let request = Request::fake_http(method, url, vec![], vec![]);
+            router!(request,
+                (GET) (/user/{id}) => { let id: u32 = id; format!("user {}", id) },
+                405 => |allowed| allowed.join(", "),
+                _ => "default".to_owned()
+            )
+        };
+
+        assert_eq!(route("GET", "/user/5"), "user 5");
+        assert_eq!(route("POST", "/user/5"), "GET, HEAD");
+        assert_eq!(route("POST", "/user/abc"), "default");
+    }
+
+    #[test]
+    fn old_style_head_like_get() {
+        let route = |method: &str| {
+            let request = Request::fake_http(method, "/hello", vec![], vec![]);
+            router!(request,
+                (GET) (/hello) => { 1 },
+                _ => 0
+            )
+        };
+
+        assert_eq!(route("GET"), 1);
+        assert_eq!(route("HEAD"), 1);
+        assert_eq!(route("POST"), 0);
+    }
+
+    #[test]
+    fn old_style_methods() {
+        let route = |method: &str| {
+            let request = Request::fake_http(method, "/hello", vec![], vec![]);
+            router!(request,
+                (PATCH) (/hello) => { 1 },
+                (PROPFIND) (/hello) => { 2 },
+                _ => 0
+            )
+        };
+
+        assert_eq!(route("PATCH"), 1);
+        assert_eq!(route("PROPFIND"), 2);
+        assert_eq!(route("patch"), 0);
+        assert_eq!(route("GET"), 0);
+    }
+
+    #[test]
+    fn old_style_each_method_enforced() {
+        let route = |method: &str| {
+            let request = Request::fake_http(method, "/hello", vec![], vec![]);
+            router!(request,
+                (POST) (/hello) => { "post" },
+                (PUT) (/hello) => { "put" },
+                (DELETE) (/hello) => { "delete" },
+                (PATCH) (/hello) => { "patch" },
+                _ => "none"
+            )
+        };
+
+        assert_eq!(route("POST"), "post");
+        assert_eq!(route("PUT"), "put");
+        assert_eq!(route("DELETE"), "delete");
+        assert_eq!(route("PATCH"), "patch");
+        assert_eq!(route("GET"), "none");
+        assert_eq!(route("HEAD"), "none");
+    }
+
+    #[test]
+    fn old_style_multiple_methods() {
+        let route = |method: &str| {
+            let request = Request::fake_http(method, "/hello", vec![], vec![]);
+            router!(request,
+                (GET|HEAD) (/hello) => { 1 },
+                (POST | PUT | PATCH) (/hello) => { 2 },
+                _ => 0
+            )
+        };
+
+        assert_eq!(route("GET"), 1);
+        assert_eq!(route("HEAD"), 1);
+        assert_eq!(route("POST"), 2);
+        assert_eq!(route("PUT"), 2);
+        assert_eq!(route("PATCH"), 2);
+        assert_eq!(route("DELETE"), 0);
+    }
+
+    #[test]
+    fn old_style_multiple_methods_not_allowed() {
+        let request = Request::fake_http("DELETE", "/hello", vec![], vec![]);
+        let allowed = router!(request,
+            (GET) (/hello) => { vec![] },
+            (POST|PUT) (/hello) => { vec![] },
+            405 => |allowed: &[&'static str]| allowed.to_vec(),
+            _ => vec![]
+        );
+        assert_eq!(allowed, vec!["GET", "POST", "PUT", "HEAD"]);
+    }
+
+    #[test]
+    fn old_style_method_not_allowed_fallthrough() {
+        let request = Request::fake_http("POST", "/hello", vec![], vec![]);
+
+        assert_eq!(
+            0,
+            router!(request,
+                (GET) (/hello) => { 1 },
+                _ => 0
+            )
+        );
+    }
+
     // -- new-style tests --
     #[test]
     fn multiple_params() {
@@ -548,6 +982,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn typed_params() {
+        let route = |url: &str| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            router!(request,
+                (GET) ["/users/{id}", id: u32] => { format!("u32 {}", id) },
+                (GET) ["/users/{id}", id: i64] => { format!("i64 {}", id) },
+                (GET) ["/users/{name}", name: String] => { format!("String {}", name) },
+                _ => String::new()
+            )
+        };
+
+        assert_eq!(route("/users/12"), "u32 12");
+        assert_eq!(route("/users/-12"), "i64 -12");
+        assert_eq!(route("/users/abc"), "String abc");
+    }
+
     #[test]
     fn skip_failed_parse_string() {
         let request = Request::fake_http("GET", "/word/wow", vec![], vec![]);
@@ -612,6 +1063,197 @@ mod tests {
         );
     }
 
+    #[test]
+    fn trailing_slash() {
+        let route = |url: &str| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            router!(request,
+                (GET) ["/foo"] => { 1 },
+                (GET) ["/a/b"] => { 2 },
+                (GET) ["/c/"] => { 3 },
+                (GET) ["/d/{id}", id: u32] => { id },
+                (GET) ["/e/{id}/", id: u32] => { id },
+                _ => 0
+            )
+        };
+
+        assert_eq!(route("/foo"), 1);
+        assert_eq!(route("/foo/"), 1);
+        assert_eq!(route("/foo//"), 0);
+        assert_eq!(route("/a/b"), 2);
+        assert_eq!(route("/a/b/"), 2);
+        assert_eq!(route("/a/"), 0);
+        assert_eq!(route("/c/"), 3);
+        assert_eq!(route("/c"), 0);
+        assert_eq!(route("/d/12"), 12);
+        assert_eq!(route("/d/12/"), 12);
+        assert_eq!(route("/e/7/"), 7);
+        assert_eq!(route("/e/7"), 0);
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        let route = |method: &str, url: &str| {
+            let request = Request::fake_http(method, url, vec![], vec![]);
+            router!(request,
+                (GET) ["/hello"] => { "get".to_owned() },
+                (PUT) ["/hello"] => { "put".to_owned() },
+                (GET) ["/user/{id}", id: u32] => { format!("user {}", id) },
+                (DELETE) ["/user/{id}", id: u32] => { format!("delete {}", id) },
+                405 => |allowed| allowed.join(", "),
+                _ => "default".to_owned()
+            )
+        };
+
+        assert_eq!(route("PUT", "/hello"), "put");
+        assert_eq!(route("POST", "/hello"), "GET, PUT, HEAD");
+        assert_eq!(route("POST", "/user/5"), "GET, DELETE, HEAD");
+        assert_eq!(route("POST", "/user/abc"), "default");
+        assert_eq!(route("POST", "/other"), "default");
+    }
+
+    #[test]
+    fn method_not_allowed_response() {
+        use Response;
+
+        let request = Request::fake_http("POST", "/hello", vec![], vec![]);
+        let response = router!(request,
+            (GET) ["/hello"] => { Response::text("hello") },
+            (HEAD) ["/hello"] => { Response::text("hello") },
+            405 => Response::empty_405,
+            _ => Response::empty_404()
+        );
+
+        assert_eq!(response.status_code, 405);
+        assert_eq!(response.headers, vec![("Allow".into(), "GET, HEAD".into())]);
+    }
+
+    #[test]
+    fn method_not_allowed_implicit_head() {
+        let request = Request::fake_http("POST", "/hello", vec![], vec![]);
+
+        assert_eq!(
+            "GET, HEAD",
+            router!(request,
+                (GET) ["/hello"] => { String::new() },
+                405 => |allowed| allowed.join(", "),
+                _ => String::new()
+            )
+        );
+    }
+
+    #[test]
+    fn multiple_methods() {
+        let route = |method: &str| {
+            let request = Request::fake_http(method, "/users/5", vec![], vec![]);
+            router!(request,
+                (GET) ["/users/{id}", id: u32] => { id },
+                (PUT|PATCH) ["/users/{id}", id: u32] => { id * 10 },
+                405 => |allowed: &[&'static str]| allowed.len() as u32,
+                _ => 0
+            )
+        };
+
+        assert_eq!(route("GET"), 5);
+        assert_eq!(route("PUT"), 50);
+        assert_eq!(route("PATCH"), 50);
+        // `GET`, `PUT`, `PATCH` and `HEAD` are allowed.
+        assert_eq!(route("POST"), 4);
+    }
+
+    #[test]
+    fn patch_and_options() {
+        let route = |method: &str| {
+            let request = Request::fake_http(method, "/users/5", vec![], vec![]);
+            router!(request,
+                (PATCH) ["/users/{id}", id: u32] => { format!("patch {}", id) },
+                (OPTIONS) ["/users/{id}", id: u32] => { "preflight".to_owned() },
+                _ => String::new()
+            )
+        };
+
+        assert_eq!(route("PATCH"), "patch 5");
+        assert_eq!(route("OPTIONS"), "preflight");
+        assert_eq!(route("GET"), "");
+    }
+
+    #[test]
+    fn explicit_head() {
+        let route = |method: &str| {
+            let request = Request::fake_http(method, "/hello", vec![], vec![]);
+            router!(request,
+                (HEAD) ["/hello"] => { 1 },
+                (GET) ["/hello"] => { 2 },
+                _ => 0
+            )
+        };
+
+        assert_eq!(route("HEAD"), 1);
+        assert_eq!(route("GET"), 2);
+    }
+
+    #[test]
+    fn head_like_get() {
+        use Response;
+
+        let route = |method: &str| {
+            let request = Request::fake_http(method, "/hello", vec![], vec![]);
+            router!(request,
+                (POST) ["/hello"] => { Response::empty_400() },
+                (GET) ["/hello"] => { Response::text("hello").with_etag(&request, "\"1\"") },
+                _ => Response::empty_404()
+            )
+        };
+
+        let get = route("GET");
+        let head = route("HEAD");
+        assert_eq!(get.status_code, 200);
+        assert_eq!(head.status_code, get.status_code);
+        assert_eq!(head.headers, get.headers);
+    }
+
+    #[test]
+    fn catch_all() {
+        let request = Request::fake_http("GET", "/files/a/b/c.txt", vec![], vec![]);
+
+        assert_eq!(
+            "a/b/c.txt",
+            router!(request,
+                (GET) ["/files/{name}", name: String] => { name },
+                (GET) ["/files/{..path}", path: String] => { path },
+                _ => String::new()
+            )
+        );
+    }
+
+    #[test]
+    fn catch_all_with_params() {
+        let request = Request::fake_http("GET", "/user/5/files/a%20b/c", vec![], vec![]);
+
+        assert_eq!(
+            "5:a b/c",
+            router!(request,
+                (GET) ["/user/{id}/files/{..path}", id: u32, path: String] => {
+                    format!("{}:{}", id, path)
+                },
+                _ => String::new()
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Catch-all url parameter must be the last segment of url: \"/files/{..path}/foo\""
+    )]
+    fn catch_all_not_last() {
+        let request = Request::fake_http("GET", "/files/a/foo", vec![], vec![]);
+
+        router!(request,
+            (GET) ["/files/{..path}/foo", path: String] => { path },
+            _ => String::new()
+        );
+    }
+
     #[test]
     fn encoded() {
         let request = Request::fake_http("GET", "/hello/%3Fa/test", vec![], vec![]);
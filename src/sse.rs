@@ -0,0 +1,367 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Support for server-sent events.
+//!
+//! Server-sent events are a way for the server to push messages to a client (usually a browser
+//! through the `EventSource` Javascript API) over a long-lived HTTP response.
+//!
+//! Call `start()` to obtain a `Response` and an `EventSender`. Return the `Response` from your
+//! handler, and use the `EventSender` (usually from another thread) to push events to the client.
+//!
+//! # Example
+//!
+//! ```
+//! use std::thread;
+//! use std::time::Duration;
+//! use rouille::Request;
+//! use rouille::Response;
+//! use rouille::sse;
+//!
+//! fn handle(request: &Request) -> Response {
+//!     let (response, events) = sse::start();
+//!
+//!     thread::spawn(move || {
+//!         for n in 0.. {
+//!             let event = sse::Event::new(format!("tick {}", n)).with_event("tick");
+//!             if events.send(&event).is_err() {
+//!                 // The client has disconnected.
+//!                 break;
+//!             }
+//!             thread::sleep(Duration::from_secs(1));
+//!         }
+//!     });
+//!
+//!     response
+//! }
+//! ```
+//!
+//! # Heartbeats
+//!
+//! Proxies and browsers tend to close connections that stay idle for too long. Unless told
+//! otherwise, a comment line is sent to the client whenever no event has been sent for 15 seconds.
+//! These comments are ignored by the client.
+//!
+//! Heartbeats are also what lets the server notice that a client went away while no event is
+//! being sent.
+//!
+//! # Connection
+//!
+//! The server takes over the connection the same way as for websockets, so that each event is
+//! sent to the client as soon as possible instead of being buffered. Because of this, the response
+//! also contains `Connection: upgrade` and empty `Upgrade` headers, which clients ignore.
+//!
+//! The response doesn't have a length, and the server has no way to close the connection. Once all
+//! the `EventSender`s have been dropped, nothing is sent anymore (not even heartbeats) but the
+//! connection stays open until the client closes it. If you want to end the stream, send an event
+//! that tells your client-side code to call `EventSource.close()`.
+
+use std::error;
+use std::fmt;
+use std::io::Write;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use ReadWrite;
+use Response;
+use Upgrade;
+
+/// Starts a stream of server-sent events, with a heartbeat every 15 seconds.
+///
+/// Returns the response to send back to the client, and an object that can be used to send
+/// events over that response.
+#[inline]
+pub fn start() -> (Response, EventSender) {
+    start_with_heartbeat(Duration::from_secs(15))
+}
+
+/// Same as `start()`, but sends a heartbeat when no event was sent during `interval` instead.
+pub fn start_with_heartbeat(interval: Duration) -> (Response, EventSender) {
+    let (tx, rx) = mpsc::channel();
+
+    let mut response = Response::text("")
+        .with_unique_header("Content-Type", "text/event-stream")
+        .with_unique_header("Cache-Control", "no-cache")
+        .with_unique_header("X-Accel-Buffering", "no");
+    response.upgrade = Some(Box::new(EventStream {
+        events: Some(rx),
+        heartbeat: interval,
+    }) as Box<_>);
+
+    (response, EventSender { events: tx })
+}
+
+/// An event that can be sent to the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    event: Option<String>,
+    data: String,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// Builds an event that contains the given data.
+    ///
+    /// The data can contain multiple lines.
+    #[inline]
+    pub fn new<S>(data: S) -> Event
+    where
+        S: Into<String>,
+    {
+        Event {
+            event: None,
+            data: data.into(),
+            id: None,
+            retry: None,
+        }
+    }
+
+    /// Sets the type of the event. Without a type, the client considers that it is a `message`.
+    ///
+    /// Line breaks are removed from the type.
+    #[inline]
+    pub fn with_event<S>(mut self, event: S) -> Event
+    where
+        S: Into<String>,
+    {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets the identifier of the event. If the connection is interrupted, the client sends it
+    /// back in the `Last-Event-ID` header when it reconnects.
+    ///
+    /// Line breaks are removed from the identifier.
+    #[inline]
+    pub fn with_id<S>(mut self, id: S) -> Event
+    where
+        S: Into<String>,
+    {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the time the client should wait before reconnecting if the connection is
+    /// interrupted.
+    #[inline]
+    pub fn with_retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    // Turns the event into what is sent on the wire.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = String::new();
+
+        if let Some(ref event) = self.event {
+            out.push_str("event: ");
+            out.push_str(&event.replace(['\r', '\n'], ""));
+            out.push('\n');
+        }
+
+        if let Some(ref id) = self.id {
+            out.push_str("id: ");
+            out.push_str(&id.replace(['\r', '\n'], ""));
+            out.push('\n');
+        }
+
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+
+        // The data is split in lines, each of them being prefixed with `data: `. The client
+        // joins them back with `\n`.
+        for line in self.data.replace("\r\n", "\n").split(['\r', '\n']) {
+            out.push_str("data: ");
+            out.push_str(line);
+            out.push('\n');
+        }
+
+        out.push('\n');
+        out.into_bytes()
+    }
+}
+
+/// Sends events to a client. Obtained with `start()`.
+///
+/// The `EventSender` can be cloned in order to send events from multiple places. Events stop
+/// being sent once all the clones have been dropped.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    events: mpsc::Sender<Vec<u8>>,
+}
+
+impl EventSender {
+    /// Queues an event to be sent to the client.
+    ///
+    /// Returns an error if the client has disconnected. Note that a disconnection is only
+    /// noticed when the server fails to write to the client.
+    #[inline]
+    pub fn send(&self, event: &Event) -> Result<(), SendError> {
+        self.events.send(event.to_bytes()).map_err(|_| SendError)
+    }
+}
+
+/// Error returned by `EventSender::send` when the client has disconnected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendError;
+
+impl error::Error for SendError {}
+
+impl fmt::Display for SendError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "the client has disconnected")
+    }
+}
+
+// Receives the connection from the server and writes the events to it in a background thread.
+struct EventStream {
+    events: Option<mpsc::Receiver<Vec<u8>>>,
+    heartbeat: Duration,
+}
+
+impl Upgrade for EventStream {
+    fn build(&mut self, mut socket: Box<dyn ReadWrite + Send>) {
+        let events = match self.events.take() {
+            Some(events) => events,
+            None => return,
+        };
+        let heartbeat = self.heartbeat;
+
+        thread::spawn(move || loop {
+            let data = match events.recv_timeout(heartbeat) {
+                Ok(data) => data,
+                Err(mpsc::RecvTimeoutError::Timeout) => b":\n".to_vec(),
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+
+            // Dropping `events` on error makes the `EventSender`s fail.
+            if socket.write_all(&data).is_err() || socket.flush().is_err() {
+                break;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Event;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+    use tests::{read_head, serve};
+
+    #[test]
+    fn event_format() {
+        assert_eq!(Event::new("hello").to_bytes(), b"data: hello\n\n");
+
+        let event = Event::new("first\nsecond\r\nthird")
+            .with_event("update")
+            .with_id("42")
+            .with_retry(Duration::from_secs(3));
+        assert_eq!(
+            String::from_utf8(event.to_bytes()).unwrap(),
+            "event: update\nid: 42\nretry: 3000\ndata: first\ndata: second\ndata: third\n\n"
+        );
+    }
+
+    #[test]
+    fn event_line_breaks_stripped() {
+        let event = Event::new("").with_event("a\nb").with_id("1\r\n2");
+        assert_eq!(event.to_bytes(), b"event: ab\nid: 12\ndata: \n\n");
+    }
+
+    // Reads the headers of the response, then returns the first `count` lines of the body.
+    fn read_body_lines(reader: &mut BufReader<TcpStream>, count: usize) -> (String, Vec<String>) {
+        let head = read_head(reader);
+
+        let lines = (0..count)
+            .map(|_| {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                line
+            })
+            .collect();
+        (head, lines)
+    }
+
+    #[test]
+    fn stream_events() {
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let tx = Mutex::new(tx);
+        let server = serve(move |_| {
+            let (response, events) = super::start();
+            tx.lock().unwrap().send(events).unwrap();
+            response
+        });
+
+        let (mut stream, mut reader) = server.connect();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+        // Each event must reach the client before the next one is sent.
+        let events = rx.recv().unwrap();
+        events.send(&Event::new("one").with_id("1")).unwrap();
+        let (head, lines) = read_body_lines(&mut reader, 3);
+        assert!(head.starts_with("HTTP/1.1 200"), "{:?}", head);
+        assert!(
+            head.contains("Content-Type: text/event-stream\r\n"),
+            "{:?}",
+            head
+        );
+        assert_eq!(lines, vec!["id: 1\n", "data: one\n", "\n"]);
+
+        events.send(&Event::new("two").with_event("e")).unwrap();
+        let lines = (0..3)
+            .map(|_| {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                line
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec!["event: e\n", "data: two\n", "\n"]);
+
+        drop(reader);
+        drop(stream);
+        thread::sleep(Duration::from_millis(100));
+        // The first writes after a disconnection may succeed, as the operating system doesn't
+        // know yet that the other side is gone.
+        let mut result = Ok(());
+        for _ in 0..100 {
+            result = events.send(&Event::new("three"));
+            if result.is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn heartbeat() {
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let tx = Mutex::new(tx);
+        let server = serve(move |_| {
+            let (response, events) = super::start_with_heartbeat(Duration::from_millis(50));
+            tx.lock().unwrap().send(events).unwrap();
+            response
+        });
+
+        let (mut stream, mut reader) = server.connect();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+        let _events = rx.recv().unwrap();
+        let (_, lines) = read_body_lines(&mut reader, 2);
+        assert_eq!(lines, vec![":\n", ":\n"]);
+    }
+}
@@ -0,0 +1,361 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Implementation of the `permessage-deflate` extension.
+//!
+//! See https://tools.ietf.org/html/rfc7692.
+
+#[cfg(feature = "websocket-deflate")]
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use Request;
+
+// Bytes that a sync flush adds at the end of the data, and that are removed from the payloads.
+#[cfg(feature = "websocket-deflate")]
+const SYNC_FLUSH_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Parameters of the extension that were agreed on with the client.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// If true, the server resets its compression context after each message.
+    pub server_no_context_takeover: bool,
+    /// If true, the client resets its compression context after each message.
+    pub client_no_context_takeover: bool,
+}
+
+impl Config {
+    /// Returns the value of the `Sec-WebSocket-Extensions` header of the response.
+    pub fn header_value(&self) -> String {
+        let mut value = "permessage-deflate".to_owned();
+        if self.server_no_context_takeover {
+            value.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            value.push_str("; client_no_context_takeover");
+        }
+        value
+    }
+}
+
+/// Returns the first `permessage-deflate` offer of the `Sec-WebSocket-Extensions` headers of the
+/// request that is supported, or `None` if there isn't any.
+///
+/// Always returns `None` if the `websocket-deflate` feature is disabled.
+pub fn negotiate(request: &Request) -> Option<Config> {
+    if !cfg!(feature = "websocket-deflate") {
+        return None;
+    }
+
+    request
+        .headers()
+        .filter(|(key, _)| key.eq_ignore_ascii_case("Sec-WebSocket-Extensions"))
+        .flat_map(|(_, value)| value.split(','))
+        .filter_map(parse_offer)
+        .next()
+}
+
+// Parses one offer, for example `permessage-deflate; client_max_window_bits`. Returns `None` if
+// this is another extension or if the parameters can't be satisfied.
+fn parse_offer(offer: &str) -> Option<Config> {
+    let mut params = offer.split(';').map(|p| p.trim());
+    if !params.next()?.eq_ignore_ascii_case("permessage-deflate") {
+        return None;
+    }
+
+    let mut config = Config {
+        server_no_context_takeover: false,
+        client_no_context_takeover: false,
+    };
+    let mut server_max_window_bits = false;
+    let mut client_max_window_bits = false;
+
+    for param in params {
+        let mut parts = param.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let value = parts.next().map(|v| v.trim().trim_matches('"'));
+
+        // Each parameter must appear at most once, otherwise the offer is invalid.
+        let seen = match (&name[..], value) {
+            ("server_no_context_takeover", None) => &mut config.server_no_context_takeover,
+            ("client_no_context_takeover", None) => &mut config.client_no_context_takeover,
+            // The compressor always uses a window of 2^15 bytes, so we can't accept a limit.
+            ("server_max_window_bits", Some("15")) => &mut server_max_window_bits,
+            // The decompressor accepts any window size, so we ignore this hint.
+            ("client_max_window_bits", None) => &mut client_max_window_bits,
+            ("client_max_window_bits", Some(v)) if window_bits_valid(v) => {
+                &mut client_max_window_bits
+            }
+            _ => return None,
+        };
+        if *seen {
+            return None;
+        }
+        *seen = true;
+    }
+
+    Some(config)
+}
+
+fn window_bits_valid(value: &str) -> bool {
+    match value.parse::<u8>() {
+        Ok(bits) => (8..=15).contains(&bits) && !value.starts_with('0'),
+        Err(_) => false,
+    }
+}
+
+/// Compression state of a websocket connection.
+#[cfg(feature = "websocket-deflate")]
+pub struct PerMessageDeflate {
+    config: Config,
+    compress: Compress,
+    decompress: Decompress,
+}
+
+#[cfg(feature = "websocket-deflate")]
+impl PerMessageDeflate {
+    /// Initializes the state for a new connection.
+    pub fn new(config: Config) -> PerMessageDeflate {
+        PerMessageDeflate {
+            config,
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+        }
+    }
+
+    /// Compresses the payload of a message sent to the client.
+    pub fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        let start = self.compress.total_in();
+
+        loop {
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity());
+            }
+
+            let consumed = (self.compress.total_in() - start) as usize;
+            self.compress
+                .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+                .expect("compressing data never fails");
+
+            // The flush is complete once all the data was consumed and there is space left.
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == data.len() && out.len() < out.capacity() {
+                break;
+            }
+        }
+
+        debug_assert!(out.ends_with(&SYNC_FLUSH_TAIL));
+        out.truncate(out.len() - SYNC_FLUSH_TAIL.len());
+
+        if self.config.server_no_context_takeover {
+            self.compress.reset();
+        }
+
+        out
+    }
+
+    /// Decompresses the payload of a message received from the client.
+    ///
+    /// On error, returns the status code and the reason to send to the client in the close
+    /// frame. This happens if the data is invalid or if the decompressed message is larger than
+    /// `max_len`.
+    pub fn decompress(
+        &mut self,
+        data: &[u8],
+        max_len: u64,
+    ) -> Result<Vec<u8>, (u16, &'static str)> {
+        let mut input = Vec::with_capacity(data.len() + SYNC_FLUSH_TAIL.len());
+        input.extend_from_slice(data);
+        input.extend_from_slice(&SYNC_FLUSH_TAIL);
+
+        let mut out = Vec::with_capacity(data.len() * 2 + 64);
+        let start = self.decompress.total_in();
+
+        loop {
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity());
+            }
+
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let produced = out.len();
+            let status = self
+                .decompress
+                .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(|_| (1007, "Invalid compressed data"))?;
+
+            if out.len() as u64 > max_len {
+                return Err((1009, "Message too large"));
+            }
+
+            // The client is allowed to end the stream, in which case the next message starts
+            // a new one.
+            if status == Status::StreamEnd {
+                self.decompress.reset(false);
+                return Ok(out);
+            }
+
+            let now_consumed = (self.decompress.total_in() - start) as usize;
+            if now_consumed == input.len() && out.len() < out.capacity() {
+                break;
+            }
+            if now_consumed == consumed && out.len() == produced {
+                return Err((1007, "Invalid compressed data"));
+            }
+        }
+
+        if self.config.client_no_context_takeover {
+            self.decompress.reset(false);
+        }
+
+        Ok(out)
+    }
+}
+
+/// Without the `websocket-deflate` feature the extension is never negotiated, so there is never
+/// any compression state.
+#[cfg(not(feature = "websocket-deflate"))]
+pub enum PerMessageDeflate {}
+
+#[cfg(not(feature = "websocket-deflate"))]
+impl PerMessageDeflate {
+    pub fn new(_: Config) -> PerMessageDeflate {
+        unreachable!("permessage-deflate is never negotiated without the websocket-deflate feature")
+    }
+
+    pub fn compress(&mut self, _: &[u8]) -> Vec<u8> {
+        match *self {}
+    }
+
+    pub fn decompress(&mut self, _: &[u8], _: u64) -> Result<Vec<u8>, (u16, &'static str)> {
+        match *self {}
+    }
+}
+
+#[cfg(all(test, feature = "websocket-deflate"))]
+mod tests {
+    use super::negotiate;
+    use super::Config;
+    use super::PerMessageDeflate;
+    use Request;
+
+    fn offer(value: &str) -> Option<Config> {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("Sec-WebSocket-Extensions".to_owned(), value.to_owned())],
+            vec![],
+        );
+        negotiate(&request)
+    }
+
+    #[test]
+    fn negotiate_basic() {
+        let config = offer("permessage-deflate; client_max_window_bits").unwrap();
+        assert!(!config.server_no_context_takeover);
+        assert!(!config.client_no_context_takeover);
+        assert_eq!(config.header_value(), "permessage-deflate");
+
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        assert_eq!(negotiate(&request), None);
+        assert_eq!(offer("x-webkit-deflate-frame"), None);
+    }
+
+    #[test]
+    fn negotiate_no_context_takeover() {
+        let config =
+            offer("permessage-deflate; server_no_context_takeover; client_no_context_takeover")
+                .unwrap();
+        assert!(config.server_no_context_takeover);
+        assert!(config.client_no_context_takeover);
+        assert_eq!(
+            config.header_value(),
+            "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+        );
+    }
+
+    #[test]
+    fn negotiate_fallback() {
+        // We can't limit the window of the compressor, so the second offer is picked.
+        let config = offer(
+            "permessage-deflate; server_max_window_bits=10, \
+             permessage-deflate; server_no_context_takeover",
+        )
+        .unwrap();
+        assert!(config.server_no_context_takeover);
+
+        assert_eq!(offer("permessage-deflate; unknown"), None);
+        assert_eq!(
+            offer("permessage-deflate; client_no_context_takeover; client_no_context_takeover"),
+            None
+        );
+        assert_eq!(offer("permessage-deflate; client_max_window_bits=16"), None);
+    }
+
+    fn round_trip(config: Config) {
+        let mut server = PerMessageDeflate::new(config);
+        let mut client = PerMessageDeflate::new(Config {
+            server_no_context_takeover: config.client_no_context_takeover,
+            client_no_context_takeover: config.server_no_context_takeover,
+        });
+
+        let message = br#"{"hello": "world", "hello2": "world", "hello3": "world"}"#;
+        for _ in 0..3 {
+            let compressed = server.compress(message);
+            assert!(compressed.len() < message.len());
+            assert_eq!(client.decompress(&compressed, 1024).unwrap(), &message[..]);
+        }
+
+        let compressed = server.compress(b"");
+        assert_eq!(client.decompress(&compressed, 1024).unwrap(), b"");
+    }
+
+    #[test]
+    fn round_trip_context_takeover() {
+        round_trip(Config {
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+        });
+    }
+
+    #[test]
+    fn round_trip_no_context_takeover() {
+        round_trip(Config {
+            server_no_context_takeover: true,
+            client_no_context_takeover: true,
+        });
+    }
+
+    #[test]
+    fn rfc_example() {
+        // Example from section 7.2.3.1 of RFC 7692.
+        let config = offer("permessage-deflate").unwrap();
+        let mut deflate = PerMessageDeflate::new(config);
+        let data = [0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        assert_eq!(deflate.decompress(&data, 1024).unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn decompress_errors() {
+        let config = offer("permessage-deflate").unwrap();
+
+        let mut deflate = PerMessageDeflate::new(config);
+        assert_eq!(
+            deflate.decompress(&[0xff, 0xff, 0xff], 1024),
+            Err((1007, "Invalid compressed data"))
+        );
+
+        let mut deflate = PerMessageDeflate::new(config);
+        let compressed = deflate.compress(&[0; 100_000]);
+        let mut deflate = PerMessageDeflate::new(config);
+        assert_eq!(
+            deflate.decompress(&compressed, 1000),
+            Err((1009, "Message too large"))
+        );
+    }
+}
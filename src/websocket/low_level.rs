@@ -11,7 +11,8 @@
 //!
 //! Usage:
 //!
-//! - Create a `StateMachine` with `StateMachine::new`.
+//! - Create a `StateMachine` with `StateMachine::new`, and optionally limit the size of the
+//!   frames with `StateMachine::set_max_frame_len`.
 //! - Whenever data is received on the socket, call `StateMachine::feed`.
 //! - The returned iterator produces zero, one or multiple `Element` objects containing what was
 //!   received.
@@ -31,6 +32,8 @@ pub enum Element<'a> {
     FrameStart {
         /// If true, this is the last frame of the message.
         fin: bool,
+        /// If true, the RSV1 bit is set. Can only happen after `StateMachine::set_compression`.
+        compressed: bool,
         /// Length of the frame in bytes.
         length: u64,
         /// Opcode. See https://tools.ietf.org/html/rfc6455#section-5.2.
@@ -47,6 +50,8 @@ pub enum Element<'a> {
 
     /// An error in the stream. The connection must be dropped ASAP.
     Error {
+        /// Status code to send to the client in the close frame.
+        code: u16,
         /// A description of the error. Can or cannot be be returned to the client.
         desc: &'static str,
     },
@@ -69,6 +74,10 @@ pub struct StateMachine {
     inner: StateMachineInner,
     // Contains the start of the header. Must be empty if `inner` is equal to `InData`.
     buffer: Vec<u8>, // TODO: use SmallVec?
+    // Maximum length of the payload of a frame.
+    max_frame_len: u64,
+    // If true, the RSV1 bit is allowed.
+    compression: bool,
 }
 
 enum StateMachineInner {
@@ -93,9 +102,27 @@ impl StateMachine {
         StateMachine {
             inner: StateMachineInner::InHeader,
             buffer: Vec::with_capacity(14),
+            max_frame_len: u64::MAX,
+            compression: false,
         }
     }
 
+    /// Allows the RSV1 bit, which indicates a compressed message when the `permessage-deflate`
+    /// extension is in use. Otherwise, all the reserved bits must be zero.
+    #[inline]
+    pub fn set_compression(&mut self, compression: bool) {
+        self.compression = compression;
+    }
+
+    /// Sets the maximum length of the payload of a frame. A frame whose header announces a
+    /// larger length produces an `Element::Error` before any of its data is processed.
+    ///
+    /// Frames are unlimited by default.
+    #[inline]
+    pub fn set_max_frame_len(&mut self, max: u64) {
+        self.max_frame_len = max;
+    }
+
     /// Feeds data to the state machine. Returns an iterator to the list of elements that were
     /// received.
     #[inline]
@@ -169,9 +196,11 @@ impl<'a> Iterator for ElementsIter<'a> {
                     (first_byte, second_byte)
                 };
 
-                // Reserved bits must be zero, otherwise error.
-                if (first_byte & 0x70) != 0 {
+                // Reserved bits must be zero, otherwise error. RSV1 is used by compression.
+                let reserved = if self.state.compression { 0x30 } else { 0x70 };
+                if (first_byte & reserved) != 0 {
                     return Some(Element::Error {
+                        code: 1002,
                         desc: "Reserved bits must be zero",
                     });
                 }
@@ -179,6 +208,7 @@ impl<'a> Iterator for ElementsIter<'a> {
                 // Client-to-server messages **must** be encoded.
                 if (second_byte & 0x80) == 0 {
                     return Some(Element::Error {
+                        code: 1002,
                         desc: "Client-to-server messages must be masked",
                     });
                 }
@@ -214,6 +244,7 @@ impl<'a> Iterator for ElementsIter<'a> {
                             // The most significant bit must be zero according to the specs.
                             if (length & 0x8000000000000000) != 0 {
                                 return Some(Element::Error {
+                                    code: 1002,
                                     desc: "Most-significant bit of the length must be zero",
                                 });
                             }
@@ -233,6 +264,14 @@ impl<'a> Iterator for ElementsIter<'a> {
                     }
                 };
 
+                // Refuse frames that are too large before the user starts buffering them.
+                if length > self.state.max_frame_len {
+                    return Some(Element::Error {
+                        code: 1009,
+                        desc: "Frame too large",
+                    });
+                }
+
                 // Builds a slice containing the start of the data.
                 let data_start = {
                     let data_start_off = match second_byte & 0x7f {
@@ -255,6 +294,7 @@ impl<'a> Iterator for ElementsIter<'a> {
                 };
                 Some(Element::FrameStart {
                     fin: (first_byte & 0x80) != 0,
+                    compressed: (first_byte & 0x40) != 0,
                     length,
                     opcode: first_byte & 0xf,
                 })
@@ -358,6 +398,7 @@ mod tests {
             iter.next().unwrap(),
             Element::FrameStart {
                 fin: true,
+                compressed: false,
                 length: 5,
                 opcode: 1
             }
@@ -374,4 +415,70 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn frame_too_large() {
+        let mut machine = StateMachine::new();
+        machine.set_max_frame_len(1024);
+
+        // Binary frame announcing a length of 2^40 bytes.
+        let data = &[
+            0x82, 0xff, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x37, 0xfa, 0x21, 0x3d,
+            0x7f,
+        ];
+        let mut iter = machine.feed(data);
+        assert_eq!(
+            iter.next().unwrap(),
+            Element::Error {
+                code: 1009,
+                desc: "Frame too large",
+            }
+        );
+    }
+
+    #[test]
+    fn frame_at_limit() {
+        let mut machine = StateMachine::new();
+        machine.set_max_frame_len(5);
+
+        let data = &[
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let mut iter = machine.feed(data);
+        assert_eq!(
+            iter.next().unwrap(),
+            Element::FrameStart {
+                fin: true,
+                compressed: false,
+                length: 5,
+                opcode: 1
+            }
+        );
+    }
+
+    #[test]
+    fn compressed() {
+        let data = &[0xc1, 0x80, 0x00, 0x00, 0x00, 0x00];
+
+        let mut machine = StateMachine::new();
+        assert_eq!(
+            machine.feed(data).next().unwrap(),
+            Element::Error {
+                code: 1002,
+                desc: "Reserved bits must be zero",
+            }
+        );
+
+        let mut machine = StateMachine::new();
+        machine.set_compression(true);
+        assert_eq!(
+            machine.feed(data).next().unwrap(),
+            Element::FrameStart {
+                fin: true,
+                compressed: true,
+                length: 0,
+                opcode: 1
+            }
+        );
+    }
 }
@@ -20,7 +20,8 @@
 //! - The `start()` function also returns a `Receiver<Websocket>` object. Once that `Receiver`
 //!   contains a value, the connection has been initiated.
 //! - You can then use the `Websocket` object to communicate with the client through the `Read`
-//!   and `Write` traits.
+//!   and `Write` traits. The simplest way is to call `Websocket::for_each_message`, which answers
+//!   each message with the value returned by a closure.
 //!
 //! # Subprotocols
 //!
@@ -41,8 +42,24 @@
 //! - Your route only handles one subprotocol. Just pass this subprotocol to `start()` and you will
 //!   get an error (which you can handle for example with `try_or_400!`) if it's not supported by
 //!   the client.
-//! - Your route supports multiple subprotocols. This is the most complex situation as you will
-//!   have to enumerate the protocols with `requested_protocols()` and choose one.
+//! - Your route supports multiple subprotocols. Pass them to `start_choosing()`, which uses the
+//!   first one that the client supports. If you need more control, you can also enumerate the
+//!   protocols with `requested_protocols()`, choose one and pass it to `start()`.
+//!
+//! # Compression
+//!
+//! The `permessage-deflate` extension, supported by all major browsers, compresses the messages.
+//! It is disabled by default. Set `Options::compression` and use `start_with()` to enable it if
+//! the client supports it. Messages are then transparently compressed and decompressed by the
+//! `Websocket` object.
+//!
+//! The extension requires the `websocket-deflate` feature, which isn't enabled by default.
+//!
+//! # Origins
+//!
+//! Browsers don't apply the same-origin policy to websockets. If the connection relies on the
+//! cookies of the user, use `start_with()` with `Options::origins` so that other websites can't
+//! open it.
 //!
 //! # Example
 //!
@@ -69,6 +86,8 @@ pub use self::websocket::Message;
 pub use self::websocket::SendError;
 pub use self::websocket::Websocket;
 
+use self::websocket::WebsocketUpgrade;
+
 use base64::{prelude::BASE64_STANDARD, Engine as _};
 use sha1_smol::Sha1;
 use std::borrow::Cow;
@@ -80,6 +99,7 @@ use std::vec::IntoIter as VecIntoIter;
 use Request;
 use Response;
 
+mod deflate;
 mod low_level;
 #[allow(clippy::module_inception)]
 mod websocket;
@@ -100,6 +120,9 @@ pub enum WebsocketError {
 
     /// The subprotocol passed to the function was not requested by the client.
     WrongSubprotocol,
+
+    /// The `Origin` header of the request is not in `Options::origins`.
+    OriginNotAllowed,
 }
 
 impl error::Error for WebsocketError {}
@@ -114,12 +137,63 @@ impl fmt::Display for WebsocketError {
             WebsocketError::WrongSubprotocol => {
                 "the subprotocol passed to the function was not requested by the client"
             }
+            WebsocketError::OriginNotAllowed => "the origin of the request is not allowed",
         };
 
         write!(fmt, "{}", description)
     }
 }
 
+/// Options of the websocket connection, passed to `start_with`.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// use rouille::Request;
+/// use rouille::Response;
+/// use rouille::websocket;
+/// # fn main() {}
+///
+/// fn handle_request(request: &Request) -> Response {
+///     let options = websocket::Options {
+///         origins: Some(vec!["https://example.com".to_owned()]),
+///         protocols: vec!["chat".into()],
+///         ..Default::default()
+///     };
+///
+///     let (response, websocket) = try_or_400!(websocket::start_with(request, &options));
+///     // ...
+///     response
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Origins that are allowed to open a connection, written like `https://example.com` or
+    /// `http://localhost:8000` and compared case-insensitively. If the `Origin` header of the
+    /// request isn't in the list, `WebsocketError::OriginNotAllowed` is returned. `None`, the
+    /// default, allows all origins.
+    ///
+    /// Browsers don't apply the same-origin policy to websockets, so without this check any
+    /// website visited by a user can open a connection to your server with the user's cookies.
+    /// Requests without an `Origin` header are always accepted, as they don't come from a
+    /// browser.
+    pub origins: Option<Vec<String>>,
+
+    /// Subprotocols supported by the server, in order of preference. The first one that was
+    /// requested by the client is used, and `WebsocketError::WrongSubprotocol` is returned if the
+    /// client didn't request any of them. If the list is empty, the default, no subprotocol is
+    /// used.
+    pub protocols: Vec<Cow<'static, str>>,
+
+    /// Enables the `permessage-deflate` extension if the client supports it. Defaults to
+    /// `false`. Has no effect without the `websocket-deflate` feature.
+    ///
+    /// Compression is worth it for large messages that compress well, like JSON, but costs
+    /// memory and CPU time for each connection.
+    pub compression: bool,
+}
+
 /// Builds a `Response` that initiates the websocket protocol.
 pub fn start<S>(
     request: &Request,
@@ -128,8 +202,30 @@ pub fn start<S>(
 where
     S: Into<Cow<'static, str>>,
 {
-    let subprotocol = subprotocol.map(|s| s.into());
+    let options = Options {
+        protocols: subprotocol.map(|s| s.into()).into_iter().collect(),
+        ..Options::default()
+    };
+    start_with(request, &options)
+}
+
+/// Same as `start`, but with the given `Options`.
+///
+/// The request is first checked to be a websocket request, then its origin and its
+/// subprotocols are checked against `options`.
+pub fn start_with(
+    request: &Request,
+    options: &Options,
+) -> Result<(Response, mpsc::Receiver<Websocket>), WebsocketError> {
+    start_impl(request, options).map(|(response, websocket, _)| (response, websocket))
+}
 
+// Same as `start_with`, but also returns the index of the chosen subprotocol in
+// `options.protocols`.
+fn start_impl(
+    request: &Request,
+    options: &Options,
+) -> Result<(Response, mpsc::Receiver<Websocket>, Option<usize>), WebsocketError> {
     if request.method() != "GET" {
         return Err(WebsocketError::InvalidWebsocketRequest);
     }
@@ -156,12 +252,6 @@ where
         _ => return Err(WebsocketError::InvalidWebsocketRequest),
     }
 
-    if let Some(ref sp) = subprotocol {
-        if !requested_protocols(request).any(|p| &p == sp) {
-            return Err(WebsocketError::WrongSubprotocol);
-        }
-    }
-
     let key = {
         let in_key = match request.header("Sec-WebSocket-Key") {
             Some(h) => h,
@@ -171,6 +261,37 @@ where
         convert_key(in_key)
     };
 
+    if let (Some(allowed_origins), Some(origin)) = (&options.origins, request.header("Origin")) {
+        let origin = origin.trim();
+        if !allowed_origins
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+        {
+            return Err(WebsocketError::OriginNotAllowed);
+        }
+    }
+
+    let protocol_index = if options.protocols.is_empty() {
+        None
+    } else {
+        let requested = requested_protocols(request).collect::<Vec<_>>();
+        match options
+            .protocols
+            .iter()
+            .position(|p| requested.iter().any(|r| r == p))
+        {
+            Some(index) => Some(index),
+            None => return Err(WebsocketError::WrongSubprotocol),
+        }
+    };
+    let subprotocol = protocol_index.map(|index| options.protocols[index].clone());
+
+    let deflate = if options.compression {
+        deflate::negotiate(request)
+    } else {
+        None
+    };
+
     let (tx, rx) = mpsc::channel();
 
     let mut response = Response::text("");
@@ -178,14 +299,68 @@ where
     response
         .headers
         .push(("Upgrade".into(), "websocket".into()));
-    if let Some(sp) = subprotocol {
-        response.headers.push(("Sec-Websocket-Protocol".into(), sp));
+    if let Some(ref sp) = subprotocol {
+        response
+            .headers
+            .push(("Sec-WebSocket-Protocol".into(), sp.clone()));
+    }
+    if let Some(ref config) = deflate {
+        response.headers.push((
+            "Sec-WebSocket-Extensions".into(),
+            config.header_value().into(),
+        ));
     }
     response
         .headers
-        .push(("Sec-Websocket-Accept".into(), key.into()));
-    response.upgrade = Some(Box::new(tx) as Box<_>);
-    Ok((response, rx))
+        .push(("Sec-WebSocket-Accept".into(), key.into()));
+    response.upgrade = Some(Box::new(WebsocketUpgrade {
+        sender: tx,
+        protocol: subprotocol,
+        deflate,
+    }) as Box<_>);
+    Ok((response, rx, protocol_index))
+}
+
+/// Same as `start`, but chooses the subprotocol among a list of protocols supported by the server.
+///
+/// The protocols are tried in order, and the first one that was requested by the client is used.
+/// It is returned alongside the `Response` and the `Receiver`. Returns
+/// `WebsocketError::WrongSubprotocol` if the client didn't request any of them, or
+/// `WebsocketError::InvalidWebsocketRequest` if the request isn't a websocket request at all.
+///
+/// This is the same as passing the protocols in `Options::protocols` to `start_with`.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// use rouille::Request;
+/// use rouille::Response;
+/// use rouille::websocket;
+/// # fn main() {}
+///
+/// fn handle_request(request: &Request) -> Response {
+///     let (response, websocket, protocol) =
+///         try_or_400!(websocket::start_choosing(request, &["superchat", "chat"]));
+///     // ... spawn a thread that waits on `websocket` and speaks `protocol` ...
+///     response
+/// }
+/// ```
+pub fn start_choosing<'a>(
+    request: &Request,
+    protocols: &[&'a str],
+) -> Result<(Response, mpsc::Receiver<Websocket>, &'a str), WebsocketError> {
+    let options = Options {
+        protocols: protocols.iter().map(|p| (*p).to_owned().into()).collect(),
+        ..Options::default()
+    };
+    let (response, websocket, index) = start_impl(request, &options)?;
+    // `start_impl` doesn't require a subprotocol if the list is empty.
+    let index = match index {
+        Some(index) => index,
+        None => return Err(WebsocketError::WrongSubprotocol),
+    };
+    Ok((response, websocket, protocols[index]))
 }
 
 /// Returns a list of the websocket protocols requested by the client.
@@ -248,3 +423,441 @@ fn convert_key(input: &str) -> String {
 
     BASE64_STANDARD.encode(sha1.digest().bytes())
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "websocket-deflate")]
+    use super::deflate::{negotiate, PerMessageDeflate};
+    use super::start;
+    use super::start_choosing;
+    use super::start_with;
+    use super::Message;
+    use super::Options;
+    use super::Websocket;
+    use super::WebsocketError;
+    use std::io;
+    use std::io::{Cursor, Read, Write};
+    use std::sync::{Arc, Mutex};
+    use Request;
+
+    // Socket whose reads come from `input` and whose writes are stored in `output`.
+    struct FakeSocket {
+        input: Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for FakeSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakeSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn websocket_request(protocols: &str) -> Request {
+        Request::fake_http(
+            "GET",
+            "/",
+            vec![
+                ("Host".to_owned(), "localhost".to_owned()),
+                ("Connection".to_owned(), "Upgrade".to_owned()),
+                ("Upgrade".to_owned(), "websocket".to_owned()),
+                ("Sec-WebSocket-Version".to_owned(), "13".to_owned()),
+                (
+                    "Sec-WebSocket-Key".to_owned(),
+                    "dGhlIHNhbXBsZSBub25jZQ==".to_owned(),
+                ),
+                ("Sec-WebSocket-Protocol".to_owned(), protocols.to_owned()),
+            ],
+            vec![],
+        )
+    }
+
+    #[test]
+    fn start_choosing_overlapping() {
+        let request = websocket_request("chat, superchat");
+        let (response, _, protocol) = start_choosing(&request, &["superchat", "chat"]).unwrap();
+        assert_eq!(protocol, "superchat");
+        assert_eq!(response.status_code, 101);
+        assert!(response
+            .headers
+            .iter()
+            .any(|(k, v)| k.eq_ignore_ascii_case("Sec-WebSocket-Protocol") && v == "superchat"));
+
+        let (_, _, protocol) = start_choosing(&request, &["other", "chat"]).unwrap();
+        assert_eq!(protocol, "chat");
+    }
+
+    #[test]
+    fn protocol() {
+        let request = websocket_request("chat, superchat");
+
+        let (mut response, websocket, _) = start_choosing(&request, &["superchat"]).unwrap();
+        response
+            .upgrade
+            .as_mut()
+            .unwrap()
+            .build(Box::new(Cursor::new(Vec::new())));
+        assert_eq!(websocket.recv().unwrap().protocol(), Some("superchat"));
+
+        let (mut response, websocket) = start::<&'static str>(&request, None).unwrap();
+        response
+            .upgrade
+            .as_mut()
+            .unwrap()
+            .build(Box::new(Cursor::new(Vec::new())));
+        assert_eq!(websocket.recv().unwrap().protocol(), None);
+    }
+
+    // Options of the connections of `websocket_request` that use compression.
+    #[cfg(feature = "websocket-deflate")]
+    fn compressed() -> Options {
+        Options {
+            protocols: vec!["chat".into()],
+            compression: true,
+            ..Options::default()
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "websocket-deflate")]
+    fn permessage_deflate() {
+        let request = websocket_request("chat").with_header(
+            "Sec-WebSocket-Extensions",
+            "permessage-deflate; client_max_window_bits",
+        );
+        let config = negotiate(&request).unwrap();
+        let (mut response, websocket) = start_with(&request, &compressed()).unwrap();
+        assert!(response.headers.iter().any(|(k, v)| {
+            k.eq_ignore_ascii_case("Sec-WebSocket-Extensions") && v == "permessage-deflate"
+        }));
+
+        // Compressed and masked text frame sent by the client.
+        let text = "Hello, hello, hello, hello!";
+        let mut client = PerMessageDeflate::new(config);
+        let payload = client.compress(text.as_bytes());
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![0xc1, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(n, b)| b ^ mask[n % 4]));
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        response
+            .upgrade
+            .as_mut()
+            .unwrap()
+            .build(Box::new(FakeSocket {
+                input: Cursor::new(frame),
+                output: output.clone(),
+            }));
+        let mut websocket = websocket.recv().unwrap();
+        assert_eq!(websocket.next(), Some(Message::Text(text.to_owned())));
+
+        // Compressed text frame sent by the server.
+        websocket.send_text(text).unwrap();
+        let output = output.lock().unwrap();
+        assert_eq!(output[0], 0xc1);
+        assert_eq!(output[1] as usize, output.len() - 2);
+        assert_eq!(
+            client.decompress(&output[2..], 1024).unwrap(),
+            text.as_bytes()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "websocket-deflate")]
+    fn permessage_deflate_invalid_data() {
+        let request =
+            websocket_request("chat").with_header("Sec-WebSocket-Extensions", "permessage-deflate");
+        let (mut response, websocket) = start_with(&request, &compressed()).unwrap();
+
+        // Compressed binary frame whose payload isn't valid deflate data.
+        let input = vec![0xc2, 0x83, 0, 0, 0, 0, 0xff, 0xff, 0xff];
+        let output = Arc::new(Mutex::new(Vec::new()));
+        response
+            .upgrade
+            .as_mut()
+            .unwrap()
+            .build(Box::new(FakeSocket {
+                input: Cursor::new(input),
+                output: output.clone(),
+            }));
+
+        assert_eq!(websocket.recv().unwrap().next(), None);
+        assert_eq!(
+            &output.lock().unwrap()[..],
+            b"\x88\x19\x03\xefInvalid compressed data"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "websocket-deflate")]
+    fn permessage_deflate_message_too_large() {
+        let request =
+            websocket_request("chat").with_header("Sec-WebSocket-Extensions", "permessage-deflate");
+        let config = negotiate(&request).unwrap();
+        let (mut response, websocket) = start_with(&request, &compressed()).unwrap();
+
+        // Compressed text message split in two frames, with a mask of zeroes. Each frame fits
+        // in the limit, but not the whole message.
+        let payload = PerMessageDeflate::new(config).compress(b"Hello, hello, hello, hello!");
+        let (first, second) = payload.split_at(payload.len() / 2);
+        let mut input = vec![0x41, 0x80 | first.len() as u8, 0, 0, 0, 0];
+        input.extend_from_slice(first);
+        input.extend_from_slice(&[0x80, 0x80 | second.len() as u8, 0, 0, 0, 0]);
+        input.extend_from_slice(second);
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        response
+            .upgrade
+            .as_mut()
+            .unwrap()
+            .build(Box::new(FakeSocket {
+                input: Cursor::new(input),
+                output: output.clone(),
+            }));
+        let mut websocket = websocket.recv().unwrap();
+        websocket.set_max_frame_len(payload.len() as u64 - 1);
+
+        assert_eq!(websocket.next(), None);
+        assert_eq!(
+            &output.lock().unwrap()[..],
+            b"\x88\x13\x03\xf1Message too large"
+        );
+    }
+
+    #[test]
+    fn compression_disabled_by_default() {
+        let request =
+            websocket_request("chat").with_header("Sec-WebSocket-Extensions", "permessage-deflate");
+        let (response, _) = start(&request, Some("chat")).unwrap();
+        assert_eq!(response.status_code, 101);
+        assert!(!response
+            .headers
+            .iter()
+            .any(|(k, _)| k.eq_ignore_ascii_case("Sec-WebSocket-Extensions")));
+    }
+
+    #[test]
+    fn permessage_deflate_not_requested() {
+        let request = websocket_request("chat");
+        let options = Options {
+            protocols: vec!["chat".into()],
+            compression: true,
+            ..Options::default()
+        };
+        let (response, _) = start_with(&request, &options).unwrap();
+        assert!(!response
+            .headers
+            .iter()
+            .any(|(k, _)| k.eq_ignore_ascii_case("Sec-WebSocket-Extensions")));
+    }
+
+    #[test]
+    fn for_each_message_echo() {
+        let request = websocket_request("chat");
+        let (mut response, websocket) = start(&request, Some("chat")).unwrap();
+
+        // Text frame containing "Hello" followed by a binary frame, both masked.
+        let input = vec![
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58, 0x82, 0x82, 0x00,
+            0x00, 0x00, 0x00, 0x01, 0x02,
+        ];
+        let output = Arc::new(Mutex::new(Vec::new()));
+        response
+            .upgrade
+            .as_mut()
+            .unwrap()
+            .build(Box::new(FakeSocket {
+                input: Cursor::new(input),
+                output: output.clone(),
+            }));
+
+        let mut received = Vec::new();
+        websocket.recv().unwrap().for_each_message(|message| {
+            received.push(message.clone());
+            match message {
+                Message::Text(text) => Some(Message::Text(text.to_uppercase())),
+                Message::Binary(_) => None,
+            }
+        });
+
+        assert_eq!(
+            received,
+            vec![
+                Message::Text("Hello".to_owned()),
+                Message::Binary(vec![1, 2])
+            ]
+        );
+        assert_eq!(&output.lock().unwrap()[..], b"\x81\x05HELLO");
+    }
+
+    // Upgrades the connection of `websocket_request` and returns the `Websocket` that reads
+    // `input`, and what it writes.
+    fn upgraded(input: Vec<u8>) -> (Websocket, Arc<Mutex<Vec<u8>>>) {
+        let (mut response, websocket) = start(&websocket_request("chat"), Some("chat")).unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        response
+            .upgrade
+            .as_mut()
+            .unwrap()
+            .build(Box::new(FakeSocket {
+                input: Cursor::new(input),
+                output: output.clone(),
+            }));
+        (websocket.recv().unwrap(), output)
+    }
+
+    #[test]
+    fn frame_too_large() {
+        // Binary frame of 10 bytes, with a mask of zeroes.
+        let mut input = vec![0x82, 0x8a, 0, 0, 0, 0];
+        input.extend_from_slice(&[0; 10]);
+        let (mut websocket, output) = upgraded(input);
+        websocket.set_max_frame_len(8);
+
+        assert_eq!(websocket.next(), None);
+        assert_eq!(
+            &output.lock().unwrap()[..],
+            b"\x88\x11\x03\xf1Frame too large"
+        );
+    }
+
+    #[test]
+    fn fragmented_message_too_large() {
+        // Text message made of two frames of 5 bytes, with a mask of zeroes.
+        let mut input = vec![0x01, 0x85, 0, 0, 0, 0];
+        input.extend_from_slice(b"hello");
+        input.extend_from_slice(&[0x80, 0x85, 0, 0, 0, 0]);
+        input.extend_from_slice(b"world");
+        let (mut websocket, output) = upgraded(input);
+        websocket.set_max_frame_len(8);
+
+        assert_eq!(websocket.next(), None);
+        assert_eq!(
+            &output.lock().unwrap()[..],
+            b"\x88\x13\x03\xf1Message too large"
+        );
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let input = vec![0x81, 0x82, 0, 0, 0, 0, 0xc3, 0x28];
+        let (mut websocket, output) = upgraded(input);
+
+        assert_eq!(websocket.next(), None);
+        assert_eq!(
+            &output.lock().unwrap()[..],
+            b"\x88\x18\x03\xefInvalid UTF-8 encoding"
+        );
+    }
+
+    #[test]
+    fn handshake_headers() {
+        let request = websocket_request("chat");
+
+        let (response, _) = start(&request, Some("chat")).unwrap();
+        assert_eq!(response.status_code, 101);
+        assert_eq!(
+            response
+                .headers
+                .iter()
+                .map(|(k, v)| (&k[..], &v[..]))
+                .filter(|(k, _)| k.starts_with("Sec-"))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Sec-WebSocket-Protocol", "chat"),
+                ("Sec-WebSocket-Accept", "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="),
+            ]
+        );
+
+        let (response, _) = start::<&'static str>(&request, None).unwrap();
+        assert!(!response
+            .headers
+            .iter()
+            .any(|(k, _)| k.eq_ignore_ascii_case("Sec-WebSocket-Protocol")));
+    }
+
+    #[test]
+    fn origins() {
+        let options = Options {
+            origins: Some(vec![
+                "https://example.com".to_owned(),
+                "http://localhost:8000".to_owned(),
+            ]),
+            protocols: vec!["chat".into()],
+            ..Options::default()
+        };
+
+        let request = websocket_request("chat").with_header("Origin", "https://Example.com");
+        assert!(start_with(&request, &options).is_ok());
+
+        let request = websocket_request("chat").with_header("Origin", "http://localhost:8000");
+        assert!(start_with(&request, &options).is_ok());
+
+        for origin in &["https://evil.com", "http://example.com", "null"] {
+            let request = websocket_request("chat").with_header("Origin", *origin);
+            match start_with(&request, &options) {
+                Err(WebsocketError::OriginNotAllowed) => (),
+                _ => panic!("{}", origin),
+            }
+        }
+
+        // Non-browser clients don't send an `Origin`.
+        let request = websocket_request("chat");
+        assert!(start_with(&request, &options).is_ok());
+
+        // All origins are allowed by default.
+        let request = websocket_request("chat").with_header("Origin", "https://evil.com");
+        assert!(start_with(&request, &Options::default()).is_ok());
+    }
+
+    #[test]
+    fn start_with_checks_upgrade_first() {
+        let options = Options {
+            origins: Some(vec!["https://example.com".to_owned()]),
+            ..Options::default()
+        };
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("Origin".to_owned(), "https://evil.com".to_owned())],
+            vec![],
+        );
+        match start_with(&request, &options) {
+            Err(WebsocketError::InvalidWebsocketRequest) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn start_choosing_disjoint() {
+        let request = websocket_request("chat, superchat");
+        match start_choosing(&request, &["mqtt", "wamp"]) {
+            Err(WebsocketError::WrongSubprotocol) => (),
+            _ => panic!(),
+        }
+
+        match start_choosing(&request, &[]) {
+            Err(WebsocketError::WrongSubprotocol) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn start_choosing_not_websocket() {
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        match start_choosing(&request, &["chat"]) {
+            Err(WebsocketError::InvalidWebsocketRequest) => (),
+            _ => panic!(),
+        }
+    }
+}
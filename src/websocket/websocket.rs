@@ -7,6 +7,8 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::io;
 use std::io::Write;
 use std::mem;
@@ -14,8 +16,12 @@ use std::sync::mpsc::Sender;
 use ReadWrite;
 use Upgrade;
 
+use websocket::deflate::{Config as DeflateConfig, PerMessageDeflate};
 use websocket::low_level;
 
+// Default value of `Websocket::set_max_frame_len`.
+const DEFAULT_MAX_FRAME_LEN: u64 = 16 * 1024 * 1024;
+
 /// A successful websocket. An open channel of communication. Implements `Read` and `Write`.
 pub struct Websocket {
     // The socket. `None` if closed.
@@ -31,10 +37,20 @@ pub struct Websocket {
     current_frame_opcode: u8,
     // Fin flag of the fragment currently being processed.
     current_frame_fin: bool,
+    // True if the fragmented message currently being processed is compressed.
+    current_message_compressed: bool,
     // Data of the fragment currently being processed.
     current_frame_payload: Vec<u8>,
+    // RSV1 flag of the fragment currently being processed.
+    current_frame_compressed: bool,
     // Queue of the messages that are going to be returned by `next()`.
-    messages_in_queue: Vec<Message>,
+    messages_in_queue: VecDeque<Message>,
+    // Subprotocol that was passed to `start()`.
+    protocol: Option<Cow<'static, str>>,
+    // Compression state, if the `permessage-deflate` extension was negotiated.
+    deflate: Option<PerMessageDeflate>,
+    // Same as the maximum frame length of `state_machine`.
+    max_frame_len: u64,
 }
 
 /// A message produced by a websocket connection.
@@ -74,13 +90,7 @@ impl Websocket {
     /// If the client is in javascript, the message will contain a string.
     #[inline]
     pub fn send_text(&mut self, data: &str) -> Result<(), SendError> {
-        let socket = match self.socket {
-            Some(ref mut s) => s,
-            None => return Err(SendError::Closed),
-        };
-
-        send(data.as_bytes(), Write::by_ref(socket), 0x1)?;
-        Ok(())
+        self.send_message(data.as_bytes(), 0x1)
     }
 
     /// Sends binary data over the websocket.
@@ -90,13 +100,52 @@ impl Websocket {
     /// If the client is in javascript, the message will contain a blob or an arraybuffer.
     #[inline]
     pub fn send_binary(&mut self, data: &[u8]) -> Result<(), SendError> {
-        let socket = match self.socket {
-            Some(ref mut s) => s,
-            None => return Err(SendError::Closed),
-        };
+        self.send_message(data, 0x2)
+    }
 
-        send(data, Write::by_ref(socket), 0x2)?;
-        Ok(())
+    /// Reads messages until the connection is closed, and calls `callback` for each of them. If
+    /// the callback returns a message, it is sent back to the client.
+    ///
+    /// Pings and close requests from the client are handled automatically. The function returns
+    /// when the connection has been closed, or if sending a reply failed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate rouille;
+    /// use std::thread;
+    /// use rouille::Request;
+    /// use rouille::Response;
+    /// use rouille::websocket;
+    /// # fn main() {}
+    ///
+    /// fn handle_request(request: &Request) -> Response {
+    ///     let (response, websocket) = try_or_400!(websocket::start(request, Some("echo")));
+    ///
+    ///     thread::spawn(move || {
+    ///         if let Ok(mut websocket) = websocket.recv() {
+    ///             websocket.for_each_message(|message| Some(message));
+    ///         }
+    ///     });
+    ///
+    ///     response
+    /// }
+    /// ```
+    pub fn for_each_message<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(Message) -> Option<Message>,
+    {
+        while let Some(message) = self.next() {
+            let result = match callback(message) {
+                Some(Message::Text(text)) => self.send_text(&text),
+                Some(Message::Binary(data)) => self.send_binary(&data),
+                None => Ok(()),
+            };
+
+            if result.is_err() {
+                break;
+            }
+        }
     }
 
     /// Returns `true` if the websocket has been closed by either the client (voluntarily or not)
@@ -106,23 +155,94 @@ impl Websocket {
         self.socket.is_none()
     }
 
-    // TODO: give access to close reason
-}
+    /// Returns the subprotocol that was passed to `start()`, or chosen by `start_choosing()`.
+    /// Returns `None` if no subprotocol was used.
+    #[inline]
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
 
-impl Upgrade for Sender<Websocket> {
-    fn build(&mut self, socket: Box<dyn ReadWrite + Send>) {
-        let websocket = Websocket {
+    /// Sets the maximum length in bytes of the payload of a frame or of a message sent by the
+    /// client. The default is 16 MiB.
+    ///
+    /// The connection is closed with the code 1009 as soon as the client announces a larger
+    /// frame, or when the frames of a fragmented message add up to more than this length. If the
+    /// `permessage-deflate` extension is in use, this also limits the size of messages once
+    /// decompressed.
+    #[inline]
+    pub fn set_max_frame_len(&mut self, max: u64) {
+        self.state_machine.set_max_frame_len(max);
+        self.max_frame_len = max;
+    }
+
+    // Builds the object once the connection has been upgraded.
+    fn new(
+        socket: Box<dyn ReadWrite + Send>,
+        protocol: Option<Cow<'static, str>>,
+        deflate: Option<DeflateConfig>,
+    ) -> Websocket {
+        let mut state_machine = low_level::StateMachine::new();
+        state_machine.set_max_frame_len(DEFAULT_MAX_FRAME_LEN);
+        state_machine.set_compression(deflate.is_some());
+
+        Websocket {
             socket: Some(socket),
-            state_machine: low_level::StateMachine::new(),
+            state_machine,
             current_message_binary: false,
             current_message_payload: Vec::new(),
+            current_message_compressed: false,
             current_frame_opcode: 0,
             current_frame_fin: false,
             current_frame_payload: Vec::new(),
-            messages_in_queue: Vec::new(),
+            current_frame_compressed: false,
+            messages_in_queue: VecDeque::new(),
+            protocol,
+            deflate: deflate.map(PerMessageDeflate::new),
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+
+    // Sends a data message, compressing it if the `permessage-deflate` extension is in use.
+    fn send_message(&mut self, data: &[u8], opcode: u8) -> Result<(), SendError> {
+        let socket = match self.socket {
+            Some(ref mut s) => s,
+            None => return Err(SendError::Closed),
         };
 
-        let _ = self.send(websocket);
+        match self.deflate {
+            Some(ref mut deflate) => {
+                let compressed = deflate.compress(data);
+                send_frame(&compressed, Write::by_ref(socket), 0xc0 | opcode)?
+            }
+            None => send(data, Write::by_ref(socket), opcode)?,
+        }
+        Ok(())
+    }
+
+    // TODO: give access to close reason
+}
+
+/// Builds a `Websocket` that uses the given subprotocol once the connection has been upgraded.
+pub struct WebsocketUpgrade {
+    /// Where to send the `Websocket`.
+    pub sender: Sender<Websocket>,
+    /// Subprotocol that was chosen by `start()`.
+    pub protocol: Option<Cow<'static, str>>,
+    /// Parameters of the `permessage-deflate` extension, if it was negotiated by `start()`.
+    pub deflate: Option<DeflateConfig>,
+}
+
+impl Upgrade for WebsocketUpgrade {
+    fn build(&mut self, socket: Box<dyn ReadWrite + Send>) {
+        let _ = self
+            .sender
+            .send(Websocket::new(socket, self.protocol.take(), self.deflate));
+    }
+}
+
+impl Upgrade for Sender<Websocket> {
+    fn build(&mut self, socket: Box<dyn ReadWrite + Send>) {
+        let _ = self.send(Websocket::new(socket, None, None));
     }
 }
 
@@ -135,8 +255,8 @@ impl Iterator for Websocket {
             self.socket.as_ref()?;
 
             // There may be some messages waiting to be processed.
-            if !self.messages_in_queue.is_empty() {
-                return Some(self.messages_in_queue.remove(0));
+            if let Some(message) = self.messages_in_queue.pop_front() {
+                return Some(message);
             }
 
             // Read `n` bytes in `buf`.
@@ -158,9 +278,27 @@ impl Iterator for Websocket {
             // Fill `messages_in_queue` by analyzing the packets.
             for element in self.state_machine.feed(&buf[0..n]) {
                 match element {
-                    low_level::Element::FrameStart { fin, opcode, .. } => {
+                    low_level::Element::FrameStart {
+                        fin,
+                        compressed,
+                        opcode,
+                        ..
+                    } => {
                         debug_assert!(self.current_frame_payload.is_empty());
+
+                        // Only the first frame of a data message can be marked as compressed.
+                        if compressed && opcode != 0x1 && opcode != 0x2 {
+                            let _ = send_close(
+                                1002,
+                                "Unexpected compressed frame",
+                                Write::by_ref(self.socket.as_mut().unwrap()),
+                            );
+                            self.socket = None;
+                            return None;
+                        }
+
                         self.current_frame_fin = fin;
+                        self.current_frame_compressed = compressed;
                         self.current_frame_opcode = opcode;
                     }
 
@@ -177,33 +315,66 @@ impl Iterator for Websocket {
                             match self.current_frame_opcode {
                                 // Frame is a continuation of the current message.
                                 0x0 => {
+                                    // The limit applies to whole messages as well, otherwise
+                                    // the client could send an endless series of small frames.
+                                    let len = self.current_message_payload.len()
+                                        + self.current_frame_payload.len();
+                                    if len as u64 > self.max_frame_len {
+                                        let _ = send_close(
+                                            1009,
+                                            "Message too large",
+                                            Write::by_ref(self.socket.as_mut().unwrap()),
+                                        );
+                                        self.socket = None;
+                                        return None;
+                                    }
+
                                     self.current_message_payload
                                         .append(&mut self.current_frame_payload);
 
                                     // If the message is finished, dispatch it.
                                     if self.current_frame_fin {
                                         let binary = mem::take(&mut self.current_message_payload);
+                                        let compressed = self.current_message_compressed;
+                                        let binary = match decode_payload(
+                                            &mut self.deflate,
+                                            binary,
+                                            compressed,
+                                            self.max_frame_len,
+                                        ) {
+                                            Ok(b) => b,
+                                            Err((code, desc)) => {
+                                                let _ = send_close(
+                                                    code,
+                                                    desc,
+                                                    Write::by_ref(self.socket.as_mut().unwrap()),
+                                                );
+                                                self.socket = None;
+                                                return None;
+                                            }
+                                        };
 
                                         if self.current_message_binary {
-                                            self.messages_in_queue.push(Message::Binary(binary));
+                                            self.messages_in_queue
+                                                .push_back(Message::Binary(binary));
                                         } else {
                                             let string = match String::from_utf8(binary) {
                                                 Ok(s) => s,
                                                 Err(_) => {
                                                     // Closing connection because text wasn't UTF-8
-                                                    let _ = send(
-                                                        b"1007 Invalid UTF-8 encoding",
+                                                    let _ = send_close(
+                                                        1007,
+                                                        "Invalid UTF-8 encoding",
                                                         Write::by_ref(
                                                             self.socket.as_mut().unwrap(),
                                                         ),
-                                                        0x8,
                                                     );
                                                     self.socket = None;
                                                     return None;
                                                 }
                                             };
 
-                                            self.messages_in_queue.push(Message::Text(string));
+                                            self.messages_in_queue.push_back(Message::Text(string));
                                         }
                                     }
                                 }
@@ -213,10 +384,10 @@ impl Iterator for Websocket {
                                     // If we're in the middle of a message, this frame is invalid
                                     // and we need to close.
                                     if !self.current_message_payload.is_empty() {
-                                        let _ = send(
-                                            b"1002 Expected continuation frame",
+                                        let _ = send_close(
+                                            1002,
+                                            "Expected continuation frame",
                                             Write::by_ref(self.socket.as_mut().unwrap()),
-                                            0x8,
                                         );
                                         self.socket = None;
                                         return None;
@@ -225,24 +396,44 @@ impl Iterator for Websocket {
                                     if self.current_frame_fin {
                                         // There's only one frame in this message.
                                         let binary = mem::take(&mut self.current_frame_payload);
+                                        let compressed = self.current_frame_compressed;
+                                        let binary = match decode_payload(
+                                            &mut self.deflate,
+                                            binary,
+                                            compressed,
+                                            self.max_frame_len,
+                                        ) {
+                                            Ok(b) => b,
+                                            Err((code, desc)) => {
+                                                let _ = send_close(
+                                                    code,
+                                                    desc,
+                                                    Write::by_ref(self.socket.as_mut().unwrap()),
+                                                );
+                                                self.socket = None;
+                                                return None;
+                                            }
+                                        };
                                         let string = match String::from_utf8(binary) {
                                             Ok(s) => s,
                                             Err(_err) => {
                                                 // Closing connection because text wasn't UTF-8
-                                                let _ = send(
-                                                    b"1007 Invalid UTF-8 encoding",
+                                                let _ = send_close(
+                                                    1007,
+                                                    "Invalid UTF-8 encoding",
                                                     Write::by_ref(self.socket.as_mut().unwrap()),
-                                                    0x8,
                                                 );
                                                 self.socket = None;
                                                 return None;
                                             }
                                         };
 
-                                        self.messages_in_queue.push(Message::Text(string));
+                                        self.messages_in_queue.push_back(Message::Text(string));
                                     } else {
                                         // Start of a fragmented message.
                                         self.current_message_binary = false;
+                                        self.current_message_compressed =
+                                            self.current_frame_compressed;
                                         self.current_message_payload
                                             .append(&mut self.current_frame_payload);
                                     }
@@ -253,10 +444,10 @@ impl Iterator for Websocket {
                                     // If we're in the middle of a message, this frame is invalid
                                     // and we need to close.
                                     if !self.current_message_payload.is_empty() {
-                                        let _ = send(
-                                            b"1002 Expected continuation frame",
+                                        let _ = send_close(
+                                            1002,
+                                            "Expected continuation frame",
                                             Write::by_ref(self.socket.as_mut().unwrap()),
-                                            0x8,
                                         );
                                         self.socket = None;
                                         return None;
@@ -264,10 +455,30 @@ impl Iterator for Websocket {
 
                                     if self.current_frame_fin {
                                         let binary = mem::take(&mut self.current_frame_payload);
-                                        self.messages_in_queue.push(Message::Binary(binary));
+                                        let compressed = self.current_frame_compressed;
+                                        let binary = match decode_payload(
+                                            &mut self.deflate,
+                                            binary,
+                                            compressed,
+                                            self.max_frame_len,
+                                        ) {
+                                            Ok(b) => b,
+                                            Err((code, desc)) => {
+                                                let _ = send_close(
+                                                    code,
+                                                    desc,
+                                                    Write::by_ref(self.socket.as_mut().unwrap()),
+                                                );
+                                                self.socket = None;
+                                                return None;
+                                            }
+                                        };
+                                        self.messages_in_queue.push_back(Message::Binary(binary));
                                     } else {
                                         // Start of a fragmented message.
                                         self.current_message_binary = true;
+                                        self.current_message_compressed =
+                                            self.current_frame_compressed;
                                         self.current_message_payload
                                             .append(&mut self.current_frame_payload);
                                     }
@@ -303,10 +514,10 @@ impl Iterator for Websocket {
 
                                 // Unknown opcode means error and close.
                                 _ => {
-                                    let _ = send(
-                                        b"Unknown opcode",
+                                    let _ = send_close(
+                                        1002,
+                                        "Unknown opcode",
                                         Write::by_ref(self.socket.as_mut().unwrap()),
-                                        0x8,
                                     );
                                     self.socket = None;
                                     return None;
@@ -317,13 +528,10 @@ impl Iterator for Websocket {
                         }
                     }
 
-                    low_level::Element::Error { desc } => {
+                    low_level::Element::Error { code, desc } => {
                         // The low level layer signaled an error. Sending it to client and closing.
-                        let _ = send(
-                            desc.as_bytes(),
-                            Write::by_ref(self.socket.as_mut().unwrap()),
-                            0x8,
-                        );
+                        let _ =
+                            send_close(code, desc, Write::by_ref(self.socket.as_mut().unwrap()));
                         self.socket = None;
                         return None;
                     }
@@ -333,12 +541,37 @@ impl Iterator for Websocket {
     }
 }
 
+// Decompresses the payload of a message if it is compressed. On error, returns the status code
+// and the reason to send in the close frame.
+fn decode_payload(
+    deflate: &mut Option<PerMessageDeflate>,
+    payload: Vec<u8>,
+    compressed: bool,
+    max_len: u64,
+) -> Result<Vec<u8>, (u16, &'static str)> {
+    match *deflate {
+        Some(ref mut deflate) if compressed => deflate.decompress(&payload, max_len),
+        _ => Ok(payload),
+    }
+}
+
+// Sends a close frame with the given status code and reason.
+fn send_close<W: Write>(code: u16, reason: &str, dest: W) -> io::Result<()> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    send(&payload, dest, 0x8)
+}
+
 // Sends a message to a websocket.
 // TODO: message fragmentation?
-fn send<W: Write>(data: &[u8], mut dest: W, opcode: u8) -> io::Result<()> {
-    // Write the opcode
+fn send<W: Write>(data: &[u8], dest: W, opcode: u8) -> io::Result<()> {
     assert!(opcode <= 0xf);
-    let first_byte = 0x80 | opcode;
+    send_frame(data, dest, 0x80 | opcode)
+}
+
+// Sends a single frame. `first_byte` contains the flags and the opcode.
+fn send_frame<W: Write>(data: &[u8], mut dest: W, first_byte: u8) -> io::Result<()> {
+    // Write the flags and the opcode
     dest.write_all(&[first_byte])?;
 
     // Write the length